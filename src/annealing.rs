@@ -1,52 +1,228 @@
-/// Stochaistic optimisation based on simulated annealing.
-/// Math is shamelessly taken from: http://mkweb.bcgsc.ca/carpalx/?simulated_annealing
-/// This code is written to be generic and can be reused for other applications.
+//! Stochaistic optimisation based on simulated annealing.
+//! Math is shamelessly taken from: http://mkweb.bcgsc.ca/carpalx/?simulated_annealing
+//! This code is written to be generic and can be reused for other applications.
 
 extern crate rand;
 
 use std::f64;
-use std::ops::Range;
-use self::rand::thread_rng;
 use self::rand::Rng;
 
 // These values are taken from Carpalx, with T0 adjusted for the scale that our
 // penalty model outputs.
-const T0: f64   = 1.5;
-const K:  f64   = 10.0;
-const P0: f64   = 1.0;
-const N:  usize = 20_000;
-const KN: f64   = K / (N as f64);
+pub const T0: f64   = 1.5;
+pub const K:  f64   = 10.0;
+pub const P0: f64   = 1.0;
+pub const N:  usize = 20_000;
+
+pub const SCHEDULES: [&str; 5] = ["exponential", "linear", "logarithmic", "restart", "reheat"];
+
+// A cooling schedule: the temperature at cycle i.
+pub trait Schedule: Send + Sync
+{
+	fn temperature(&self, i: usize) -> f64;
+}
 
 // T(i) = T0 exp(-ik/N)
-fn temperature(i: usize)
--> f64
+pub struct Exponential
+{
+	pub t0: f64,
+	pub k:  f64,
+	pub n:  usize,
+}
+
+// T(i) = T0 (1 - i/N), and 0 after N cycles.
+pub struct Linear
+{
+	pub t0: f64,
+	pub n:  usize,
+}
+
+// T(i) = T0 / (1 + ln(1 + ik/N)), which cools much more slowly than
+// `Exponential` with the same k and N.
+pub struct Logarithmic
+{
+	pub t0: f64,
+	pub k:  f64,
+	pub n:  usize,
+}
+
+// Runs `inner` over and over, starting again every `period` cycles.
+pub struct Restart
+{
+	pub inner:  Box<dyn Schedule>,
+	pub period: usize,
+}
+
+// Like `Restart`, but every restart heats up to only `decay` times the
+// temperature of the one before.
+pub struct Reheat
+{
+	pub inner:  Box<dyn Schedule>,
+	pub period: usize,
+	pub decay:  f64,
+}
+
+// A schedule together with the acceptance probability p0 at dE = 0.
+pub struct Annealing
+{
+	pub schedule: Box<dyn Schedule>,
+	pub p0:       f64,
+}
+
+/* ----- *
+ * IMPLS *
+ * ----- */
+
+impl Schedule for Exponential
 {
-	T0 * f64::exp(-(i as f64) * KN)
+	fn temperature(&self, i: usize)
+	-> f64
+	{
+		self.t0 * f64::exp(-(i as f64) * self.k / (self.n as f64))
+	}
 }
 
-// p(dE, i) = p0 exp(-dE/T(i))
-fn cutoff_p(de: f64, i: usize)
--> f64
+impl Schedule for Linear
 {
-	let t = temperature(i);
-	P0 * f64::exp(-de / t)
+	fn temperature(&self, i: usize)
+	-> f64
+	{
+		self.t0 * (1.0 - (i as f64) / (self.n as f64)).max(0.0)
+	}
 }
 
-// For positive dE, accept if r < p_dE where r ~ Uniform(0, 1)
-pub fn accept_transition(de: f64, i: usize)
--> bool
+impl Schedule for Logarithmic
 {
-	if de < 0.0 {
-		true
-	} else {
-		let p_de = cutoff_p(de, i);
-		let r: f64 = thread_rng().gen();
-		r < p_de
+	fn temperature(&self, i: usize)
+	-> f64
+	{
+		self.t0 / (1.0 + f64::ln(1.0 + (i as f64) * self.k / (self.n as f64)))
 	}
 }
 
-pub fn get_simulation_range()
--> Range<usize>
+impl Schedule for Restart
 {
-	1..(N+1)
+	fn temperature(&self, i: usize)
+	-> f64
+	{
+		self.inner.temperature(i % self.period)
+	}
+}
+
+impl Schedule for Reheat
+{
+	fn temperature(&self, i: usize)
+	-> f64
+	{
+		let restarts = (i / self.period) as i32;
+		self.decay.powi(restarts) * self.inner.temperature(i % self.period)
+	}
+}
+
+impl Default for Annealing
+{
+	fn default()
+	-> Annealing
+	{
+		Annealing {
+			schedule: Box::new(Exponential { t0: T0, k: K, n: N }),
+			p0: P0,
+		}
+	}
+}
+
+impl Annealing
+{
+	// Builds the schedule called `name` (one of `SCHEDULES`). `Restart` and
+	// `Reheat` repeat an exponential schedule every N cycles; `Reheat` halves
+	// the starting temperature each time.
+	pub fn new(name: &str, t0: f64, k: f64, p0: f64, n: usize)
+	-> Result<Annealing, String>
+	{
+		if n == 0 {
+			return Err("the schedule length must be at least 1".to_string());
+		}
+		let exponential = || Box::new(Exponential { t0, k, n });
+		let schedule: Box<dyn Schedule> = match name {
+			"exponential" => exponential(),
+			"linear" => Box::new(Linear { t0, n }),
+			"logarithmic" => Box::new(Logarithmic { t0, k, n }),
+			"restart" => Box::new(Restart { inner: exponential(), period: n }),
+			"reheat" => Box::new(Reheat { inner: exponential(), period: n, decay: 0.5 }),
+			_ => return Err(format!("unknown schedule `{}` (expected one of {})", name, SCHEDULES.join(", "))),
+		};
+		Ok(Annealing { schedule, p0 })
+	}
+
+	// p(dE, i) = p0 exp(-dE/T(i))
+	fn cutoff_p(&self, de: f64, i: usize)
+	-> f64
+	{
+		let t = self.schedule.temperature(i);
+		self.p0 * f64::exp(-de / t)
+	}
+
+	// For positive dE, accept if r < p_dE where r ~ Uniform(0, 1)
+	pub fn accept_transition<R: Rng>(&self, de: f64, i: usize, rng: &mut R)
+	-> bool
+	{
+		if de < 0.0 {
+			true
+		} else {
+			let p_de = self.cutoff_p(de, i);
+			let r: f64 = rng.gen();
+			r < p_de
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn close(a: f64, b: f64)
+	-> bool
+	{
+		(a - b).abs() <= 1e-12 * b.abs().max(1.0)
+	}
+
+	#[test]
+	fn schedules_by_name()
+	{
+		for name in SCHEDULES.iter() {
+			let annealing = Annealing::new(name, 2.0, 10.0, 1.0, 100).expect("could not build a schedule");
+			assert!(close(annealing.schedule.temperature(0), 2.0), "{}", name);
+		}
+
+		let err = Annealing::new("cubic", T0, K, P0, N).err().expect("an unknown schedule was built");
+		assert_eq!(err, "unknown schedule `cubic` (expected one of exponential, linear, logarithmic, restart, reheat)");
+		let err = Annealing::new("linear", T0, K, P0, 0).err().expect("an empty schedule was built");
+		assert_eq!(err, "the schedule length must be at least 1");
+	}
+
+	#[test]
+	fn temperatures()
+	{
+		let t = |name: &str, i: usize| Annealing::new(name, 2.0, 10.0, 1.0, 100).unwrap().schedule.temperature(i);
+
+		assert!(close(t("exponential", 100), 2.0 * f64::exp(-10.0)));
+		assert!(close(t("linear", 50), 1.0));
+		assert!(close(t("linear", 150), 0.0));
+		assert!(close(t("logarithmic", 10), 2.0 / (1.0 + f64::ln(2.0))));
+		assert!(close(t("restart", 130), t("exponential", 30)));
+		assert!(close(t("reheat", 230), 0.25 * t("exponential", 30)));
+	}
+
+	#[test]
+	fn acceptance()
+	{
+		let annealing = Annealing::default();
+		let mut rng = rand::thread_rng();
+		assert!((0..100).all(|i| annealing.accept_transition(-1e-9, i, &mut rng)));
+		// With p0 = 1, no change in penalty is always taken.
+		assert!((0..100).all(|i| annealing.accept_transition(0.0, i, &mut rng)));
+		// Late in the default schedule, a worse layout is as good as never.
+		assert!(!(0..100).any(|_| annealing.accept_transition(1.0, 10 * N, &mut rng)));
+	}
 }
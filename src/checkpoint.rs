@@ -0,0 +1,206 @@
+//! Checkpoints of annealing runs, for `keygen resume`.
+//!
+//! `keygen run --checkpoint <file>` writes where the run is every
+//! `--checkpoint-every` cycles and when it is interrupted:
+//!
+//! ```text
+//! # keygen checkpoint
+//! args run corpus/books.txt --checkpoint run.ckpt
+//! quartads 1034412
+//! threads 4
+//! iteration 3
+//! best qwert... QWERT...
+//! ...
+//! worker 20000 81235 7342 512334.25 498311.5 510021.75
+//! accepted ...
+//! found ...
+//! ```
+//!
+//! `args` is the command line of the run, with `--seed` added if the run drew
+//! its own seed, and `quartads` the number of quartads in its corpus, to tell
+//! whether the corpus has changed since. Each `best` line is one of the best
+//! layouts at the start of the iteration: three per thread, or the whole
+//! starting population if that is more. Each worker of
+//! the iteration has a `worker` line with its cycle, the position of its
+//! random number generator, its print frequency, the totals of its accepted
+//! and best layout and the running sums of its evaluator, then the layout it
+//! last accepted and the best one it found. A layout is written as
+//! one field per layer, its characters escaped as in n-gram files. Totals are
+//! written so that they read back to the same bits.
+
+use std::fmt;
+use std::sync::Arc;
+
+use geometry::Geometry;
+use layout::Layout;
+use ngrams;
+use simulator::{AnnealState, WorkerState};
+
+pub const HEADER: &str = "# keygen checkpoint";
+
+pub struct Checkpoint
+{
+	// The command line of the run, without the program name.
+	pub args:     Vec<String>,
+	// The number of quartads in the corpus of the run.
+	pub quartads: i64,
+	pub state:    AnnealState,
+}
+
+impl Checkpoint
+{
+	// The command line a checkpoint file was written for, which tells how to
+	// read the rest of it.
+	pub fn args(s: &str)
+	-> Result<Vec<String>, String>
+	{
+		if !s.starts_with(HEADER) {
+			return Err(format!("not a checkpoint (expected `{}` on the first line)", HEADER));
+		}
+		match s.lines().find(|line| line.starts_with("args ")) {
+			Some(line) => line.split_whitespace().skip(1).map(ngrams::unescape).collect(),
+			None => Err("no `args` line".to_string()),
+		}
+	}
+
+	pub fn from_string(s: &str, geometry: &Arc<Geometry>)
+	-> Result<Checkpoint, String>
+	{
+		let args = Checkpoint::args(s)?;
+		let mut quartads = None;
+		let mut threads = None;
+		let mut iteration = None;
+		let mut best_layouts = Vec::new();
+		let mut workers: Vec<WorkerState> = Vec::new();
+
+		let lines: Vec<(usize, &str)> = s.lines().enumerate()
+			.filter(|&(_, line)| !line.starts_with('#') && !line.trim().is_empty())
+			.collect();
+		let mut lines = lines.into_iter();
+		while let Some((i, line)) = lines.next() {
+			let fields: Vec<&str> = line.split_whitespace().collect();
+			let at = |e: String| format!("line {}: {}", i + 1, e);
+			match fields[0] {
+				"args" => (),
+				"quartads" => quartads = Some(number(field(&fields, 1)?).map_err(at)?),
+				"threads" => threads = Some(number(field(&fields, 1)?).map_err(at)?),
+				"iteration" => iteration = Some(number(field(&fields, 1)?).map_err(at)?),
+				"best" => best_layouts.push(read_layout(&fields[1..], geometry).map_err(at)?),
+				"worker" => {
+					if fields.len() < 7 {
+						return Err(at("expected `worker <cycle> <rng> <print frequency> <accepted> <best> <sums>...`".to_string()));
+					}
+					let mut next_layout = |name: &str| {
+						match lines.next() {
+							Some((j, line)) if line.starts_with(name) => {
+								let fields: Vec<&str> = line.split_whitespace().collect();
+								read_layout(&fields[1..], geometry).map_err(|e| format!("line {}: {}", j + 1, e))
+							},
+							_ => Err(at(format!("expected a `{}` line after the `worker` line", name))),
+						}
+					};
+					let layout = next_layout("accepted")?;
+					let best_layout = next_layout("found")?;
+					workers.push(WorkerState {
+						cycle: number(fields[1]).map_err(at)?,
+						rng_pos: number(fields[2]).map_err(at)?,
+						print_frequency: number(fields[3]).map_err(at)?,
+						layout,
+						accepted_total: number(fields[4]).map_err(at)?,
+						sums: fields[6..].iter().map(|f| number(f)).collect::<Result<_, _>>().map_err(at)?,
+						best_layout,
+						best_total: number(fields[5]).map_err(at)?,
+					});
+				},
+				other => return Err(at(format!("unknown line `{}`", other))),
+			}
+		}
+
+		let (quartads, threads, iteration) = match (quartads, threads, iteration) {
+			(Some(q), Some(t), Some(i)) => (q, t, i),
+			_ => return Err("a checkpoint needs `quartads`, `threads` and `iteration` lines".to_string()),
+		};
+		if best_layouts.len() < threads * 3 || !(workers.is_empty() || workers.len() == threads) {
+			return Err(format!(
+				"{} best layouts and {} workers do not make a run of {} threads",
+				best_layouts.len(), workers.len(), threads));
+		}
+		Ok(Checkpoint {
+			args,
+			quartads,
+			state: AnnealState { threads, iteration, best_layouts, workers },
+		})
+	}
+}
+
+impl fmt::Display for Checkpoint
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		let state = &self.state;
+		let args: Vec<String> = self.args.iter().map(|a| ngrams::escape(a)).collect();
+		writeln!(f, "{}", HEADER)?;
+		writeln!(f, "args {}", args.join(" "))?;
+		writeln!(f, "quartads {}", self.quartads)?;
+		writeln!(f, "threads {}", state.threads)?;
+		writeln!(f, "iteration {}", state.iteration)?;
+		for layout in &state.best_layouts {
+			writeln!(f, "best {}", write_layout(layout))?;
+		}
+		for w in &state.workers {
+			let sums: Vec<String> = w.sums.iter().map(|x| x.to_string()).collect();
+			writeln!(
+				f, "worker {} {} {} {} {} {}",
+				w.cycle, w.rng_pos, w.print_frequency, w.accepted_total, w.best_total, sums.join(" "))?;
+			writeln!(f, "accepted {}", write_layout(&w.layout))?;
+			writeln!(f, "found {}", write_layout(&w.best_layout))?;
+		}
+		Ok(())
+	}
+}
+
+/* ------- *
+ * HELPERS *
+ * ------- */
+
+fn field<'a>(fields: &[&'a str], i: usize)
+-> Result<&'a str, String>
+{
+	fields.get(i).cloned().ok_or_else(|| format!("`{}` needs a value", fields[0]))
+}
+
+fn number<T: ::std::str::FromStr>(s: &str)
+-> Result<T, String>
+{
+	s.parse::<T>().map_err(|_| format!("invalid number `{}`", s))
+}
+
+fn write_layout(layout: &Layout)
+-> String
+{
+	let layers: Vec<String> = (0..layout.layers().len())
+		.map(|n| {
+			let layer: String = (0..layout.geometry().len()).map(|i| layout.chars_at(i)[n]).collect();
+			ngrams::escape(&layer)
+		})
+		.collect();
+	layers.join(" ")
+}
+
+fn read_layout(fields: &[&str], geometry: &Arc<Geometry>)
+-> Result<Layout, String>
+{
+	if fields.len() != geometry.layers.len() {
+		return Err(format!("a layout has {} layers, not {}", geometry.layers.len(), fields.len()));
+	}
+	let mut layers = Vec::with_capacity(fields.len());
+	for field in fields {
+		let layer: Vec<char> = ngrams::unescape(field)?.chars().collect();
+		if layer.len() != geometry.len() {
+			return Err(format!("a layer has {} keys, not {}", geometry.len(), layer.len()));
+		}
+		layers.push(layer);
+	}
+	Ok(Layout::from_layers(layers, geometry))
+}
@@ -0,0 +1,425 @@
+//! Corpus preprocessing: what the quartads are taken from.
+//!
+//! By default the corpus is scored as it is read. `CorpusOptions` can fold it
+//! to lower case, normalise whitespace, type newlines as spaces and strip the
+//! characters the layout cannot type, or map them to ones it can first.
+//!
+//! Several corpora can be blended into one list of quartads, each with a
+//! weight: `prose.txt:0.5 code.rs:0.3 chat.txt:0.2`. A corpus may also be an
+//! n-gram file written by `corpus-stats`; see `ngrams`.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use error::{self, Error};
+use layout::{Layout, LayoutPosMap};
+use ngrams::NgramTable;
+use penalty::{self, QuartadList};
+
+// A corpus file and its weight in the blend.
+pub struct Corpus
+{
+	pub name:   String,
+	pub weight: f64,
+	pub data:   CorpusData,
+}
+
+// What a corpus file holds: text, or the n-gram counts of some text.
+pub enum CorpusData
+{
+	Text(String),
+	Ngrams(NgramTable),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct CorpusOptions
+{
+	// Fold everything to lower case.
+	pub lowercase:   bool,
+	// Turn CRLF and CR line endings into LF, tabs into spaces, and runs of
+	// spaces into one.
+	pub whitespace:  bool,
+	// What to do with newlines.
+	pub newline:     Newline,
+	// What to do with characters the layout cannot type.
+	pub unsupported: Unsupported,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Newline
+{
+	// A newline is typed with its own key, if the layout has one.
+	Key,
+	// A newline is typed as a space.
+	Space,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Unsupported
+{
+	// Left in the corpus, where they break up the quartads around them.
+	Keep,
+	// Taken out of the corpus.
+	Strip,
+	// Replaced with a character the layout can type if there is one, e.g. a
+	// smart quote with a straight one or a capital with its lower case, and
+	// taken out otherwise.
+	Map,
+}
+
+// What preprocessing did to a corpus, in characters.
+#[derive(Clone, Copy, Default)]
+pub struct CorpusStats
+{
+	pub read:    usize,
+	pub kept:    usize,
+	pub mapped:  usize,
+	// Taken out because the layout cannot type them.
+	pub dropped: usize,
+}
+
+// Characters commonly found in text that have a plain ASCII stand-in.
+static ASCII_MAP: [(char, &str); 16] = [
+	('\u{2018}', "'"),   // left single quotation mark
+	('\u{2019}', "'"),   // right single quotation mark
+	('\u{201a}', "'"),   // single low-9 quotation mark
+	('\u{201b}', "'"),   // single high-reversed-9 quotation mark
+	('\u{2032}', "'"),   // prime
+	('\u{201c}', "\""),  // left double quotation mark
+	('\u{201d}', "\""),  // right double quotation mark
+	('\u{201e}', "\""),  // double low-9 quotation mark
+	('\u{00ab}', "\""),  // left-pointing double angle quotation mark
+	('\u{00bb}', "\""),  // right-pointing double angle quotation mark
+	('\u{2033}', "\""),  // double prime
+	('\u{2013}', "-"),   // en dash
+	('\u{2014}', "-"),   // em dash
+	('\u{2212}', "-"),   // minus sign
+	('\u{2026}', "..."), // horizontal ellipsis
+	('\u{00a0}', " "),   // no-break space
+];
+
+impl Default for CorpusOptions
+{
+	fn default()
+	-> CorpusOptions
+	{
+		CorpusOptions {
+			lowercase: false,
+			whitespace: false,
+			newline: Newline::Key,
+			unsupported: Unsupported::Keep,
+		}
+	}
+}
+
+impl FromStr for Newline
+{
+	type Err = String;
+
+	fn from_str(s: &str)
+	-> Result<Newline, String>
+	{
+		match s {
+			"key" => Ok(Newline::Key),
+			"space" => Ok(Newline::Space),
+			_ => Err(format!("unknown newline handling `{}` (expected key or space)", s)),
+		}
+	}
+}
+
+impl FromStr for Unsupported
+{
+	type Err = String;
+
+	fn from_str(s: &str)
+	-> Result<Unsupported, String>
+	{
+		match s {
+			"keep" => Ok(Unsupported::Keep),
+			"strip" => Ok(Unsupported::Strip),
+			"map" => Ok(Unsupported::Map),
+			_ => Err(format!("unknown handling of unsupported characters `{}` (expected keep, strip or map)", s)),
+		}
+	}
+}
+
+// Reads the corpus in `filename`, text or n-gram file.
+pub fn load(filename: &str, weight: f64)
+-> Result<Corpus, Error>
+{
+	if !(weight > 0.0 && weight.is_finite()) {
+		return Err(Error::Usage(format!("{}: weight {} is not a positive number", filename, weight)));
+	}
+	let text = error::read_file(filename)?;
+	let data = CorpusData::from_string(text).map_err(|e| Error::Parse(filename.to_string(), e))?;
+	let empty = match data {
+		CorpusData::Text(ref text) => text.chars().nth(3).is_none(),
+		CorpusData::Ngrams(ref table) => table.quartads().map.is_empty(),
+	};
+	if empty {
+		return Err(Error::Parse(filename.to_string(), "nothing to score: a corpus needs at least four characters".to_string()));
+	}
+	Ok(Corpus { name: filename.to_string(), weight, data })
+}
+
+// Splits `path:weight` into the path and the weight, or returns None if `arg`
+// carries no weight.
+pub fn parse_weighted(arg: &str)
+-> Option<(String, f64)>
+{
+	let i = arg.rfind(':')?;
+	let weight = arg[i + 1..].parse::<f64>().ok()?;
+	Some((arg[..i].to_string(), weight))
+}
+
+// The quartads of all `corpora`, blended by `blend_counts`.
+pub fn blend(corpora: &[Corpus])
+-> QuartadList
+{
+	let mut lists: Vec<QuartadList> = corpora.iter().map(|c| c.data.quartads()).collect();
+	if lists.len() == 1 {
+		return lists.remove(0);
+	}
+
+	let maps: Vec<(&HashMap<[char; 4], i64>, f64)> = lists.iter()
+		.zip(corpora)
+		.map(|(list, corpus)| (&list.map, corpus.weight))
+		.collect();
+	QuartadList { map: blend_counts(&maps) }
+}
+
+// The n-grams of all `corpora`, blended by `blend_counts`.
+pub fn blend_ngrams(corpora: &[Corpus])
+-> NgramTable
+{
+	let tables: Vec<(NgramTable, f64)> = corpora.iter()
+		.map(|c| (c.data.ngrams(), c.weight))
+		.collect();
+	NgramTable::blend(&tables)
+}
+
+// The characters of all `corpora` and their counts, blended by `blend_counts`.
+pub fn blend_chars(corpora: &[Corpus])
+-> HashMap<char, i64>
+{
+	let mut counts: Vec<HashMap<char, i64>> = corpora.iter().map(|c| c.data.chars()).collect();
+	if counts.len() == 1 {
+		return counts.remove(0);
+	}
+
+	let maps: Vec<(&HashMap<char, i64>, f64)> = counts.iter()
+		.zip(corpora)
+		.map(|(map, corpus)| (map, corpus.weight))
+		.collect();
+	blend_counts(&maps)
+}
+
+// Adds up weighted counts. The counts of each map are scaled so that its share
+// of the result matches its share of the total weight, and the result has
+// about as many counts as the maps together.
+pub fn blend_counts<K: Hash + Eq + Clone>(maps: &[(&HashMap<K, i64>, f64)])
+-> HashMap<K, i64>
+{
+	let lens: Vec<i64> = maps.iter().map(|&(map, _)| map.values().sum()).collect();
+	let len: i64 = lens.iter().sum();
+	let weight: f64 = maps.iter().map(|&(_, weight)| weight).sum();
+
+	let mut blend: HashMap<K, f64> = HashMap::new();
+	for (&(map, map_weight), &map_len) in maps.iter().zip(&lens) {
+		if map_len == 0 {
+			continue;
+		}
+		let scale = map_weight / weight * len as f64 / map_len as f64;
+		for (key, &count) in map {
+			*blend.entry(key.clone()).or_insert(0.0) += count as f64 * scale;
+		}
+	}
+
+	blend.into_iter()
+		.map(|(key, count)| (key, count.round() as i64))
+		.filter(|&(_, count)| count > 0)
+		.collect()
+}
+
+impl CorpusData
+{
+	// Reads the contents of a corpus file, which is either text or an n-gram
+	// file.
+	pub fn from_string(s: String)
+	-> Result<CorpusData, String>
+	{
+		if NgramTable::is_ngram_file(&s) {
+			NgramTable::from_string(&s).map(CorpusData::Ngrams)
+		} else {
+			Ok(CorpusData::Text(s))
+		}
+	}
+
+	pub fn quartads(&self)
+	-> QuartadList
+	{
+		match *self {
+			CorpusData::Text(ref text) => penalty::prepare_quartad_list(text),
+			CorpusData::Ngrams(ref table) => table.quartads(),
+		}
+	}
+
+	// The count of each character.
+	pub fn chars(&self)
+	-> HashMap<char, i64>
+	{
+		match *self {
+			CorpusData::Text(ref text) => {
+				let mut counts = HashMap::new();
+				for c in text.chars() {
+					*counts.entry(c).or_insert(0) += 1;
+				}
+				counts
+			},
+			CorpusData::Ngrams(ref table) => table.chars(),
+		}
+	}
+
+	pub fn ngrams(&self)
+	-> NgramTable
+	{
+		match *self {
+			CorpusData::Text(ref text) => NgramTable::from_text(text),
+			CorpusData::Ngrams(ref table) => table.clone(),
+		}
+	}
+}
+
+// Runs `raw` through the pipeline set up by `options`. Whether a character
+// can be typed is decided by `layout`.
+pub fn prepare(raw: &str, layout: &Layout, options: &CorpusOptions)
+-> (String, CorpusStats)
+{
+	let position_map = layout.get_position_map();
+	let mut out = String::with_capacity(raw.len());
+	let mut stats = CorpusStats::default();
+
+	let mut chars = raw.chars().peekable();
+	while let Some(c) = chars.next() {
+		stats.read += 1;
+		let c = match c {
+			'\r' if options.whitespace => {
+				if chars.peek() == Some(&'\n') {
+					continue;
+				}
+				'\n'
+			},
+			'\t' if options.whitespace => ' ',
+			'\n' if options.newline == Newline::Space => ' ',
+			c => c,
+		};
+		if options.lowercase {
+			for c in c.to_lowercase() {
+				push(&mut out, &mut stats, c, &position_map, options);
+			}
+		} else {
+			push(&mut out, &mut stats, c, &position_map, options);
+		}
+	}
+	stats.kept = out.chars().count();
+
+	(out, stats)
+}
+
+// Appends `c` to `out`, or whatever `options` turn it into.
+fn push(out: &mut String, stats: &mut CorpusStats, c: char, position_map: &LayoutPosMap, options: &CorpusOptions)
+{
+	let typeable = |c: char| position_map.get_key_position(c).is_some();
+
+	if options.unsupported == Unsupported::Keep || typeable(c) {
+		push_char(out, c, options);
+		return;
+	}
+	if options.unsupported == Unsupported::Map {
+		let mapped = match ASCII_MAP.iter().find(|&&(from, _)| from == c) {
+			Some(&(_, to)) => to.to_string(),
+			None => c.to_lowercase().collect(),
+		};
+		if mapped != c.to_string() && mapped.chars().all(typeable) {
+			stats.mapped += 1;
+			for m in mapped.chars() {
+				push_char(out, m, options);
+			}
+			return;
+		}
+	}
+	stats.dropped += 1;
+}
+
+fn push_char(out: &mut String, c: char, options: &CorpusOptions)
+{
+	if options.whitespace && c == ' ' && out.ends_with(' ') {
+		return;
+	}
+	out.push(c);
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashMap;
+
+	use super::*;
+
+	fn text(name: &str, weight: f64, text: &str)
+	-> Corpus
+	{
+		Corpus { name: name.to_string(), weight, data: CorpusData::Text(text.to_string()) }
+	}
+
+	#[test]
+	fn weights()
+	{
+		assert_eq!(parse_weighted("prose.txt:0.5"), Some(("prose.txt".to_string(), 0.5)));
+		assert_eq!(parse_weighted("logs/a:b.txt:2"), Some(("logs/a:b.txt".to_string(), 2.0)));
+		assert_eq!(parse_weighted("logs/a:b.txt"), None);
+		assert_eq!(parse_weighted("prose.txt"), None);
+
+		match load("corpus/books.veryshort.txt", 0.0) {
+			Err(Error::Usage(e)) => assert_eq!(e, "corpus/books.veryshort.txt: weight 0 is not a positive number"),
+			_ => panic!("a corpus was loaded with weight 0"),
+		}
+	}
+
+	#[test]
+	fn blend_by_weight()
+	{
+		// Each map makes up its share of the weight, whatever its size.
+		let small: HashMap<&str, i64> = [("a", 10)].iter().cloned().collect();
+		let large: HashMap<&str, i64> = [("b", 600), ("c", 390)].iter().cloned().collect();
+		let blend = blend_counts(&[(&small, 1.0), (&large, 1.0)]);
+		assert_eq!(blend["a"], 500);
+		assert_eq!(blend["b"] + blend["c"], 500);
+		assert_eq!(blend["b"], 303);
+
+		let blend = blend_counts(&[(&small, 3.0), (&large, 1.0)]);
+		assert_eq!(blend["a"], 750);
+		assert_eq!(blend["b"] + blend["c"], 250);
+
+		// Counts that round to nothing are dropped.
+		let blend = blend_counts(&[(&small, 1e-6), (&large, 1.0)]);
+		assert!(!blend.contains_key("a"));
+	}
+
+	#[test]
+	fn blend_corpora()
+	{
+		let one = [text("one", 1.0, "aaaaaaaa")];
+		assert_eq!(blend(&one).map, penalty::prepare_quartad_list("aaaaaaaa").map);
+
+		let two = [text("one", 1.0, "aaaaaaaa"), text("two", 1.0, "bbbbbbbbbbbbbbbbbbbbbb")];
+		let quartads = blend(&two);
+		assert_eq!(quartads.map[&['a'; 4]], 12);
+		assert_eq!(quartads.map[&['b'; 4]], 12);
+		let chars = blend_chars(&two);
+		assert_eq!(chars[&'a'], 15);
+		assert_eq!(chars[&'b'], 15);
+		assert_eq!(blend_ngrams(&two).quartads().map, quartads.map);
+	}
+}
@@ -0,0 +1,92 @@
+//! The errors keygen stops with, and the exit code of each.
+//!
+//! The parsers of the file formats return `Result<T, String>` with messages
+//! such as "line 3: ..."; the loaders wrap them with the name of the file.
+//!
+//! ```text
+//!   2  bad command line
+//!   3  a file cannot be read or written
+//!   4  a file cannot be understood
+//!   5  nothing to score, a layout that cannot be exported, or an incomplete
+//!      layout given to `validate`
+//! 130  interrupted, after writing a checkpoint
+//! ```
+
+extern crate getopts;
+
+use std::fmt;
+use std::fs;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error
+{
+	// An unknown option, a missing argument or an option with a bad value.
+	Usage(String),
+	// A file and why it cannot be read or written.
+	Io(String, io::Error),
+	// A file and what is wrong with its contents.
+	Parse(String, String),
+	// Anything that cannot be scored, exported or validated.
+	Invalid(String),
+	// A run stopped by Ctrl-C, and where it left its checkpoint.
+	Interrupted(String),
+}
+
+impl Error
+{
+	pub fn exit_code(&self)
+	-> i32
+	{
+		match *self {
+			Error::Usage(_) => 2,
+			Error::Io(..) => 3,
+			Error::Parse(..) => 4,
+			Error::Invalid(_) => 5,
+			Error::Interrupted(_) => 130,
+		}
+	}
+}
+
+impl fmt::Display for Error
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		match *self {
+			Error::Usage(ref msg) => write!(f, "{}", msg),
+			Error::Io(ref file, ref e) => write!(f, "{}: {}", file, e),
+			Error::Parse(ref file, ref msg) => write!(f, "{}: {}", file, msg),
+			Error::Invalid(ref msg) => write!(f, "{}", msg),
+			Error::Interrupted(ref msg) => write!(f, "{}", msg),
+		}
+	}
+}
+
+impl From<getopts::Fail> for Error
+{
+	fn from(f: getopts::Fail)
+	-> Error
+	{
+		Error::Usage(f.to_string())
+	}
+}
+
+// Reads the whole of `filename`.
+pub fn read_file(filename: &str)
+-> Result<String, Error>
+{
+	fs::read_to_string(filename).map_err(|e| Error::Io(filename.to_string(), e))
+}
+
+// Writes `contents` to `filename`, or to standard output without one.
+pub fn write_output(filename: Option<&str>, contents: &[u8])
+-> Result<(), Error>
+{
+	use std::io::Write;
+
+	match filename {
+		None => io::stdout().write_all(contents).map_err(|e| Error::Io("standard output".to_string(), e)),
+		Some(filename) => fs::write(filename, contents).map_err(|e| Error::Io(filename.to_string(), e)),
+	}
+}
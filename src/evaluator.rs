@@ -0,0 +1,355 @@
+//! Incremental scoring of key swaps.
+//!
+//! A full `penalty::calculate_penalty` rescans every quartad of the corpus. A
+//! swap only moves the characters on two keys, so `Evaluator` keeps an index
+//! from each character to the quartads it takes part in, caches the score of
+//! every quartad, and rescores just the ones a swap touches.
+
+use std::collections::HashMap;
+
+use layout::{Layout, LayoutPosMap};
+use model::Model;
+use penalty::{self, Penalty, QuartadList, Strokes};
+
+// Scores a layout and swaps of its keys, for the optimisers. See
+// `scorer::Scorer::evaluator`.
+pub trait Evaluate: Send + Sync {
+    fn layout(&self) -> &Layout;
+
+    // The penalty of the current layout.
+    fn total(&self) -> f64;
+
+    // Applies `swaps` in order. `undo` takes them back.
+    fn swap(&mut self, swaps: &[(usize, usize)]);
+
+    // Takes back the last call to `swap`.
+    fn undo(&mut self);
+
+    // The running sums behind `total`, which carry the rounding of every swap
+    // before. A new evaluator of the same layout given them with `set_sums`
+    // goes on exactly like this one.
+    fn sums(&self) -> Vec<f64>;
+    fn set_sums(&mut self, sums: &[f64]);
+
+    // A copy of the evaluator in its current state.
+    fn clone_box<'s>(&'s self) -> Box<dyn Evaluate + 's>;
+}
+
+pub struct QuartadIndex {
+    quartads: Vec<([char; 4], i64)>,
+    by_char: HashMap<char, Vec<usize>>,
+    // How often each pair of characters ends a quartad. The last character is
+    // the one that counts towards the finger and hand load; the one before it
+    // decides whether a held layer key has to be pressed again.
+    pairs: Vec<([char; 2], i64)>,
+    pairs_by_char: HashMap<char, Vec<usize>>,
+}
+
+#[derive(Clone)]
+pub struct Evaluator<'a> {
+    index: &'a QuartadIndex,
+    model: &'a Model,
+    layout: Layout,
+    position_map: LayoutPosMap,
+    scores: Vec<f64>,
+    // Sum of `scores`, i.e. the penalty without the load terms.
+    total: f64,
+    fingers: [i64; 8],
+    hands: [i64; 2],
+    keystrokes: i64,
+    scratch: Penalty,
+    undo: Undo,
+}
+
+// Everything needed to take back the last call to `Evaluator::swap`.
+#[derive(Clone, Default)]
+struct Undo {
+    swaps: Vec<(usize, usize)>,
+    moved: Vec<char>,
+    scores: Vec<(usize, f64)>,
+    total: f64,
+    fingers: [i64; 8],
+    hands: [i64; 2],
+    keystrokes: i64,
+}
+
+impl QuartadIndex {
+    pub fn new(quartads: &QuartadList) -> QuartadIndex {
+        let mut list: Vec<([char; 4], i64)> =
+            quartads.map.iter().map(|(&chars, &count)| (chars, count)).collect();
+        // A fixed order keeps the floating point sums the same between runs.
+        list.sort_unstable();
+
+        let mut pair_counts: HashMap<[char; 2], i64> = HashMap::new();
+        for &(ref chars, count) in &list {
+            *pair_counts.entry([chars[2], chars[3]]).or_insert(0) += count;
+        }
+        let mut pairs: Vec<([char; 2], i64)> = pair_counts.into_iter().collect();
+        pairs.sort_unstable();
+
+        QuartadIndex {
+            by_char: index_chars(&list),
+            pairs_by_char: index_chars(&pairs),
+            quartads: list,
+            pairs,
+        }
+    }
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(index: &'a QuartadIndex, layout: &Layout, model: &'a Model) -> Evaluator<'a> {
+        let mut evaluator = Evaluator {
+            index,
+            model,
+            layout: layout.clone(),
+            position_map: layout.get_position_map(),
+            scores: Vec::with_capacity(index.quartads.len()),
+            total: 0.0,
+            fingers: [0; 8],
+            hands: [0; 2],
+            keystrokes: 0,
+            scratch: Penalty::new(model),
+            undo: Undo::default(),
+        };
+
+        for &(ref chars, count) in &index.quartads {
+            let score = evaluator.score(chars, count);
+            evaluator.scores.push(score);
+            evaluator.total += score;
+        }
+        for &(ref chars, count) in &index.pairs {
+            evaluator.add_load(chars, count);
+        }
+
+        evaluator
+    }
+
+    // The penalty of typing `chars` `count` times on the current layout,
+    // without the load terms.
+    fn score(&mut self, chars: &[char; 4], count: i64) -> f64 {
+        self.scratch.total = 0.0;
+        penalty::score_quartad(&mut self.scratch, &self.position_map, chars, count, self.model);
+        self.scratch.total
+    }
+
+    // Counts `count` times typing `chars[1]` after `chars[0]` towards the
+    // finger and hand load.
+    fn add_load(&mut self, chars: &[char; 2], count: i64) {
+        let strokes = Strokes::new(&self.position_map, chars);
+        for kp in strokes.last().iter().flatten() {
+            penalty::count_load(&mut self.fingers, &mut self.hands, &mut self.keystrokes, kp, count);
+        }
+    }
+
+    // Adds (`sign` 1) or takes back (`sign` -1) the load of every pair of
+    // characters that contains one of `moved`.
+    fn add_moved_load(&mut self, moved: &[char], sign: i64) {
+        let index = self.index;
+        for (n, c) in moved.iter().enumerate() {
+            let pairs = match index.pairs_by_char.get(c) {
+                Some(p) => p,
+                None => continue,
+            };
+            for &p in pairs {
+                let (ref chars, count) = index.pairs[p];
+                if chars.iter().any(|x| moved[..n].contains(x)) {
+                    continue;
+                }
+                self.add_load(chars, sign * count);
+            }
+        }
+    }
+}
+
+impl<'a> Evaluate for Evaluator<'a> {
+    fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    // The penalty of the current layout, including the load terms.
+    fn total(&self) -> f64 {
+        let (finger_excess, hand_excess) =
+            penalty::load_excess(&self.fingers, &self.hands, self.keystrokes, self.model);
+        self.total
+            + self.model.weights[penalty::FINGER_LOAD] * finger_excess
+            + self.model.weights[penalty::HAND_LOAD] * hand_excess
+    }
+
+    // Rescores every quartad that contains one of the characters `swaps`
+    // move.
+    fn swap(&mut self, swaps: &[(usize, usize)]) {
+        let index = self.index;
+
+        let mut moved: Vec<char> = Vec::new();
+        for &(i, j) in swaps {
+            for &c in self.layout.chars_at(i).iter().chain(&self.layout.chars_at(j)) {
+                if !moved.contains(&c) {
+                    moved.push(c);
+                }
+            }
+        }
+
+        self.undo.swaps.clear();
+        self.undo.swaps.extend_from_slice(swaps);
+        self.undo.scores.clear();
+        self.undo.total = self.total;
+        self.undo.fingers = self.fingers;
+        self.undo.hands = self.hands;
+        self.undo.keystrokes = self.keystrokes;
+
+        self.add_moved_load(&moved, -1);
+        for &(i, j) in swaps {
+            self.layout.swap(i, j);
+        }
+        self.layout.update_position_map(&mut self.position_map, &moved);
+        self.add_moved_load(&moved, 1);
+
+        for (n, c) in moved.iter().enumerate() {
+            let quartads = match index.by_char.get(c) {
+                Some(q) => q,
+                None => continue,
+            };
+            for &q in quartads {
+                let (ref chars, count) = index.quartads[q];
+                // Quartads with several moved characters are listed under each
+                // of them; only rescore them under the first.
+                if chars.iter().any(|x| moved[..n].contains(x)) {
+                    continue;
+                }
+                let score = self.score(chars, count);
+                self.undo.scores.push((q, self.scores[q]));
+                self.total += score - self.scores[q];
+                self.scores[q] = score;
+            }
+        }
+
+        self.undo.moved = moved;
+    }
+
+    fn undo(&mut self) {
+        for &(q, score) in &self.undo.scores {
+            self.scores[q] = score;
+        }
+        self.total = self.undo.total;
+        self.fingers = self.undo.fingers;
+        self.hands = self.undo.hands;
+        self.keystrokes = self.undo.keystrokes;
+
+        for &(i, j) in self.undo.swaps.iter().rev() {
+            self.layout.swap(i, j);
+        }
+        self.layout.update_position_map(&mut self.position_map, &self.undo.moved);
+
+        self.undo.swaps.clear();
+        self.undo.scores.clear();
+    }
+
+    fn sums(&self) -> Vec<f64> {
+        vec![self.total]
+    }
+
+    fn set_sums(&mut self, sums: &[f64]) {
+        self.total = sums[0];
+    }
+
+    fn clone_box<'s>(&'s self) -> Box<dyn Evaluate + 's> {
+        Box::new(self.clone())
+    }
+}
+
+// Lists, for every character, the entries of `list` that contain it.
+fn index_chars<K: AsRef<[char]>>(list: &[(K, i64)]) -> HashMap<char, Vec<usize>> {
+    let mut by_char: HashMap<char, Vec<usize>> = HashMap::new();
+    for (i, (chars, _)) in list.iter().enumerate() {
+        let chars = chars.as_ref();
+        for (n, c) in chars.iter().enumerate() {
+            if !chars[..n].contains(c) {
+                by_char.entry(*c).or_default().push(i);
+            }
+        }
+    }
+    by_char
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rand;
+    extern crate rand_chacha;
+
+    use std::fs;
+    use std::sync::Arc;
+
+    use self::rand::{Rng, SeedableRng};
+    use self::rand_chacha::ChaCha20Rng;
+
+    use super::*;
+    use geometry::Geometry;
+    use layout::LayoutShuffleMask;
+
+    fn data(path: &str) -> String {
+        fs::read_to_string(format!("{}/{}", env!("CARGO_MANIFEST_DIR"), path)).expect("could not read test data")
+    }
+
+    fn assert_matches(evaluator: &Evaluator, layout: &Layout, quartads: &QuartadList, model: &Model, step: &str) {
+        assert!(evaluator.layout() == layout, "{}: the layouts differ", step);
+        let full = penalty::calculate_penalty(quartads, layout, model).penalty.total;
+        let total = evaluator.total();
+        assert!(
+            (total - full).abs() <= full.abs().max(1.0) * 1e-9,
+            "{}: incremental {} but full {}",
+            step,
+            total,
+            full
+        );
+    }
+
+    // Random swaps and undos, on a thumb shift that is held, tapped once and
+    // taken from the other half, must always score what a full rescore does.
+    #[test]
+    fn swaps_match_full_rescore() {
+        let text: String = data("corpus/books.veryshort.txt").chars().take(20000).collect();
+        let quartads = penalty::prepare_quartad_list(&text);
+        let index = QuartadIndex::new(&quartads);
+        let file = data("tests/data/thumb-shift.txt");
+
+        for activation in &["hold 32", "oneshot 32", "shift 32 33"] {
+            let geometry = Geometry::from_string(&file.replace("hold 32", activation)).expect("could not read the geometry");
+            let geometry = Arc::new(geometry);
+            let mut layout = Layout::from_string(&data("tests/data/thumb-shift-layout.txt"), &geometry)
+                .expect("could not read the layout");
+            let model = Model::new(&geometry);
+            assert!(model.weights[penalty::FINGER_LOAD] != 0.0 && model.weights[penalty::HAND_LOAD] != 0.0);
+            let free = LayoutShuffleMask::new(&geometry).positions();
+            let mut rng = ChaCha20Rng::seed_from_u64(1);
+
+            let mut evaluator = Evaluator::new(&index, &layout, &model);
+            assert_matches(&evaluator, &layout, &quartads, &model, activation);
+            for step in 0..300 {
+                let mut key = || free[rng.gen::<usize>() % free.len()];
+                // Every few steps, chain the swaps through a shared key, so
+                // that pairs and quartads see several of their characters
+                // move at once.
+                let swaps: Vec<(usize, usize)> = if step % 4 == 0 {
+                    let (a, b, c) = (key(), key(), key());
+                    vec![(a, b), (b, c), (c, a)]
+                } else {
+                    (0..step % 3 + 1).map(|_| (key(), key())).collect()
+                };
+
+                let before = layout.clone();
+                evaluator.swap(&swaps);
+                for &(i, j) in &swaps {
+                    layout.swap(i, j);
+                }
+                let name = format!("{}, step {}, swaps {:?}", activation, step, swaps);
+                assert_matches(&evaluator, &layout, &quartads, &model, &name);
+
+                if rng.gen::<bool>() {
+                    evaluator.undo();
+                    layout = before;
+                    assert_matches(&evaluator, &layout, &quartads, &model, &format!("{}, undone", name));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,619 @@
+//! Exporters that turn a layout into a keymap for firmware or an operating
+//! system.
+//!
+//! * `qmk`: a QMK `keymap.c`, one `LAYOUT(...)` per layer.
+//! * `zmk`: a ZMK `.keymap`, one layer node per layer.
+//! * `xkb`: an XKB symbols file for X11 and Wayland.
+//! * `keylayout`: a macOS `.keylayout`.
+//! * `klc`: a Microsoft Keyboard Layout Creator source file (UTF-16).
+//!
+//! The firmware keymaps list the keys in the rows of the geometry, left to
+//! right, as `import` reads them back. Layer keys become momentary (`hold`,
+//! `shift`) or one-shot layer keys. Characters without a keycode of their own
+//! are left out and listed at the top of the file.
+//!
+//! The operating system keymaps put the layout on an ANSI board: the left
+//! pinky column of the geometry goes on Q, A and Z, the thumb key that types a
+//! space on the space bar, and the keys the geometry leaves out keep their US
+//! characters. The layers become the Shift, AltGr (Option) and Shift+AltGr
+//! levels, so there can be at most four.
+
+use std::str::FromStr;
+
+use geometry::Geometry;
+use import::{self, QMK_KEYCODES};
+use layout::{Activation, Finger, Hand, Layout, Row};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExportFormat
+{
+	Qmk,
+	Zmk,
+	Xkb,
+	Keylayout,
+	Klc,
+}
+
+// How a firmware names its keys.
+struct Firmware
+{
+	// The keycode typing a character, if there is one.
+	key:     fn(char) -> Option<String>,
+	none:    &'static str,
+	trans:   &'static str,
+	hold:    fn(usize) -> String,
+	oneshot: fn(usize) -> String,
+}
+
+// A key of an ANSI board: its XKB name, Windows scan code and virtual key,
+// macOS key code and US characters.
+struct AnsiKey
+{
+	xkb:  &'static str,
+	scan: u8,
+	vk:   &'static str,
+	mac:  u16,
+	us:   [char; 2],
+}
+
+/* ------- *
+ * STATICS *
+ * ------- */
+
+// ZMK key names of the characters other than letters and digits.
+static ZMK_KEYCODES: [(&str, char); 35] = [
+	("SPACE", ' '), ("RET", '\n'), ("TAB", '\t'), ("MINUS", '-'), ("EQUAL", '='), ("LBKT", '['),
+	("RBKT", ']'), ("BSLH", '\\'), ("SEMI", ';'), ("SQT", '\''), ("GRAVE", '`'), ("COMMA", ','),
+	("DOT", '.'), ("FSLH", '/'), ("EXCL", '!'), ("AT", '@'), ("HASH", '#'), ("DLLR", '$'),
+	("PRCNT", '%'), ("CARET", '^'), ("AMPS", '&'), ("STAR", '*'), ("LPAR", '('), ("RPAR", ')'),
+	("UNDER", '_'), ("PLUS", '+'), ("LBRC", '{'), ("RBRC", '}'), ("PIPE", '|'), ("COLON", ':'),
+	("DQT", '"'), ("TILDE", '~'), ("LT", '<'), ("GT", '>'), ("QMARK", '?'),
+];
+
+// XKB keysyms of the ASCII characters other than letters and digits.
+static XKB_KEYSYMS: [(&str, char); 35] = [
+	("space", ' '), ("Return", '\n'), ("Tab", '\t'), ("exclam", '!'), ("quotedbl", '"'),
+	("numbersign", '#'), ("dollar", '$'), ("percent", '%'), ("ampersand", '&'), ("apostrophe", '\''),
+	("parenleft", '('), ("parenright", ')'), ("asterisk", '*'), ("plus", '+'), ("comma", ','),
+	("minus", '-'), ("period", '.'), ("slash", '/'), ("colon", ':'), ("semicolon", ';'),
+	("less", '<'), ("equal", '='), ("greater", '>'), ("question", '?'), ("at", '@'),
+	("bracketleft", '['), ("backslash", '\\'), ("bracketright", ']'), ("asciicircum", '^'),
+	("underscore", '_'), ("grave", '`'), ("braceleft", '{'), ("bar", '|'), ("braceright", '}'),
+	("asciitilde", '~'),
+];
+
+static NUMBER_ROW: [AnsiKey; 13] = [
+	AnsiKey { xkb: "TLDE", scan: 0x29, vk: "OEM_3",     mac: 50, us: ['`', '~'] },
+	AnsiKey { xkb: "AE01", scan: 0x02, vk: "1",         mac: 18, us: ['1', '!'] },
+	AnsiKey { xkb: "AE02", scan: 0x03, vk: "2",         mac: 19, us: ['2', '@'] },
+	AnsiKey { xkb: "AE03", scan: 0x04, vk: "3",         mac: 20, us: ['3', '#'] },
+	AnsiKey { xkb: "AE04", scan: 0x05, vk: "4",         mac: 21, us: ['4', '$'] },
+	AnsiKey { xkb: "AE05", scan: 0x06, vk: "5",         mac: 23, us: ['5', '%'] },
+	AnsiKey { xkb: "AE06", scan: 0x07, vk: "6",         mac: 22, us: ['6', '^'] },
+	AnsiKey { xkb: "AE07", scan: 0x08, vk: "7",         mac: 26, us: ['7', '&'] },
+	AnsiKey { xkb: "AE08", scan: 0x09, vk: "8",         mac: 28, us: ['8', '*'] },
+	AnsiKey { xkb: "AE09", scan: 0x0a, vk: "9",         mac: 25, us: ['9', '('] },
+	AnsiKey { xkb: "AE10", scan: 0x0b, vk: "0",         mac: 29, us: ['0', ')'] },
+	AnsiKey { xkb: "AE11", scan: 0x0c, vk: "OEM_MINUS", mac: 27, us: ['-', '_'] },
+	AnsiKey { xkb: "AE12", scan: 0x0d, vk: "OEM_PLUS",  mac: 24, us: ['=', '+'] },
+];
+
+static TOP_ROW: [AnsiKey; 13] = [
+	AnsiKey { xkb: "AD01", scan: 0x10, vk: "Q",         mac: 12, us: ['q', 'Q'] },
+	AnsiKey { xkb: "AD02", scan: 0x11, vk: "W",         mac: 13, us: ['w', 'W'] },
+	AnsiKey { xkb: "AD03", scan: 0x12, vk: "E",         mac: 14, us: ['e', 'E'] },
+	AnsiKey { xkb: "AD04", scan: 0x13, vk: "R",         mac: 15, us: ['r', 'R'] },
+	AnsiKey { xkb: "AD05", scan: 0x14, vk: "T",         mac: 17, us: ['t', 'T'] },
+	AnsiKey { xkb: "AD06", scan: 0x15, vk: "Y",         mac: 16, us: ['y', 'Y'] },
+	AnsiKey { xkb: "AD07", scan: 0x16, vk: "U",         mac: 32, us: ['u', 'U'] },
+	AnsiKey { xkb: "AD08", scan: 0x17, vk: "I",         mac: 34, us: ['i', 'I'] },
+	AnsiKey { xkb: "AD09", scan: 0x18, vk: "O",         mac: 31, us: ['o', 'O'] },
+	AnsiKey { xkb: "AD10", scan: 0x19, vk: "P",         mac: 35, us: ['p', 'P'] },
+	AnsiKey { xkb: "AD11", scan: 0x1a, vk: "OEM_4",     mac: 33, us: ['[', '{'] },
+	AnsiKey { xkb: "AD12", scan: 0x1b, vk: "OEM_6",     mac: 30, us: [']', '}'] },
+	AnsiKey { xkb: "BKSL", scan: 0x2b, vk: "OEM_5",     mac: 42, us: ['\\', '|'] },
+];
+
+static HOME_ROW: [AnsiKey; 11] = [
+	AnsiKey { xkb: "AC01", scan: 0x1e, vk: "A",         mac: 0,  us: ['a', 'A'] },
+	AnsiKey { xkb: "AC02", scan: 0x1f, vk: "S",         mac: 1,  us: ['s', 'S'] },
+	AnsiKey { xkb: "AC03", scan: 0x20, vk: "D",         mac: 2,  us: ['d', 'D'] },
+	AnsiKey { xkb: "AC04", scan: 0x21, vk: "F",         mac: 3,  us: ['f', 'F'] },
+	AnsiKey { xkb: "AC05", scan: 0x22, vk: "G",         mac: 5,  us: ['g', 'G'] },
+	AnsiKey { xkb: "AC06", scan: 0x23, vk: "H",         mac: 4,  us: ['h', 'H'] },
+	AnsiKey { xkb: "AC07", scan: 0x24, vk: "J",         mac: 38, us: ['j', 'J'] },
+	AnsiKey { xkb: "AC08", scan: 0x25, vk: "K",         mac: 40, us: ['k', 'K'] },
+	AnsiKey { xkb: "AC09", scan: 0x26, vk: "L",         mac: 37, us: ['l', 'L'] },
+	AnsiKey { xkb: "AC10", scan: 0x27, vk: "OEM_1",     mac: 41, us: [';', ':'] },
+	AnsiKey { xkb: "AC11", scan: 0x28, vk: "OEM_7",     mac: 39, us: ['\'', '"'] },
+];
+
+static BOTTOM_ROW: [AnsiKey; 10] = [
+	AnsiKey { xkb: "AB01", scan: 0x2c, vk: "Z",          mac: 6,  us: ['z', 'Z'] },
+	AnsiKey { xkb: "AB02", scan: 0x2d, vk: "X",          mac: 7,  us: ['x', 'X'] },
+	AnsiKey { xkb: "AB03", scan: 0x2e, vk: "C",          mac: 8,  us: ['c', 'C'] },
+	AnsiKey { xkb: "AB04", scan: 0x2f, vk: "V",          mac: 9,  us: ['v', 'V'] },
+	AnsiKey { xkb: "AB05", scan: 0x30, vk: "B",          mac: 11, us: ['b', 'B'] },
+	AnsiKey { xkb: "AB06", scan: 0x31, vk: "N",          mac: 45, us: ['n', 'N'] },
+	AnsiKey { xkb: "AB07", scan: 0x32, vk: "M",          mac: 46, us: ['m', 'M'] },
+	AnsiKey { xkb: "AB08", scan: 0x33, vk: "OEM_COMMA",  mac: 43, us: [',', '<'] },
+	AnsiKey { xkb: "AB09", scan: 0x34, vk: "OEM_PERIOD", mac: 47, us: ['.', '>'] },
+	AnsiKey { xkb: "AB10", scan: 0x35, vk: "OEM_2",      mac: 44, us: ['/', '?'] },
+];
+
+static SPACE: AnsiKey = AnsiKey { xkb: "SPCE", scan: 0x39, vk: "SPACE", mac: 49, us: [' ', ' '] };
+
+// The macOS keys that do not type a character, with their output.
+static MAC_CONTROL_KEYS: [(u16, &str); 14] = [
+	(36, "&#x000D;"), (48, "&#x0009;"), (51, "&#x0008;"), (53, "&#x001B;"), (76, "&#x0003;"),
+	(115, "&#x0001;"), (116, "&#x000B;"), (117, "&#x007F;"), (119, "&#x0004;"), (121, "&#x000C;"),
+	(123, "&#x001C;"), (124, "&#x001D;"), (125, "&#x001F;"), (126, "&#x001E;"),
+];
+
+/* ----- *
+ * IMPLS *
+ * ----- */
+
+impl FromStr for ExportFormat
+{
+	type Err = String;
+
+	fn from_str(s: &str)
+	-> Result<ExportFormat, String>
+	{
+		match s {
+			"qmk" => Ok(ExportFormat::Qmk),
+			"zmk" => Ok(ExportFormat::Zmk),
+			"xkb" => Ok(ExportFormat::Xkb),
+			"keylayout" => Ok(ExportFormat::Keylayout),
+			"klc" => Ok(ExportFormat::Klc),
+			_ => Err(format!("unknown export format `{}` (expected qmk, zmk, xkb, keylayout or klc)", s)),
+		}
+	}
+}
+
+// Writes `layout` in `format`.
+pub fn export(layout: &Layout, format: ExportFormat)
+-> Result<Vec<u8>, String>
+{
+	match format {
+		ExportFormat::Qmk => Ok(qmk(layout).into_bytes()),
+		ExportFormat::Zmk => Ok(zmk(layout).into_bytes()),
+		ExportFormat::Xkb => xkb(layout).map(String::into_bytes),
+		ExportFormat::Keylayout => keylayout(layout).map(String::into_bytes),
+		ExportFormat::Klc => klc(layout).map(|s| utf16(&s.replace('\n', "\r\n"))),
+	}
+}
+
+/* -------- *
+ * FIRMWARE *
+ * -------- */
+
+fn qmk(layout: &Layout)
+-> String
+{
+	let firmware = Firmware {
+		key: qmk_key,
+		none: "KC_NO",
+		trans: "KC_TRNS",
+		hold: |n| format!("MO({})", n),
+		oneshot: |n| format!("OSL({})", n),
+	};
+	let geometry = layout.geometry();
+	let (layers, missing) = keycodes(layout, &firmware);
+	let last = *sorted_rows(geometry).iter().flat_map(|row| row.last()).last().unwrap_or(&0);
+
+	let mut out = firmware_header(layout, &missing);
+	out.push_str("\n#include QMK_KEYBOARD_H\n\nconst uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {\n");
+	for (n, codes) in layers.iter().enumerate() {
+		let cells: Vec<String> = codes.iter()
+			.enumerate()
+			.map(|(i, code)| if i == last { code.clone() } else { format!("{},", code) })
+			.collect();
+		out.push_str(&format!("    [{}] = LAYOUT(\n", n));
+		out.push_str(&grid(geometry, &cells, "        "));
+		out.push_str(if n + 1 < layers.len() { "    ),\n" } else { "    )\n" });
+	}
+	out.push_str("};\n");
+	out
+}
+
+fn qmk_key(c: char)
+-> Option<String>
+{
+	if c.is_ascii_lowercase() || c.is_ascii_digit() {
+		Some(format!("KC_{}", c.to_ascii_uppercase()))
+	} else if c.is_ascii_uppercase() {
+		Some(format!("S(KC_{})", c))
+	} else {
+		QMK_KEYCODES.iter().find(|&&(_, k)| k == c).map(|&(code, _)| code.to_string())
+	}
+}
+
+fn zmk(layout: &Layout)
+-> String
+{
+	let firmware = Firmware {
+		key: zmk_key,
+		none: "&none",
+		trans: "&trans",
+		hold: |n| format!("&mo {}", n),
+		oneshot: |n| format!("&sl {}", n),
+	};
+	let geometry = layout.geometry();
+	let (layers, missing) = keycodes(layout, &firmware);
+
+	let mut out = firmware_header(layout, &missing);
+	out.push_str("\n#include <behaviors.dtsi>\n#include <dt-bindings/zmk/keys.h>\n\n");
+	out.push_str("/ {\n    keymap {\n        compatible = \"zmk,keymap\";\n");
+	for (n, codes) in layers.iter().enumerate() {
+		out.push_str(&format!("\n        layer_{} {{\n            bindings = <\n", n));
+		out.push_str(&grid(geometry, codes, "                "));
+		out.push_str("            >;\n        };\n");
+	}
+	out.push_str("    };\n};\n");
+	out
+}
+
+fn zmk_key(c: char)
+-> Option<String>
+{
+	if c.is_ascii_lowercase() {
+		Some(format!("&kp {}", c.to_ascii_uppercase()))
+	} else if c.is_ascii_uppercase() {
+		Some(format!("&kp LS({})", c))
+	} else if c.is_ascii_digit() {
+		Some(format!("&kp N{}", c))
+	} else {
+		ZMK_KEYCODES.iter().find(|&&(_, k)| k == c).map(|&(code, _)| format!("&kp {}", code))
+	}
+}
+
+// The comment a firmware keymap starts with.
+fn firmware_header(layout: &Layout, missing: &[char])
+-> String
+{
+	let mut out = String::from("// Generated by keygen. The keys follow the rows of the geometry, left to\n// right, thumb keys last.\n");
+	if !missing.is_empty() {
+		let chars: Vec<String> = missing.iter().map(|c| format!("{:?}", c)).collect();
+		out.push_str(&format!("// Left out for want of a keycode: {}\n", chars.join(", ")));
+	}
+	for (n, activation) in layout.geometry().layers.iter().enumerate().skip(1) {
+		if *activation == Activation::None {
+			out.push_str(&format!("// Layer {} has no layer key in the geometry.\n", n));
+		}
+	}
+	out
+}
+
+// The keycode of every key on every layer, and the characters that have none.
+fn keycodes(layout: &Layout, firmware: &Firmware)
+-> (Vec<Vec<String>>, Vec<char>)
+{
+	let geometry = layout.geometry();
+	let mut layers = vec![vec![String::new(); geometry.len()]; geometry.layers.len()];
+	let mut missing = Vec::new();
+	for (i, _) in geometry.keys.iter().enumerate() {
+		for (n, &c) in layout.chars_at(i).iter().enumerate() {
+			let code = match (firmware.key)(c) {
+				Some(code) => code,
+				None => {
+					if c != '\0' && !missing.contains(&c) {
+						missing.push(c);
+					}
+					firmware.none.to_string()
+				},
+			};
+			layers[n][i] = code;
+		}
+	}
+
+	// Layer keys switch to their layer from the base layer and let it through
+	// on the layers above.
+	for (n, activation) in geometry.layers.iter().enumerate() {
+		let (keys, code) = match *activation {
+			Activation::None => continue,
+			Activation::Hold(k) => (vec![k], (firmware.hold)(n)),
+			Activation::OneShot(k) => (vec![k], (firmware.oneshot)(n)),
+			Activation::Shift(l, r) => (vec![l, r], (firmware.hold)(n)),
+		};
+		for k in keys {
+			layers[0][k] = code.clone();
+			for layer in layers.iter_mut().skip(1) {
+				layer[k] = firmware.trans.to_string();
+			}
+		}
+	}
+
+	(layers, missing)
+}
+
+// The keys of each row of the geometry, left to right.
+fn sorted_rows(geometry: &Geometry)
+-> Vec<Vec<usize>>
+{
+	let mut rows = geometry.rows();
+	for row in &mut rows {
+		row.sort_by_key(|&i| geometry.keys[i].col);
+	}
+	rows
+}
+
+// Lays out one cell per key in the rows of the geometry, each in the column
+// of its key and with a gap between the halves.
+fn grid(geometry: &Geometry, cells: &[String], indent: &str)
+-> String
+{
+	let width = cells.iter().map(|c| c.chars().count()).max().unwrap_or(0) + 1;
+	let split = geometry.split();
+	let first = geometry.keys.iter().map(|k| k.col).min().unwrap_or(0);
+
+	let mut out = String::new();
+	for row in sorted_rows(geometry) {
+		let mut line = String::new();
+		for i in row {
+			let col = geometry.keys[i].col;
+			let x = (col - first) * width + if col >= split { 2 } else { 0 };
+			let len = line.chars().count();
+			if len < x {
+				line.push_str(&" ".repeat(x - len));
+			}
+			line.push_str(&cells[i]);
+		}
+		out.push_str(indent);
+		out.push_str(line.trim_end());
+		out.push('\n');
+	}
+	out
+}
+
+/* ---------------- *
+ * OPERATING SYSTEM *
+ * ---------------- */
+
+fn xkb(layout: &Layout)
+-> Result<String, String>
+{
+	let keys = ansi_keys(layout)?;
+
+	let mut out = String::from("// Generated by keygen. Copy to /usr/share/X11/xkb/symbols/keygen and load\n// with `setxkbmap keygen`.\n\n");
+	out.push_str("default partial alphanumeric_keys\nxkb_symbols \"basic\" {\n    name[Group1] = \"keygen\";\n\n");
+	for (key, chars) in &keys {
+		let syms: Vec<String> = chars.iter().map(|&c| xkb_keysym(c)).collect();
+		out.push_str(&format!("    key <{}> {{ [ {} ] }};\n", key.xkb, syms.join(", ")));
+	}
+	if layout.geometry().layers.len() > 2 {
+		out.push_str("\n    include \"level3(ralt_switch)\"\n");
+	}
+	out.push_str("};\n");
+	Ok(out)
+}
+
+fn xkb_keysym(c: char)
+-> String
+{
+	if c == '\0' {
+		"NoSymbol".to_string()
+	} else if c.is_ascii_alphanumeric() {
+		c.to_string()
+	} else {
+		match XKB_KEYSYMS.iter().find(|&&(_, k)| k == c) {
+			Some(&(sym, _)) => sym.to_string(),
+			None => format!("U{:04X}", c as u32),
+		}
+	}
+}
+
+fn keylayout(layout: &Layout)
+-> Result<String, String>
+{
+	let keys = ansi_keys(layout)?;
+	let levels = layout.geometry().layers.len();
+	static MODIFIERS: [&str; 4] = ["", "anyShift caps?", "anyOption caps?", "anyShift anyOption caps?"];
+
+	let mut out = String::from("<?xml version=\"1.1\" encoding=\"UTF-8\"?>\n");
+	out.push_str("<!DOCTYPE keyboard SYSTEM \"file://localhost/System/Library/DTDs/KeyboardLayout.dtd\">\n");
+	out.push_str("<!-- Generated by keygen. Copy to ~/Library/Keyboard Layouts and select it\n     under Input Sources. -->\n");
+	out.push_str("<keyboard group=\"126\" id=\"-19341\" name=\"keygen\" maxout=\"1\">\n");
+	out.push_str("    <layouts>\n        <layout first=\"0\" last=\"17\" modifiers=\"modifiers\" mapSet=\"ansi\"/>\n    </layouts>\n");
+	out.push_str("    <modifierMap id=\"modifiers\" defaultIndex=\"0\">\n");
+	for (n, modifiers) in MODIFIERS.iter().enumerate().take(levels) {
+		out.push_str(&format!("        <keyMapSelect mapIndex=\"{}\">\n", n));
+		out.push_str(&format!("            <modifier keys=\"{}\"/>\n", modifiers));
+		out.push_str("        </keyMapSelect>\n");
+	}
+	out.push_str("    </modifierMap>\n    <keyMapSet id=\"ansi\">\n");
+	for n in 0..levels {
+		let mut outputs: Vec<(u16, String)> = keys.iter()
+			.filter(|(_, chars)| chars[n] != '\0')
+			.map(|&(key, ref chars)| (key.mac, xml_escape(chars[n])))
+			.collect();
+		for &(code, output) in MAC_CONTROL_KEYS.iter() {
+			if !outputs.iter().any(|&(c, _)| c == code) {
+				outputs.push((code, output.to_string()));
+			}
+		}
+		outputs.sort_by_key(|&(code, _)| code);
+
+		out.push_str(&format!("        <keyMap index=\"{}\">\n", n));
+		for (code, output) in outputs {
+			out.push_str(&format!("            <key code=\"{}\" output=\"{}\"/>\n", code, output));
+		}
+		out.push_str("        </keyMap>\n");
+	}
+	out.push_str("    </keyMapSet>\n</keyboard>\n");
+	Ok(out)
+}
+
+fn xml_escape(c: char)
+-> String
+{
+	match c {
+		'&' => "&amp;".to_string(),
+		'<' => "&lt;".to_string(),
+		'>' => "&gt;".to_string(),
+		'"' => "&quot;".to_string(),
+		'\'' => "&apos;".to_string(),
+		// The return key of a Mac types a carriage return.
+		'\n' => "&#x000D;".to_string(),
+		c if c.is_control() || (c.is_whitespace() && c != ' ') => format!("&#x{:04X};", c as u32),
+		c => c.to_string(),
+	}
+}
+
+fn klc(layout: &Layout)
+-> Result<String, String>
+{
+	let keys = ansi_keys(layout)?;
+	let levels = layout.geometry().layers.len();
+	static SHIFT_STATES: [(&str, &str); 4] = [
+		("0", "//Column 4"),
+		("1", "//Column 5 : Shft"),
+		("6", "//Column 6 :       Ctrl Alt"),
+		("7", "//Column 7 : Shft  Ctrl Alt"),
+	];
+
+	let mut out = String::from("KBD\tkeygen\t\"keygen\"\n\nCOPYRIGHT\t\"(c) keygen\"\n\nCOMPANY\t\"keygen\"\n\n");
+	out.push_str("LOCALENAME\t\"en-US\"\n\nLOCALEID\t\"00000409\"\n\nVERSION\t1.0\n\nSHIFTSTATE\n\n");
+	for &(state, comment) in SHIFT_STATES.iter().take(levels) {
+		out.push_str(&format!("{}\t{}\n", state, comment));
+	}
+	out.push_str("\nLAYOUT\t\t;an extra '@' at the end is a dead key\n\n");
+	let states: Vec<&str> = SHIFT_STATES.iter().take(levels).map(|&(state, _)| state).collect();
+	out.push_str(&format!("//SC\tVK_\t\tCap\t{}\n", states.join("\t")));
+	out.push_str(&format!("//--\t----\t\t----\t{}\n\n", vec!["----"; levels].join("\t")));
+	for (key, chars) in &keys {
+		let cap = chars.len() > 1 && chars[0].is_lowercase() && chars[1] == import::shift(chars[0]);
+		let chars: Vec<String> = chars.iter().map(|&c| klc_char(c)).collect();
+		out.push_str(&format!("{:02x}\t{}\t\t{}\t{}\n", key.scan, klc_vk(key, chars[0].as_ref()), cap as u8, chars.join("\t")));
+	}
+	out.push_str("\nDESCRIPTIONS\n\n0409\tkeygen\n\nLANGUAGENAMES\n\n0409\tEnglish (United States)\n\nENDKBD\n");
+	Ok(out)
+}
+
+fn klc_char(c: char)
+-> String
+{
+	match c {
+		'\0' => "-1".to_string(),
+		'\n' => "000d".to_string(),
+		c if c.is_ascii_alphanumeric() => c.to_string(),
+		c => format!("{:04x}", c as u32),
+	}
+}
+
+// The virtual key of `key`: the one of the US key with the same base
+// character, so that shortcuts follow the characters.
+fn klc_vk(key: &AnsiKey, base: &str)
+-> &'static str
+{
+	all_ansi_keys()
+		.find(|k| klc_char(k.us[0]) == base)
+		.unwrap_or(key)
+		.vk
+}
+
+fn utf16(s: &str)
+-> Vec<u8>
+{
+	let mut out = vec![0xff, 0xfe];
+	for unit in s.encode_utf16() {
+		out.push(unit as u8);
+		out.push((unit >> 8) as u8);
+	}
+	out
+}
+
+fn all_ansi_keys()
+-> impl Iterator<Item = &'static AnsiKey>
+{
+	NUMBER_ROW.iter()
+		.chain(TOP_ROW.iter())
+		.chain(HOME_ROW.iter())
+		.chain(BOTTOM_ROW.iter())
+		.chain(Some(&SPACE))
+}
+
+// The characters of every key of an ANSI board, one per layer.
+fn ansi_keys(layout: &Layout)
+-> Result<Vec<(&'static AnsiKey, Vec<char>)>, String>
+{
+	let geometry = layout.geometry();
+	let levels = geometry.layers.len();
+	if levels > 4 {
+		return Err(format!(
+			"the geometry has {} layers, a keymap for an operating system at most 4 (base, Shift, AltGr and Shift+AltGr)",
+			levels));
+	}
+	let mut keys: Vec<(&AnsiKey, Vec<char>)> = all_ansi_keys()
+		.map(|k| (k, (0..levels).map(|n| if n < 2 { k.us[n] } else { '\0' }).collect()))
+		.collect();
+
+	let layer_keys = geometry.layer_keys();
+	let first = geometry.keys.iter()
+		.filter(|k| k.hand == Hand::Left && k.finger == Finger::Pinky && !k.stretch)
+		.map(|k| k.col)
+		.min()
+		.unwrap_or(0);
+	let mut mapped = vec![false; keys.len()];
+	let mut space = false;
+	for (i, k) in geometry.keys.iter().enumerate() {
+		// Layer keys are modifiers here.
+		if layer_keys.contains(&i) {
+			continue;
+		}
+		let chars = layout.chars_at(i);
+		let (row, name): (&[AnsiKey], &str) = match k.row {
+			Row::Top => (&TOP_ROW, "top"),
+			Row::Home => (&HOME_ROW, "home"),
+			Row::Bottom => (&BOTTOM_ROW, "bottom"),
+			Row::Thumb => (&[], "thumb"),
+		};
+		let target = if k.row == Row::Thumb {
+			if chars[0] == ' ' && !space {
+				space = true;
+				Some(&SPACE)
+			} else {
+				None
+			}
+		} else {
+			k.col.checked_sub(first).and_then(|col| row.get(col))
+		};
+		match target {
+			Some(target) => {
+				if let Some(j) = keys.iter().position(|&(key, _)| key.xkb == target.xkb) {
+					keys[j].1 = chars;
+					mapped[j] = true;
+				}
+			},
+			// Thumb keys that only add whitespace can be left to the keys of
+			// their own.
+			None if chars.iter().all(|&c| c == '\0' || (k.row == Row::Thumb && c.is_whitespace())) => (),
+			None => return Err(format!(
+				"key {} ({} row, column {}) has no place on an ANSI board; export to qmk or zmk instead",
+				i, name, k.col)),
+		}
+	}
+
+	// The US characters the layout pushed off the board go on the keys left
+	// over whose US characters the layout has elsewhere, e.g. `[` and `{` on
+	// the minus key when the layout puts `-` where `[` was.
+	let typed = |keys: &[(&AnsiKey, Vec<char>)], c: char| {
+		keys.iter().zip(&mapped).any(|((_, chars), &m)| m && chars.contains(&c))
+	};
+	let displaced: Vec<[char; 2]> = keys.iter()
+		.zip(&mapped)
+		.filter(|&(&(key, _), &m)| m && !key.us.iter().any(|&c| typed(&keys, c)))
+		.map(|(&(key, _), _)| key.us)
+		.collect();
+	let free: Vec<usize> = (0..keys.len())
+		.filter(|&j| !mapped[j] && keys[j].0.us.iter().all(|&c| typed(&keys, c)))
+		.collect();
+	for (j, us) in free.into_iter().zip(displaced) {
+		for (n, c) in keys[j].1.iter_mut().enumerate() {
+			*c = if n < 2 { us[n] } else { '\0' };
+		}
+	}
+
+	Ok(keys)
+}
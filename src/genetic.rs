@@ -0,0 +1,170 @@
+//! Genetic operators on keyboard layouts, for `Optimizer::evolve`.
+//!
+//! A layout is a permutation of the keys `LayoutShuffleMask` leaves free: each
+//! of those keys, with its characters on every layer, is a gene. Crossover
+//! takes the order of some genes from one parent and the rest from the other
+//! in a way that keeps every gene exactly once, so that a child types what its
+//! parents type. Mutation is a few random swaps, as in annealing.
+
+extern crate rand;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use self::rand::Rng;
+
+use layout::{Layout, LayoutShuffleMask};
+
+// Members of each tournament.
+pub const TOURNAMENT: usize = 3;
+// Best members carried over to the next generation as they are.
+pub const ELITE:      usize = 2;
+// Chance of a child being mutated.
+pub const MUTATION:   f64   = 0.2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Crossover
+{
+	// Partially mapped crossover: a run of slots from the first parent, the
+	// rest from the second, mapped through the run where they clash.
+	Pmx,
+	// Cycle crossover: every slot from one parent or the other, in cycles
+	// that keep each gene in a slot it has in one of them.
+	Cycle,
+}
+
+impl FromStr for Crossover
+{
+	type Err = String;
+
+	fn from_str(s: &str)
+	-> Result<Crossover, String>
+	{
+		match s {
+			"pmx" => Ok(Crossover::Pmx),
+			"cycle" => Ok(Crossover::Cycle),
+			_ => Err(format!("unknown crossover `{}` (expected pmx or cycle)", s)),
+		}
+	}
+}
+
+impl Crossover
+{
+	// A child of `a` and `b`, or None if they do not have the same free keys,
+	// such as two layouts from different files.
+	pub fn cross<R: Rng>(self, a: &Layout, b: &Layout, mask: &LayoutShuffleMask, rng: &mut R)
+	-> Option<Layout>
+	{
+		let slots = mask.positions();
+		let order = genes_of(a, b, &slots)?;
+		let child = match self {
+			Crossover::Pmx => pmx(&order, rng),
+			Crossover::Cycle => cycle(&order, rng),
+		};
+
+		// Arrange a copy of `a` in the child's order, a swap at a time.
+		let mut layout = a.clone();
+		let mut genes: Vec<usize> = (0..slots.len()).collect();
+		let mut slot_of: Vec<usize> = (0..slots.len()).collect();
+		for k in 0..slots.len() {
+			let j = slot_of[child[k]];
+			if j != k {
+				layout.swap(slots[k], slots[j]);
+				slot_of[genes[k]] = j;
+				slot_of[genes[j]] = k;
+				genes.swap(k, j);
+			}
+		}
+		Some(layout)
+	}
+}
+
+// The index of the member of `totals` with the lowest total of `TOURNAMENT`
+// drawn at random.
+pub fn tournament<R: Rng>(totals: &[f64], rng: &mut R)
+-> usize
+{
+	let mut best = rng.gen::<usize>() % totals.len();
+	for _ in 1..TOURNAMENT {
+		let i = rng.gen::<usize>() % totals.len();
+		if totals[i] < totals[best] {
+			best = i;
+		}
+	}
+	best
+}
+
+// Makes a few random swaps to `layout`, now and then.
+pub fn mutate<R: Rng>(layout: &mut Layout, num_swaps: usize, mask: &LayoutShuffleMask, rng: &mut R)
+{
+	if rng.gen::<f64>() < MUTATION {
+		let swaps = rng.gen::<usize>() % num_swaps.max(1) + 1;
+		layout.shuffle(swaps, mask, rng);
+	}
+}
+
+/* ------- *
+ * HELPERS *
+ * ------- */
+
+// `b` as a permutation of `a`: for each of `slots`, the index of the slot `a`
+// has its key in. Keys that are the same, such as empty ones, pair up in order.
+fn genes_of(a: &Layout, b: &Layout, slots: &[usize])
+-> Option<Vec<usize>>
+{
+	let mut in_a: HashMap<Vec<char>, Vec<usize>> = HashMap::new();
+	for (j, &slot) in slots.iter().enumerate().rev() {
+		in_a.entry(a.chars_at(slot)).or_default().push(j);
+	}
+	slots.iter()
+		.map(|&slot| in_a.get_mut(&b.chars_at(slot)).and_then(|js| js.pop()))
+		.collect()
+}
+
+// PMX of the identity and `order`.
+fn pmx<R: Rng>(order: &[usize], rng: &mut R)
+-> Vec<usize>
+{
+	let n = order.len();
+	let mut start = rng.gen::<usize>() % n;
+	let mut end = rng.gen::<usize>() % n;
+	if start > end {
+		::std::mem::swap(&mut start, &mut end);
+	}
+	let in_run = |gene: usize| start <= gene && gene <= end;
+	(0..n)
+		.map(|k| {
+			if in_run(k) {
+				return k;
+			}
+			// The gene `order` has here is in the run already: take the one
+			// `order` has where the identity has it, until it is not.
+			let mut gene = order[k];
+			while in_run(gene) {
+				gene = order[gene];
+			}
+			gene
+		})
+		.collect()
+}
+
+// Cycle crossover of the identity and `order`, the first cycle from either.
+fn cycle<R: Rng>(order: &[usize], rng: &mut R)
+-> Vec<usize>
+{
+	let n = order.len();
+	let mut child = vec![n; n];
+	let mut from_order = rng.gen::<bool>();
+	for start in 0..n {
+		if child[start] != n {
+			continue;
+		}
+		let mut k = start;
+		while child[k] == n {
+			child[k] = if from_order { order[k] } else { k };
+			k = order[k];
+		}
+		from_order = !from_order;
+	}
+	child
+}
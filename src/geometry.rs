@@ -0,0 +1,399 @@
+//! Physical keyboard geometry: which finger, hand and row type each key.
+//!
+//! A geometry file lists one key per line, in key position order:
+//!
+//! ```text
+//! # offset  row     col  hand   finger  base  [flags]
+//! 0         top     0    left   pinky   5.0
+//! 4         top     4    left   index   2.5   center
+//! 37        thumb   4    thumb  thumb   0.0   fixed
+//! ```
+//!
+//! * `offset` is the index of the key's character in a layout file. The upper
+//!   layer follows `layer` characters later (`layer 40` by default).
+//! * `row` is one of top, home, bottom or thumb; `col` is the key's column,
+//!   used to draw the layout. Columns left of the first right hand key are
+//!   drawn as the left half of the board.
+//! * `hand` is left, right or thumb; `finger` is pinky, ring, middle, index or
+//!   thumb.
+//! * `base` is the base penalty of the key, i.e. the default for the `base`
+//!   array of the penalty model.
+//! * Flags: `center` marks the inner index finger column, `stretch` a key the
+//!   finger has to reach out for, and `fixed` a key that is never moved by the
+//!   optimisers.
+//!
+//! A layout has two layers unless the geometry says otherwise with
+//! `layers <count>`. Layer n starts n times `layer` characters into a layout
+//! file. Other than the first, each layer may declare how it is reached:
+//!
+//! ```text
+//! activate 1 hold 32        # hold key 32 while typing on layer 1
+//! activate 2 oneshot 33     # tap key 33 before each key of layer 2
+//! activate 1 shift 22 31    # hold 22 for keys on the right half and
+//!                           # 31 for keys on the left half
+//! ```
+//!
+//! Layers without an `activate` line cost nothing to reach. Activation keys
+//! are never moved by the optimisers.
+
+use layout::{Activation, Finger, Hand, Row, KeyMap, NUM_OF_KEYS};
+use penalty::BASE_PENALTY;
+
+#[derive(Clone, PartialEq)]
+pub struct Geometry
+{
+	pub keys:   Vec<KeyGeometry>,
+	// Distance between two layers in a layout file.
+	pub layer:  usize,
+	// How each layer is reached, base layer first.
+	pub layers: Vec<Activation>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct KeyGeometry
+{
+	pub offset:  usize,
+	pub row:     Row,
+	pub col:     usize,
+	pub hand:    Hand,
+	pub finger:  Finger,
+	pub base:    f64,
+	pub center:  bool,
+	pub stretch: bool,
+	pub fixed:   bool,
+}
+
+/* ------- *
+ * STATICS *
+ * ------- */
+
+// The built-in split 3x5/3x6 board with two thumb keys. KeyMap format:
+//    LEFT HAND   |    RIGHT HAND
+//  0  1  2  3  4 |  5  6  7  8  9 10
+// 11 12 13 14 15 | 16 17 18 19 20 21
+// 22 23 24 25 26 | 27 28 29 30 31
+//
+//             32 | 33 (thumb keys)
+
+static KEY_FINGERS: KeyMap<Finger> = [
+	Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
+	Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
+	Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky,
+	Finger::Thumb, Finger::Thumb
+];
+static KEY_HANDS: KeyMap<Hand> = [
+	Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
+	Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
+	Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
+	Hand::Thumb, Hand::Thumb
+];
+static KEY_ROWS: KeyMap<Row> = [
+	Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,       Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,
+	Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,      Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,
+	Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom,    Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom,
+	Row::Thumb, Row::Thumb
+];
+static KEY_COLUMNS: KeyMap<usize> = [
+	0, 1, 2, 3, 4,    5, 6, 7, 8, 9, 10,
+	0, 1, 2, 3, 4,    5, 6, 7, 8, 9, 10,
+	0, 1, 2, 3, 4,    5, 6, 7, 8, 9,
+	            4,    5
+];
+static KEY_CENTER_COLUMN: KeyMap<bool> = [
+	false, false, false, false, true,    true, false, false, false, false, false,
+	false, false, false, false, true,    true, false, false, false, false, false,
+	false, false, false, false, true,    true, false, false, false, false,
+	false, false
+];
+static KEY_STRETCH: KeyMap<bool> = [
+	false, false, false, false, false,    false, false, false, false, false, true,
+	false, false, false, false, false,    false, false, false, false, false, true,
+	false, false, false, false, false,    false, false, false, false, false,
+	false, false
+];
+static KEY_FIXED: KeyMap<bool> = [
+	false, false, false, false, false,    false, false, false, false, false, false,
+	false, false, false, false, false,    false, false, false, false, false, false,
+	false, false, false, false, false,    false, false, false, false, false,
+	true, true
+];
+
+static LAYOUT_FILE_IDXS: KeyMap<usize> = [
+	0,  1,  2,  3,  4,     6,  7,  8,  9,  10, 11,
+	13, 14, 15, 16, 17,    19, 20, 21, 22, 23, 24,
+	26, 27, 28, 29, 30,    32, 33, 34, 35, 36,
+	37, 38];
+
+/* ----- *
+ * IMPLS *
+ * ----- */
+
+impl Default for Geometry
+{
+	fn default()
+	-> Geometry
+	{
+		let keys = (0..NUM_OF_KEYS)
+			.map(|i| KeyGeometry {
+				offset: LAYOUT_FILE_IDXS[i],
+				row: KEY_ROWS[i],
+				col: KEY_COLUMNS[i],
+				hand: KEY_HANDS[i],
+				finger: KEY_FINGERS[i],
+				base: BASE_PENALTY[i],
+				center: KEY_CENTER_COLUMN[i],
+				stretch: KEY_STRETCH[i],
+				fixed: KEY_FIXED[i],
+			})
+			.collect();
+
+		Geometry { keys, layer: 40, layers: vec![Activation::None; 2] }
+	}
+}
+
+impl Geometry
+{
+	pub fn from_string(s: &str)
+	-> Result<Geometry, String>
+	{
+		let mut geometry = Geometry { keys: Vec::new(), layer: 40, layers: vec![Activation::None; 2] };
+		let mut activations = Vec::new();
+
+		for (i, line) in s.lines().enumerate() {
+			let line = match line.find('#') {
+				Some(c) => &line[..c],
+				None => line,
+			};
+			let fields: Vec<&str> = line.split_whitespace().collect();
+			if fields.is_empty() {
+				continue;
+			}
+
+			let res = match fields[0] {
+				"layer" => match fields[1..] {
+					[layer] => parse_usize(layer).map(|l| geometry.layer = l),
+					_ => Err("expected `layer <offset>`".to_string()),
+				},
+				"layers" => match fields[1..] {
+					[n] => parse_usize(n).map(|n| geometry.layers = vec![Activation::None; n]),
+					_ => Err("expected `layers <count>`".to_string()),
+				},
+				"activate" => parse_activation(&fields[1..]).map(|a| activations.push((i, a))),
+				_ => parse_key(&fields).map(|k| geometry.keys.push(k)),
+			};
+			if let Err(e) = res {
+				return Err(format!("line {}: {}", i + 1, e));
+			}
+		}
+
+		if geometry.keys.len() < 2 {
+			return Err("a geometry needs at least two keys".to_string());
+		}
+		if geometry.layers.is_empty() {
+			return Err("a geometry needs at least one layer".to_string());
+		}
+		for (i, (layer, activation)) in activations {
+			let keys = match activation {
+				Activation::Hold(key) | Activation::OneShot(key) => vec![key],
+				Activation::Shift(left, right) => vec![left, right],
+				Activation::None => unreachable!(),
+			};
+			if layer == 0 || layer >= geometry.layers.len() {
+				return Err(format!("line {}: layer {} is out of range (1-{})", i + 1, layer, geometry.layers.len() - 1));
+			}
+			for key in keys {
+				if key >= geometry.keys.len() {
+					return Err(format!("line {}: key {} is out of range (0-{})", i + 1, key, geometry.keys.len() - 1));
+				}
+				geometry.keys[key].fixed = true;
+			}
+			geometry.layers[layer] = activation;
+		}
+		for (i, key) in geometry.keys.iter().enumerate() {
+			if key.offset >= geometry.layer {
+				return Err(format!("key {}: offset {} is beyond the start of the upper layer ({})", i, key.offset, geometry.layer));
+			}
+			if geometry.keys[..i].iter().any(|k| k.offset == key.offset) {
+				return Err(format!("key {}: offset {} is used twice", i, key.offset));
+			}
+		}
+
+		Ok(geometry)
+	}
+
+	pub fn len(&self)
+	-> usize
+	{
+		self.keys.len()
+	}
+
+	pub fn is_empty(&self)
+	-> bool
+	{
+		self.keys.is_empty()
+	}
+
+	// Key positions grouped by row, top row first and thumb keys last.
+	pub fn rows(&self)
+	-> Vec<Vec<usize>>
+	{
+		[Row::Top, Row::Home, Row::Bottom, Row::Thumb].iter()
+			.map(|&row| (0..self.len()).filter(|&i| self.keys[i].row == row).collect::<Vec<usize>>())
+			.filter(|keys| !keys.is_empty())
+			.collect()
+	}
+
+	// The keys that reach the upper layers.
+	pub fn layer_keys(&self)
+	-> Vec<usize>
+	{
+		self.layers.iter()
+			.flat_map(|a| match *a {
+				Activation::None => vec![],
+				Activation::Hold(k) | Activation::OneShot(k) => vec![k],
+				Activation::Shift(l, r) => vec![l, r],
+			})
+			.collect()
+	}
+
+	// The first column of the right half of the board.
+	pub fn split(&self)
+	-> usize
+	{
+		self.keys.iter()
+			.filter(|k| k.hand == Hand::Right)
+			.map(|k| k.col)
+			.min()
+			.unwrap_or(usize::MAX)
+	}
+}
+
+/* ------- *
+ * HELPERS *
+ * ------- */
+
+fn parse_key(fields: &[&str])
+-> Result<KeyGeometry, String>
+{
+	if fields.len() < 6 {
+		return Err("expected `offset row col hand finger base [flags]`".to_string());
+	}
+
+	let mut key = KeyGeometry {
+		offset: parse_usize(fields[0])?,
+		row: match fields[1] {
+			"top" => Row::Top,
+			"home" => Row::Home,
+			"bottom" => Row::Bottom,
+			"thumb" => Row::Thumb,
+			r => return Err(format!("unknown row `{}`", r)),
+		},
+		col: parse_usize(fields[2])?,
+		hand: match fields[3] {
+			"left" => Hand::Left,
+			"right" => Hand::Right,
+			"thumb" => Hand::Thumb,
+			h => return Err(format!("unknown hand `{}`", h)),
+		},
+		finger: match fields[4] {
+			"pinky" => Finger::Pinky,
+			"ring" => Finger::Ring,
+			"middle" => Finger::Middle,
+			"index" => Finger::Index,
+			"thumb" => Finger::Thumb,
+			f => return Err(format!("unknown finger `{}`", f)),
+		},
+		base: fields[5].parse::<f64>().map_err(|_| format!("invalid number `{}`", fields[5]))?,
+		center: false,
+		stretch: false,
+		fixed: false,
+	};
+	for flag in &fields[6..] {
+		match *flag {
+			"center" => key.center = true,
+			"stretch" => key.stretch = true,
+			"fixed" => key.fixed = true,
+			f => return Err(format!("unknown flag `{}`", f)),
+		}
+	}
+
+	Ok(key)
+}
+
+// `<layer> hold|oneshot <key>` or `<layer> shift <left key> <right key>`,
+// returned as the layer and its activation.
+fn parse_activation(fields: &[&str])
+-> Result<(usize, Activation), String>
+{
+	match *fields {
+		[layer, "shift", left, right] => {
+			Ok((parse_usize(layer)?, Activation::Shift(parse_usize(left)?, parse_usize(right)?)))
+		},
+		[layer, kind, key] => {
+			let layer = parse_usize(layer)?;
+			let key = parse_usize(key)?;
+			match kind {
+				"hold" => Ok((layer, Activation::Hold(key))),
+				"oneshot" => Ok((layer, Activation::OneShot(key))),
+				k => Err(format!("unknown activation `{}` (expected hold, oneshot or shift)", k)),
+			}
+		},
+		_ => Err("expected `activate <layer> hold|oneshot <key>` or `activate <layer> shift <left key> <right key>`".to_string()),
+	}
+}
+
+fn parse_usize(s: &str)
+-> Result<usize, String>
+{
+	s.parse::<usize>().map_err(|_| format!("invalid number `{}`", s))
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn default_file_is_the_default()
+	{
+		let geometry = Geometry::from_string(include_str!("../geometries/default.txt")).expect("could not read the geometry");
+		assert!(geometry == Geometry::default());
+
+		let shift = include_str!("../geometries/default.txt").to_string() + "activate 1 shift 32 33\n";
+		let geometry = Geometry::from_string(&shift).expect("could not read the geometry");
+		assert!(geometry.layers[1] == Activation::Shift(32, 33));
+		assert!(geometry.keys[32].fixed && geometry.keys[33].fixed);
+		assert_eq!(geometry.layer_keys(), vec![32, 33]);
+	}
+
+	#[test]
+	fn errors()
+	{
+		// Two keys, so that only the line in front of them is wrong.
+		let keys = "0 top 0 left pinky 1.0\n1 top 1 right pinky 1.0\n";
+		for &(line, message) in &[
+			("layer 4 5", "line 1: expected `layer <offset>`"),
+			("layer x", "line 1: invalid number `x`"),
+			("2 top 2 left pinky", "line 1: expected `offset row col hand finger base [flags]`"),
+			("2 middle 2 left pinky 1.0", "line 1: unknown row `middle`"),
+			("2 top 2 centre pinky 1.0", "line 1: unknown hand `centre`"),
+			("2 top 2 left toe 1.0", "line 1: unknown finger `toe`"),
+			("2 top 2 left pinky heavy", "line 1: invalid number `heavy`"),
+			("2 top 2 left pinky 1.0 sticky", "line 1: unknown flag `sticky`"),
+			("layers 0", "a geometry needs at least one layer"),
+			("activate 1 toggle 0", "line 1: unknown activation `toggle` (expected hold, oneshot or shift)"),
+			("activate 1", "line 1: expected `activate <layer> hold|oneshot <key>` or `activate <layer> shift <left key> <right key>`"),
+			("activate 2 hold 0", "line 1: layer 2 is out of range (1-1)"),
+			("activate 1 shift 0 2", "line 1: key 2 is out of range (0-1)"),
+			("layer 1", "key 1: offset 1 is beyond the start of the upper layer (1)"),
+			("0 top 2 left ring 1.0", "key 1: offset 0 is used twice"),
+		] {
+			let file = format!("{}\n{}", line, keys);
+			let err = Geometry::from_string(&file).err().unwrap_or_else(|| panic!("{:?} was read", file));
+			assert_eq!(err, message, "{:?}", file);
+		}
+
+		let err = Geometry::from_string("# one key\n0 top 0 left pinky 1.0\n").err().expect("one key was read");
+		assert_eq!(err, "a geometry needs at least two keys");
+	}
+}
@@ -0,0 +1,760 @@
+//! Importers for layouts published in other formats.
+//!
+//! Each importer reads the keys of a file row by row and layer by layer, and
+//! the keys are then fitted onto the rows of the geometry (top, home, bottom,
+//! thumb), left to right. A file either has to have the same number of keys
+//! in each row as the geometry, or list every key of the geometry in that
+//! order. When a file only gives the base layer, the upper layer is filled
+//! with the shifted characters of a US keyboard.
+//!
+//! * `kle`: keyboard-layout-editor.com JSON (raw data or the downloaded file).
+//!   A key with two legends, e.g. "!\n1", has the shifted character first; a
+//!   single letter is typed in lower case on the base layer.
+//! * `kbd`: whitespace-separated keys, one row per line and a blank line
+//!   between layers. `space`, `enter`, `tab` and `none` name the keys that
+//!   cannot be written as themselves.
+//! * `qmk`: the `LAYOUT(...)` macros of a QMK `keymap.c`, one layer each. Rows
+//!   follow the lines of the macro; comments are skipped. The common keycodes
+//!   that do not type a character (modifiers, layer keys, `KC_TRNS`, ...) are
+//!   left empty, and any other keycode the importer does not know is an error.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use error::{self, Error};
+use geometry::Geometry;
+use layout::Layout;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LayoutFormat
+{
+	Native,
+	Kle,
+	Kbd,
+	Qmk,
+}
+
+// The keys of a layer, row by row.
+type Rows = Vec<Vec<char>>;
+
+impl FromStr for LayoutFormat
+{
+	type Err = String;
+
+	fn from_str(s: &str)
+	-> Result<LayoutFormat, String>
+	{
+		match s {
+			"native" => Ok(LayoutFormat::Native),
+			"kle" => Ok(LayoutFormat::Kle),
+			"kbd" => Ok(LayoutFormat::Kbd),
+			"qmk" => Ok(LayoutFormat::Qmk),
+			_ => Err(format!("unknown layout format `{}` (expected native, kle, kbd or qmk)", s)),
+		}
+	}
+}
+
+impl LayoutFormat
+{
+	// The format a layout file is most likely in, going by its name.
+	pub fn from_filename(filename: &str)
+	-> LayoutFormat
+	{
+		if filename.ends_with(".json") {
+			LayoutFormat::Kle
+		} else if filename.ends_with(".kbd") {
+			LayoutFormat::Kbd
+		} else if filename.ends_with(".c") {
+			LayoutFormat::Qmk
+		} else {
+			LayoutFormat::Native
+		}
+	}
+}
+
+// Reads the layout file `filename` in `format` onto `geometry`.
+pub fn load(filename: &str, format: LayoutFormat, geometry: &Arc<Geometry>, lenient: bool)
+-> Result<Layout, Error>
+{
+	let s = error::read_file(filename)?;
+	import(&s, format, geometry, lenient).map_err(|e| Error::Parse(filename.to_string(), e))
+}
+
+// Reads a layout in `format` onto `geometry`. Only `lenient` lets a native
+// layout have a character on two keys of a layer; the other formats always do.
+pub fn import(s: &str, format: LayoutFormat, geometry: &Arc<Geometry>, lenient: bool)
+-> Result<Layout, String>
+{
+	let layers = match format {
+		LayoutFormat::Native if lenient => return Layout::from_string_lenient(s, geometry),
+		LayoutFormat::Native => return Layout::from_string(s, geometry),
+		LayoutFormat::Kle => read_kle(s)?,
+		LayoutFormat::Kbd => read_kbd(s)?,
+		LayoutFormat::Qmk => read_qmk(s)?,
+	};
+	fit(layers, geometry)
+}
+
+// QMK keycodes of the characters other than letters and digits. Where there are
+// several for a character, the first is the short one.
+pub static QMK_KEYCODES: [(&str, char); 52] = [
+	("KC_SPC", ' '), ("KC_SPACE", ' '), ("KC_ENT", '\n'), ("KC_ENTER", '\n'), ("KC_TAB", '\t'),
+	("KC_MINS", '-'), ("KC_MINUS", '-'), ("KC_EQL", '='), ("KC_EQUAL", '='),
+	("KC_LBRC", '['), ("KC_LEFT_BRACKET", '['), ("KC_RBRC", ']'), ("KC_RIGHT_BRACKET", ']'),
+	("KC_BSLS", '\\'), ("KC_BACKSLASH", '\\'), ("KC_SCLN", ';'), ("KC_SEMICOLON", ';'),
+	("KC_QUOT", '\''), ("KC_QUOTE", '\''), ("KC_GRV", '`'), ("KC_GRAVE", '`'),
+	("KC_COMM", ','), ("KC_COMMA", ','), ("KC_DOT", '.'), ("KC_SLSH", '/'), ("KC_SLASH", '/'),
+	("KC_EXLM", '!'), ("KC_AT", '@'), ("KC_HASH", '#'), ("KC_DLR", '$'), ("KC_PERC", '%'),
+	("KC_CIRC", '^'), ("KC_AMPR", '&'), ("KC_ASTR", '*'), ("KC_LPRN", '('), ("KC_RPRN", ')'),
+	("KC_UNDS", '_'), ("KC_PLUS", '+'), ("KC_LCBR", '{'), ("KC_RCBR", '}'), ("KC_PIPE", '|'),
+	("KC_COLN", ':'), ("KC_DQUO", '"'), ("KC_DQT", '"'), ("KC_TILD", '~'), ("KC_LT", '<'),
+	("KC_GT", '>'), ("KC_QUES", '?'), ("KC_LABK", '<'), ("KC_RABK", '>'), ("KC_NO", '\0'),
+	("XXXXXXX", '\0'),
+];
+
+// QMK keycodes that do not type a character, read as empty keys, besides
+// KC_NO, XXXXXXX and the function keys.
+static QMK_SILENT: [&str; 76] = [
+	"KC_TRNS", "KC_TRANSPARENT", "_______",
+	"KC_LSFT", "KC_RSFT", "KC_LCTL", "KC_RCTL", "KC_LALT", "KC_RALT", "KC_LGUI", "KC_RGUI", "KC_ALGR",
+	"KC_LCMD", "KC_RCMD", "KC_LOPT", "KC_ROPT", "KC_LWIN", "KC_RWIN",
+	"KC_LEFT_SHIFT", "KC_RIGHT_SHIFT", "KC_LEFT_CTRL", "KC_RIGHT_CTRL", "KC_LEFT_ALT", "KC_RIGHT_ALT",
+	"KC_LEFT_GUI", "KC_RIGHT_GUI",
+	"KC_BSPC", "KC_BACKSPACE", "KC_DEL", "KC_DELETE", "KC_ESC", "KC_ESCAPE", "KC_INS", "KC_INSERT",
+	"KC_HOME", "KC_END", "KC_PGUP", "KC_PAGE_UP", "KC_PGDN", "KC_PAGE_DOWN",
+	"KC_LEFT", "KC_RGHT", "KC_RIGHT", "KC_UP", "KC_DOWN",
+	"KC_CAPS", "KC_CAPS_LOCK", "KC_NUM", "KC_NUM_LOCK", "KC_SCRL", "KC_SCROLL_LOCK",
+	"KC_APP", "KC_APPLICATION", "KC_PSCR", "KC_PRINT_SCREEN", "KC_PAUS", "KC_PAUSE",
+	"KC_MUTE", "KC_VOLU", "KC_VOLD", "KC_MPLY", "KC_MSTP", "KC_MNXT", "KC_MPRV",
+	"KC_BRIU", "KC_BRID",
+	"QK_BOOT", "RESET", "QK_RBT", "EE_CLR", "QK_LOCK", "CW_TOGG", "QK_REP", "QK_AREP", "QK_LEAD", "QK_GESC",
+];
+
+// Function-style QMK keycodes that do not type a character: layer switches and
+// shortcuts with modifiers held.
+static QMK_SILENT_FUNCTIONS: [&str; 24] = [
+	"MO", "TG", "TO", "TT", "DF", "PDF", "OSL", "OSM", "LM",
+	"C", "A", "G", "LCTL", "RCTL", "LALT", "RALT", "LGUI", "RGUI", "LCMD", "RCMD", "LOPT", "ROPT",
+	"MEH", "HYPR",
+];
+
+/* ------- *
+ * FITTING *
+ * ------- */
+
+// Puts the keys of each layer of a file onto the key positions of `geometry`.
+fn fit(mut layers: Vec<Rows>, geometry: &Arc<Geometry>)
+-> Result<Layout, String>
+{
+	if layers.is_empty() {
+		return Err("the file has no keys".to_string());
+	}
+	if layers.len() > geometry.layers.len() {
+		return Err(format!("the file has {} layers, the geometry only {}", layers.len(), geometry.layers.len()));
+	}
+	if layers.len() == 1 && geometry.layers.len() > 1 {
+		let shifted = layers[0].iter().map(|row| row.iter().map(|&c| shift(c)).collect()).collect();
+		layers.push(shifted);
+	}
+
+	let mut rows = geometry.rows();
+	for row in &mut rows {
+		row.sort_by_key(|&i| geometry.keys[i].col);
+	}
+	let shape: Vec<usize> = rows.iter().map(|r| r.len()).collect();
+
+	let mut keys = vec![vec!['\0'; geometry.len()]; geometry.layers.len()];
+	for (n, layer) in layers.iter().enumerate() {
+		let layer_shape: Vec<usize> = layer.iter().map(|r| r.len()).collect();
+		let chars: Vec<char> = layer.iter().flat_map(|r| r.iter().cloned()).collect();
+		if layer_shape != shape && chars.len() != geometry.len() {
+			return Err(format!(
+				"layer {} has rows of {} keys ({} in all), the geometry rows of {} keys ({} in all)",
+				n, join(&layer_shape), chars.len(), join(&shape), geometry.len()));
+		}
+		for (&pos, &c) in rows.iter().flat_map(|r| r.iter()).zip(&chars) {
+			keys[n][pos] = c;
+		}
+	}
+
+	Ok(Layout::from_layers(keys, geometry))
+}
+
+fn join(shape: &[usize])
+-> String
+{
+	shape.iter().map(|n| n.to_string()).collect::<Vec<String>>().join(", ")
+}
+
+// The character typed with shift on a US keyboard, or nothing.
+pub fn shift(c: char)
+-> char
+{
+	const US_SHIFT: &str = "`~1!2@3#4$5%6^7&8*9(0)-_=+[{]}\\|;:'\",<.>/?";
+	if c.is_whitespace() {
+		return c;
+	}
+	if c.is_lowercase() {
+		let mut upper = c.to_uppercase();
+		return match (upper.next(), upper.next()) {
+			(Some(u), None) => u,
+			_ => '\0',
+		};
+	}
+	let pairs: Vec<char> = US_SHIFT.chars().collect();
+	pairs.chunks(2)
+		.find(|pair| pair[0] == c)
+		.map(|pair| pair[1])
+		.unwrap_or('\0')
+}
+
+// The character a key called `name` types, for names of more than one
+// character.
+fn named_key(name: &str)
+-> Option<char>
+{
+	match name.to_lowercase().as_ref() {
+		"space" | "spacebar" | "spc" => Some(' '),
+		"enter" | "return" | "ent" => Some('\n'),
+		"tab" => Some('\t'),
+		"none" => Some('\0'),
+		_ => None,
+	}
+}
+
+/* --- *
+ * KLE *
+ * --- */
+
+fn read_kle(s: &str)
+-> Result<Vec<Rows>, String>
+{
+	let value = Json::parse(s)?;
+	let rows = match value {
+		Json::Array(rows) => rows,
+		_ => return Err("expected an array of rows".to_string()),
+	};
+
+	let mut base = Vec::new();
+	let mut upper = Vec::new();
+	for (i, row) in rows.iter().enumerate() {
+		let keys = match *row {
+			Json::Array(ref keys) => keys,
+			// Keyboard metadata.
+			Json::Object if i == 0 => continue,
+			_ => return Err(format!("row {}: expected an array of keys", i + 1)),
+		};
+		let mut base_row = Vec::new();
+		let mut upper_row = Vec::new();
+		for key in keys {
+			// Objects hold the size and position of the key after them.
+			if let Json::String(ref legend) = *key {
+				let (b, u) = kle_legend(legend);
+				base_row.push(b);
+				upper_row.push(u);
+			}
+		}
+		base.push(base_row);
+		upper.push(upper_row);
+	}
+
+	Ok(vec![base, upper])
+}
+
+// The base and shifted character of a key with `legend`.
+fn kle_legend(legend: &str)
+-> (char, char)
+{
+	let legends: Vec<&str> = legend.split('\n').filter(|l| !l.is_empty()).collect();
+	let single = |l: &str| {
+		let mut chars = l.chars();
+		match (chars.next(), chars.next()) {
+			(Some(c), None) => c,
+			_ => named_key(l).unwrap_or('\0'),
+		}
+	};
+	match legends[..] {
+		[] => ('\0', '\0'),
+		[l] => {
+			let c = single(l);
+			if c.is_uppercase() {
+				let mut lower = c.to_lowercase();
+				match (lower.next(), lower.next()) {
+					(Some(lc), None) => (lc, c),
+					_ => (c, '\0'),
+				}
+			} else {
+				(c, shift(c))
+			}
+		},
+		[upper, lower, ..] => (single(lower), single(upper)),
+	}
+}
+
+// Just enough JSON for keyboard-layout-editor files, whose raw data leaves
+// the keys of objects unquoted.
+enum Json
+{
+	Array(Vec<Json>),
+	// The properties of objects do not matter here, nor do numbers and the like.
+	Object,
+	String(String),
+	Other,
+}
+
+impl Json
+{
+	fn parse(s: &str)
+	-> Result<Json, String>
+	{
+		let chars: Vec<char> = s.chars().collect();
+		let mut pos = 0;
+		skip_space(&chars, &mut pos);
+		// Raw data from the editor leaves out the outer brackets.
+		let value = if chars.get(pos) == Some(&'[') && !starts_with_row(&chars, pos) {
+			parse_value(&chars, &mut pos)?
+		} else {
+			let mut rows = Vec::new();
+			while pos < chars.len() {
+				rows.push(parse_value(&chars, &mut pos)?);
+				skip_space(&chars, &mut pos);
+				if chars.get(pos) == Some(&',') {
+					pos += 1;
+					skip_space(&chars, &mut pos);
+				}
+			}
+			Json::Array(rows)
+		};
+		skip_space(&chars, &mut pos);
+		if pos < chars.len() {
+			return Err(format!("unexpected `{}` at character {}", chars[pos], pos + 1));
+		}
+		Ok(value)
+	}
+}
+
+// Whether the array at `pos` is a row of keys rather than the array of rows,
+// i.e. whether its first element is not an array or object.
+fn starts_with_row(chars: &[char], pos: usize)
+-> bool
+{
+	let mut p = pos + 1;
+	skip_space(chars, &mut p);
+	match chars.get(p) {
+		Some(&'[') => false,
+		Some(&'{') => {
+			// `[{...}, [...]]` is metadata followed by rows; `[{...}, "Q"]` a row.
+			let mut depth = 0;
+			while p < chars.len() {
+				match chars[p] {
+					'{' => depth += 1,
+					'}' => {
+						depth -= 1;
+						if depth == 0 {
+							break;
+						}
+					},
+					'"' => skip_string(chars, &mut p),
+					_ => (),
+				}
+				p += 1;
+			}
+			p += 1;
+			skip_space(chars, &mut p);
+			if chars.get(p) == Some(&',') {
+				p += 1;
+				skip_space(chars, &mut p);
+			}
+			chars.get(p) != Some(&'[')
+		},
+		_ => true,
+	}
+}
+
+fn parse_value(chars: &[char], pos: &mut usize)
+-> Result<Json, String>
+{
+	skip_space(chars, pos);
+	match chars.get(*pos) {
+		Some(&'[') => {
+			*pos += 1;
+			let mut items = Vec::new();
+			loop {
+				skip_space(chars, pos);
+				if chars.get(*pos) == Some(&']') {
+					*pos += 1;
+					return Ok(Json::Array(items));
+				}
+				items.push(parse_value(chars, pos)?);
+				skip_space(chars, pos);
+				match chars.get(*pos) {
+					Some(&',') => *pos += 1,
+					Some(&']') => (),
+					Some(_) => return Err(format!("expected `,` or `]` at character {}", *pos + 1)),
+					None => return Err("unexpected end of file".to_string()),
+				}
+			}
+		},
+		Some(&'{') => {
+			*pos += 1;
+			loop {
+				skip_space(chars, pos);
+				if chars.get(*pos) == Some(&'}') {
+					*pos += 1;
+					return Ok(Json::Object);
+				}
+				if chars.get(*pos) == Some(&'"') {
+					parse_string(chars, pos)?;
+				} else {
+					let start = *pos;
+					while *pos < chars.len() && (chars[*pos].is_alphanumeric() || chars[*pos] == '_') {
+						*pos += 1;
+					}
+					if *pos == start {
+						return Err(format!("expected a key at character {}", start + 1));
+					}
+				}
+				skip_space(chars, pos);
+				if chars.get(*pos) != Some(&':') {
+					if *pos >= chars.len() {
+						return Err("unexpected end of file".to_string());
+					}
+					return Err(format!("expected `:` at character {}", *pos + 1));
+				}
+				*pos += 1;
+				parse_value(chars, pos)?;
+				skip_space(chars, pos);
+				match chars.get(*pos) {
+					Some(&',') => *pos += 1,
+					Some(&'}') => (),
+					Some(_) => return Err(format!("expected `,` or `}}` at character {}", *pos + 1)),
+					None => return Err("unexpected end of file".to_string()),
+				}
+			}
+		},
+		Some(&'"') => parse_string(chars, pos).map(Json::String),
+		Some(_) => {
+			// Numbers, booleans and null.
+			let start = *pos;
+			while *pos < chars.len() && (chars[*pos].is_alphanumeric() || "+-.".contains(chars[*pos])) {
+				*pos += 1;
+			}
+			if *pos == start {
+				return Err(format!("unexpected `{}` at character {}", chars[start], start + 1));
+			}
+			Ok(Json::Other)
+		},
+		None => Err("unexpected end of file".to_string()),
+	}
+}
+
+fn parse_string(chars: &[char], pos: &mut usize)
+-> Result<String, String>
+{
+	let start = *pos;
+	*pos += 1;
+	let mut out = String::new();
+	while let Some(&c) = chars.get(*pos) {
+		*pos += 1;
+		match c {
+			'"' => return Ok(out),
+			'\\' => {
+				let e = chars.get(*pos).cloned();
+				*pos += 1;
+				match e {
+					Some('n') => out.push('\n'),
+					Some('t') => out.push('\t'),
+					Some('r') => out.push('\r'),
+					Some('b') => out.push('\u{8}'),
+					Some('f') => out.push('\u{c}'),
+					Some('u') => {
+						let hex: String = chars.iter().skip(*pos).take(4).collect();
+						*pos += 4;
+						match u32::from_str_radix(&hex, 16).ok().and_then(std::char::from_u32) {
+							Some(c) => out.push(c),
+							None => return Err(format!("invalid escape at character {}", *pos - 5)),
+						}
+					},
+					Some(c) => out.push(c),
+					None => break,
+				}
+			},
+			c => out.push(c),
+		}
+	}
+	Err(format!("unterminated string at character {}", start + 1))
+}
+
+fn skip_string(chars: &[char], pos: &mut usize)
+{
+	*pos += 1;
+	while *pos < chars.len() && chars[*pos] != '"' {
+		if chars[*pos] == '\\' {
+			*pos += 1;
+		}
+		*pos += 1;
+	}
+}
+
+fn skip_space(chars: &[char], pos: &mut usize)
+{
+	while *pos < chars.len() && chars[*pos].is_whitespace() {
+		*pos += 1;
+	}
+}
+
+/* --- *
+ * KBD *
+ * --- */
+
+fn read_kbd(s: &str)
+-> Result<Vec<Rows>, String>
+{
+	let mut layers: Vec<Rows> = Vec::new();
+	let mut rows: Rows = Vec::new();
+	for (i, line) in s.lines().enumerate() {
+		if line.trim().is_empty() {
+			if !rows.is_empty() {
+				layers.push(rows);
+				rows = Vec::new();
+			}
+			continue;
+		}
+		let mut row = Vec::new();
+		for token in line.split_whitespace() {
+			let mut chars = token.chars();
+			let c = match (chars.next(), chars.next()) {
+				(Some(c), None) => c,
+				_ => match named_key(token) {
+					Some(c) => c,
+					None => return Err(format!("line {}: unknown key `{}` (expected one character, space, enter, tab or none)", i + 1, token)),
+				},
+			};
+			row.push(c);
+		}
+		rows.push(row);
+	}
+	if !rows.is_empty() {
+		layers.push(rows);
+	}
+	Ok(layers)
+}
+
+/* --- *
+ * QMK *
+ * --- */
+
+fn read_qmk(s: &str)
+-> Result<Vec<Rows>, String>
+{
+	let keycodes = qmk_keycodes();
+	let mut layers = Vec::new();
+	let s = strip_c_comments(s);
+	let mut rest = &s[..];
+	while let Some(start) = rest.find("LAYOUT") {
+		let after = &rest[start..];
+		let open = match after.find('(') {
+			Some(open) if after[..open].chars().all(|c| c.is_alphanumeric() || c == '_') => open,
+			_ => {
+				rest = &after["LAYOUT".len()..];
+				continue;
+			},
+		};
+
+		// Find the closing parenthesis, splitting the arguments on the way.
+		let body = &after[open + 1..];
+		let mut depth = 0;
+		let mut end = None;
+		let mut args: Vec<(usize, &str)> = Vec::new();
+		let mut arg_start = 0;
+		let mut line = 0;
+		let mut arg_line = 0;
+		for (i, c) in body.char_indices() {
+			match c {
+				'(' => depth += 1,
+				')' if depth > 0 => depth -= 1,
+				')' => {
+					end = Some(i);
+					break;
+				},
+				',' if depth == 0 => {
+					args.push((arg_line, &body[arg_start..i]));
+					arg_start = i + 1;
+				},
+				'\n' => line += 1,
+				_ => (),
+			}
+			if body[arg_start..i + c.len_utf8()].trim().is_empty() {
+				arg_line = line;
+			}
+		}
+		let end = match end {
+			Some(end) => end,
+			None => return Err(format!("layer {}: unterminated LAYOUT macro", layers.len())),
+		};
+		if !body[arg_start..end].trim().is_empty() {
+			args.push((arg_line, &body[arg_start..end]));
+		}
+
+		// Keys on the same line of the macro make a row.
+		let mut rows: Rows = Vec::new();
+		let mut last_line = None;
+		for (arg_line, arg) in args {
+			let c = qmk_char(arg.trim(), &keycodes)
+				.map_err(|e| format!("layer {}: {}", layers.len(), e))?;
+			if last_line != Some(arg_line) {
+				rows.push(Vec::new());
+				last_line = Some(arg_line);
+			}
+			rows.last_mut().unwrap().push(c);
+		}
+		layers.push(rows);
+		rest = &body[end..];
+	}
+	if layers.is_empty() {
+		return Err("no LAYOUT macros found".to_string());
+	}
+	Ok(layers)
+}
+
+// The character a QMK keycode types, or nothing for keys that do not type.
+fn qmk_char(code: &str, keycodes: &HashMap<&str, char>)
+-> Result<char, String>
+{
+	if let Some(open) = code.find('(') {
+		if !code.ends_with(')') {
+			return Err(format!("invalid keycode `{}`", code));
+		}
+		let name = code[..open].trim();
+		let args = &code[open + 1..code.len() - 1];
+		let last = args.rsplit(',').next().unwrap_or("").trim();
+		return match name {
+			"S" | "LSFT" | "RSFT" => qmk_char(args.trim(), keycodes).map(shift),
+			// Mod-taps and layer-taps type their last argument when tapped.
+			"LT" | "MT" => qmk_char(last, keycodes),
+			n if n.ends_with("_T") => qmk_char(last, keycodes),
+			n if QMK_SILENT_FUNCTIONS.contains(&n) => Ok('\0'),
+			_ => Err(format!("unknown keycode `{}`", code)),
+		};
+	}
+	if code.is_empty() {
+		return Err("empty keycode".to_string());
+	}
+	let function_key = code.strip_prefix("KC_F").and_then(|n| n.parse::<u8>().ok()).is_some_and(|n| (1..=24).contains(&n));
+	match keycodes.get(code) {
+		Some(&c) => Ok(c),
+		None if function_key || QMK_SILENT.contains(&code) => Ok('\0'),
+		None => Err(format!("unknown keycode `{}`", code)),
+	}
+}
+
+// `s` with its C comments blanked out, keeping the lines where they are.
+fn strip_c_comments(s: &str)
+-> String
+{
+	let mut out = String::with_capacity(s.len());
+	let mut chars = s.chars().peekable();
+	let mut quote = None;
+	while let Some(c) = chars.next() {
+		match (quote, c, chars.peek()) {
+			(Some(_), '\\', Some(&next)) => {
+				out.push(c);
+				out.push(next);
+				chars.next();
+			},
+			(Some(q), c, _) => {
+				if c == q {
+					quote = None;
+				}
+				out.push(c);
+			},
+			(None, '"', _) | (None, '\'', _) => {
+				quote = Some(c);
+				out.push(c);
+			},
+			(None, '/', Some(&'/')) => {
+				while let Some(&c) = chars.peek() {
+					if c == '\n' {
+						break;
+					}
+					chars.next();
+				}
+				out.push(' ');
+			},
+			(None, '/', Some(&'*')) => {
+				chars.next();
+				let mut last = ' ';
+				for c in chars.by_ref() {
+					if c == '\n' {
+						out.push('\n');
+					}
+					if last == '*' && c == '/' {
+						break;
+					}
+					last = c;
+				}
+				out.push(' ');
+			},
+			(None, c, _) => out.push(c),
+		}
+	}
+	out
+}
+
+fn qmk_keycodes()
+-> HashMap<&'static str, char>
+{
+	let mut keycodes: HashMap<&'static str, char> = HashMap::new();
+	static LETTERS: [&str; 26] = [
+		"KC_A", "KC_B", "KC_C", "KC_D", "KC_E", "KC_F", "KC_G", "KC_H", "KC_I", "KC_J", "KC_K", "KC_L", "KC_M",
+		"KC_N", "KC_O", "KC_P", "KC_Q", "KC_R", "KC_S", "KC_T", "KC_U", "KC_V", "KC_W", "KC_X", "KC_Y", "KC_Z"];
+	static DIGITS: [&str; 10] = ["KC_0", "KC_1", "KC_2", "KC_3", "KC_4", "KC_5", "KC_6", "KC_7", "KC_8", "KC_9"];
+	for (code, c) in LETTERS.iter().zip("abcdefghijklmnopqrstuvwxyz".chars()) {
+		keycodes.insert(code, c);
+	}
+	for (code, c) in DIGITS.iter().zip("0123456789".chars()) {
+		keycodes.insert(code, c);
+	}
+	for &(code, c) in QMK_KEYCODES.iter() {
+		keycodes.insert(code, c);
+	}
+	keycodes
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	const QWERTY: &str = include_str!("../tests/golden/qwerty.keymap.c");
+
+	#[test]
+	fn qmk_comments()
+	{
+		let geometry = Arc::new(Geometry::default());
+		let plain = import(QWERTY, LayoutFormat::Qmk, &geometry, false).expect("could not import the keymap");
+		let commented = QWERTY
+			.replacen("LAYOUT(", "LAYOUT( // é base, with \"quotes\"", 1)
+			.replacen("KC_E,", "KC_E, /* ü, ö,\n */", 1);
+		let layout = import(&commented, LayoutFormat::Qmk, &geometry, false).expect("could not import the commented keymap");
+		assert!(layout.layers() == plain.layers());
+	}
+
+	#[test]
+	fn qmk_unknown_keycodes()
+	{
+		let geometry = Arc::new(Geometry::default());
+		let silent = QWERTY.replacen("KC_Q,", "KC_TRNS,", 1).replacen("KC_W,", "MO(1),", 1).replacen("KC_E,", "KC_F12,", 1);
+		let layout = import(&silent, LayoutFormat::Qmk, &geometry, false).expect("could not import the keymap");
+		assert_eq!(layout.chars_at(0)[0], '\0');
+
+		let unknown = QWERTY.replacen("KC_Q,", "KC_SOMETHING,", 1);
+		let e = import(&unknown, LayoutFormat::Qmk, &geometry, false).err().expect("an unknown keycode was accepted");
+		assert!(e.contains("unknown keycode `KC_SOMETHING`"), "{}", e);
+
+		let unknown = QWERTY.replacen("KC_Q,", "SOMETHING(KC_Q),", 1);
+		let e = import(&unknown, LayoutFormat::Qmk, &geometry, false).err().expect("an unknown keycode was accepted");
+		assert!(e.contains("unknown keycode `SOMETHING(KC_Q)`"), "{}", e);
+	}
+}
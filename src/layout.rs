@@ -1,37 +1,48 @@
-/// Data structures and methods for creating and shuffling keyboard layouts.
+//! Data structures and methods for creating and shuffling keyboard layouts.
 
 extern crate rand;
 
+use std::collections::HashMap;
 use std::fmt;
-use self::rand::random;
+use std::sync::Arc;
+use self::rand::Rng;
+
+use geometry::Geometry;
 
 /* ----- *
  * TYPES *
  * ----- */
 
-// KeyMap format:
-//    LEFT HAND   |    RIGHT HAND
-//  0  1  2  3  4 |  5  6  7  8  9 10
-// 11 12 13 14 15 | 16 17 18 19 20 21 
-// 22 23 24 25 26 | 27 28 29 30 31
-//
-//             32 | 33 (thumb keys)
-
+// The keys of the default geometry, in KeyMap order; see `geometry`.
 pub type KeyMap<T> =  [T; NUM_OF_KEYS];
 
+// The characters of one layer, one per key of the geometry.
+#[derive(Clone, PartialEq)]
+pub struct Layer(Vec<char>);
 
+// The layers of a layout on a keyboard geometry, base layer first.
 #[derive(Clone)]
-pub struct Layer(KeyMap<char>);
-
-#[derive(Clone)]
-pub struct Layout(Layer, Layer);
-
+pub struct Layout(Vec<Layer>, Arc<Geometry>);
 
+// A layout for the default geometry, as written in the source.
+pub struct StaticLayout(KeyMap<char>, KeyMap<char>);
 
-pub struct LayoutPosMap([Option<KeyPress>; 128]);
+// ASCII characters are looked up in a flat table, everything else in a map.
+#[derive(Clone)]
+pub struct LayoutPosMap
+{
+	ascii:  [Option<KeyPress>; 128],
+	other:  HashMap<char, Option<KeyPress>>,
+	// How each layer is reached, with the key press that activates it for
+	// keys on the left and on the right half of the board.
+	layers: Vec<(Activation, [Option<KeyPress>; 2])>,
+	// The first column of the right half, for thumb keys.
+	split:  usize,
+}
 
+// Which keys may be moved by shuffles and swap searches.
 #[derive(Clone)]
-pub struct LayoutShuffleMask(KeyMap<bool>);
+pub struct LayoutShuffleMask(Vec<bool>);
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum Finger 
@@ -51,6 +62,19 @@ pub enum Hand
 	Thumb
 }
 
+// How a layer is reached. `Hold` and `OneShot` name the position of the
+// layer key: held down while typing on the layer, or tapped before each key.
+// `Shift` names a layer key on the left and one on the right half; a key is
+// typed with the one on the other half held down.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Activation
+{
+	None,
+	Hold(usize),
+	OneShot(usize),
+	Shift(usize, usize),
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum Row
 {
@@ -60,318 +84,637 @@ pub enum Row
 	Thumb,
 }
 
+// Every combination of 1 to `max_swaps` distinct swaps of swappable keys, i.e.
+// every layout within `max_swaps` swaps of a given one.
+pub struct SwapCombinations
+{
+	swaps:     Vec<(usize, usize)>,
+	idx:       Vec<usize>,
+	max_swaps: usize,
+}
+
+#[allow(dead_code)]
 #[derive(Clone, Copy)]
 pub struct KeyPress
 {
-	pub kc:     char,
-	pub pos:    usize,
-	pub finger: Finger,
-	pub hand:   Hand,
-	pub row:    Row,
-	pub center: bool,
+	pub kc:      char,
+	pub pos:     usize,
+	pub layer:   usize,
+	pub finger:  Finger,
+	pub hand:    Hand,
+	pub row:     Row,
+	pub col:     usize,
+	pub center:  bool,
+	pub stretch: bool,
 }
 
 /* ------- *
 * STATICS *
 * ------- */
 
-const NUM_OF_KEYS: usize = 34;
-
-// pub static _: Layout = Layout(
-//     Layer(['', '', '',  '', '',     '', '', '', '', '', '',
-//            '', '', '',  '', '',     '', '', '', '', '', '',
-//            '', '', '',  '', '',     '', '', '', '', '',
-//                             '',     '']),
-//     Layer(['', '', '',  '', '',     '', '', '', '', '', '',
-//            '', '', '',  '', '',     '', '', '', '', '', '',
-//            '', '', '',  '', '',     '', '', '', '', '',
-//                             '',     ''])
-// );
-
-pub static DABEST: Layout = Layout(
-	Layer(['b', 'y', 'o', 'u', '/',   'f', 'g', 'd', 'l', 'V', '-',
-		   'h', 'i', 'e', 'a', ',',   'd', 't', 's', 'n', 'r', '\'',
-		   'q', 'x', 'z', '.', ';',   'k', 'w', 'c', 'm', 'j',
-                               '\0',  ' ']),
-	Layer(['B', 'Y', 'O', 'U', '?',   'F', 'G', 'D', 'L', 'V', '_',
-		   'H', 'I', 'E', 'A', '<',   'D', 'T', 'S', 'N', 'R', '"',
-		   'Q', 'X', 'Z', '>', ':',   'K', 'W', 'C', 'M', 'J',
-                               '\0',  '\n']));
-
-pub static X1: Layout = Layout(
-	Layer(['k', 'y', 'o', 'u', '/',   'f', 'c', 'l', 'p', 'v', '-',
-		   'h', 'i', 'e', 'a', ',',   'd', 's', 't', 'n', 'r', '\'',
-		   'q', 'x', 'z', '.', ';',   'w', 'g', 'm', 'b', 'j',
-							   '\0',  ' ']),
-	Layer(['K', 'Y', 'O', 'U', '?',   'F', 'C', 'L', 'P', 'V', '_',
-		   'H', 'I', 'E', 'A', '<',   'D', 'S', 'T', 'N', 'R', '"',
-		   'Q', 'X', 'Z', '>', ':',   'W', 'G', 'M', 'B', 'J',
-							   '\0',  '\n']));
-
-pub static QWERTY_LAYOUT: Layout = Layout(
-	Layer(['q', 'w', 'e', 'r', 't',   'y', 'u', 'i', 'o', 'p', '-',
-		   'a', 's', 'd', 'f', 'g',   'h', 'j', 'k', 'l', ';', '\'',
-		   'z', 'x', 'c', 'v', 'b',   'n', 'm', ',', '.', '/',
-                               '\0',  ' ']),
-	Layer(['Q', 'W', 'E', 'R', 'T',   'Y', 'U', 'I', 'O', 'P', '_',
-		   'A', 'S', 'D', 'F', 'G',   'H', 'J', 'K', 'L', ':', '"',
-		   'Z', 'X', 'C', 'V', 'B',   'N', 'M', '<', '>', '?',
-                               '\0',  '\n']));
-
-pub static DVORAK_LAYOUT: Layout = Layout(
-	Layer(['\'', ',', '.', 'p', 'y',  'f', 'g', 'c', 'r', 'l', '/',
-		   'a', 'o', 'e', 'u', 'i',   'd', 'h', 't', 'n', 's', '-',
-		   ';', 'q', 'j', 'k', 'x',   'b', 'm', 'w', 'v', 'z',
-                               '\0',  ' ']),
-	Layer(['"', ',', '.', 'P', 'Y',   'F', 'G', 'C', 'R', 'L', '?',
-		   'A', 'O', 'E', 'U', 'I',   'D', 'H', 'T', 'N', 'S', '_',
-		   ':', 'Q', 'J', 'K', 'X',   'B', 'M', 'W', 'V', 'Z',
-                               '\0',  '\n']));
-
-pub static COLEMAK_LAYOUT: Layout = Layout(
-	Layer(['q', 'w', 'f', 'p', 'g',   'j', 'l', 'u', 'y', ';', '-',
-		   'a', 'r', 's', 't', 'd',   'h', 'n', 'e', 'i', 'o', '\'',
-           'z', 'x', 'c', 'v', 'b',   'k', 'm', ',', '.', '/',
-							   '\0',  ' ']),
-	Layer(['Q', 'W', 'F', 'P', 'G',   'J', 'L', 'U', 'Y', ':', '_',
-		   'A', 'R', 'S', 'T', 'D',   'H', 'N', 'E', 'I', 'O', '"',
-		   'Z', 'X', 'C', 'V', 'B',   'K', 'M', '<', '>', '?',
-							   '\0',  '\n']));
-
-pub static RSTHD: Layout = Layout(
-	Layer(['j', 'c', 'y', 'f', 'k',   'z', 'l', ',', 'u', 'q', '=',
-		   'r', 's', 't', 'h', 'd',   'm', 'n', 'a', 'i', 'o',  '\'',
-		   '/', 'v', 'g', 'p', 'b',   'x', 'w', '.', ';', '-',
-                               'e',   ' ']),
-	Layer(['J', 'C', 'Y', 'F', 'K',   'Z', 'L', '<', 'U', 'Q', '+',
-		   'R', 'S', 'T', 'H', 'D',   'M', 'N', 'A', 'I', 'O', '"',
-		   '?', 'V', 'G', 'P', 'B',   'X', 'W', '>', ':', '_',
-                               'E',   ' ']));
-
-pub static QGMLWY_LAYOUT: Layout = Layout(
-	Layer(['q', 'g', 'm', 'l', 'w',   'y', 'f', 'u', 'b', ';', '-',
-		   'd', 's', 't', 'n', 'r',   'i', 'a', 'e', 'o', 'h', '\'',
-		   'z', 'x', 'c', 'v', 'j',   'k', 'p', ',', '.', '/',
-                               '\0',  ' ']),
-	Layer(['Q', 'G', 'M', 'L', 'W',   'Y', 'F', 'U', 'B', ':', '_',
-		   'D', 'S', 'T', 'N', 'R',   'I', 'A', 'E', 'O', 'H', '"',
-		   'Z', 'X', 'C', 'V', 'J',   'K', 'P', '<', '>', '?',
-                               '\0',  ' ']));
-
-pub static WORKMAN_LAYOUT: Layout = Layout(
-	Layer(['q', 'd', 'r', 'w', 'b',   'j', 'f', 'u', 'p', ';', '-',
-		   'a', 's', 'h', 't', 'g',   'y', 'n', 'e', 'o', 'i', '\'',
-		   'z', 'x', 'm', 'c', 'v',   'k', 'l', ',', '.', '/',
-                               '\0',  ' ']),
-	Layer(['Q', 'D', 'R', 'W', 'B',   'J', 'F', 'U', 'P', ':', '_',
-		   'A', 'S', 'H', 'T', 'G',   'Y', 'N', 'E', 'O', 'I', '"',
-		   'Z', 'X', 'M', 'C', 'V',   'K', 'L', '<', '>', '?',
-                               '\0',  ' ']));
-
-pub static MALTRON_LAYOUT: Layout = Layout(
-	Layer(['q', 'p', 'y', 'c', 'b',   'v', 'm', 'u', 'z', 'l', '=',
-		   'a', 'n', 'i', 's', 'f',   'd', 't', 'h', 'o', 'r', '\'',
-		   ',', '.', 'j', 'g', '/',   ';', 'w', 'k', '-', 'x',
-                               'e',   ' ']),
-	Layer(['Q', 'P', 'Y', 'C', 'B',   'V', 'M', 'U', 'Z', 'L', '+',
-		   'A', 'N', 'I', 'S', 'F',   'D', 'T', 'H', 'O', 'R', '"',
-		   '<', '>', 'J', 'G', '?',   ':', 'W', 'K', '_', 'X',
-                               'E',   ' ']));
-
-pub static MTGAP_LAYOUT: Layout = Layout(
-	Layer(['y', 'p', 'o', 'u', '-',    'b', 'd', 'l', 'c', 'k', 'j',
-		   'i', 'n', 'e', 'a', ',',    'm', 'h', 't', 's', 'r', 'v',
-		   '(', '"', '\'', '.', '_',   ')', 'f', 'w', 'g', 'x',
-                                'z',   ' ']),
-	Layer(['Y', 'P', 'O', 'U', ':',   'B', 'D', 'L', 'C', 'K', 'J',
-           'I', 'N', 'E', 'A', ';',   'M', 'H', 'T', 'S', 'R', 'V',
-           '&', '?', '*', '=', '<',   '>', 'F', 'W', 'G', 'X',
-                               'Z',   '\n']));
-
-pub static CAPEWELL_LAYOUT: Layout = Layout(
-	Layer(['.', 'y', 'w', 'd', 'f',   'j', 'p', 'l', 'u', 'q', '/',
-		   'a', 'e', 'r', 's', 'g',   'b', 't', 'n', 'i', 'o', '-',
-		   'x', 'z', 'c', 'v', ';',   'k', 'w', 'h', ',', '\'',
-                               '\0',  ' ']),
-	Layer(['>', 'Y', 'W', 'D', 'F',   'J', 'P', 'L', 'U', 'Q', '?',
-		   'A', 'E', 'R', 'S', 'G',   'B', 'T', 'N', 'I', 'O', '_',
-		   'X', 'Z', 'C', 'V', ':',   'K', 'W', 'H', '<', '"',
-                               '\0',  ' ']));
-
-pub static ARENSITO_LAYOUT: Layout = Layout(
-	Layer(['q', 'l', ',', 'p', '\0',  '\0', 'f', 'u', 'd', 'k', '\0',
-		   'a', 'r', 'e', 'n', 'b',   'g', 's', 'i', 't', 'o', '\0',
-		   'z', 'w', '.', 'h', 'j',   'v', 'c', 'y', 'm', 'x',
-                               '\0',  ' ']),
-	Layer(['Q', 'L', '<', 'P', '\0',  '\0', 'F', 'U', 'D', 'K', '\0',
-		   'A', 'R', 'E', 'N', 'B',   'G', 'S', 'I', 'T', 'O', '\0',
-		   'Z', 'W', '>', 'H', 'J',   'V', 'C', 'Y', 'M', 'X',
-                               '\0',  ' ']));
-
-pub static THE_ONE: Layout = Layout(
-	Layer(['k', 'm', 'l', 'u', '!',   'v', 'd', 'r', '\'', 'q', '\\',
-           'a', 't', 'h', 'e', '.',   'c', 's', 'n', 'o', 'i', '_',
-		   'z', 'p', 'f', 'j', ',',   'b', 'g', 'w', 'x', 'y',
-                               '\0',  ' ']),
-	Layer(['K', 'M', 'L', 'U', '?',   'V', 'D', 'R', '"', 'Q', '|',
-		   'A', 'T', 'H', 'E', '>',   'C', 'S', 'N', 'O', 'I', '-',
-		   'Z', 'P', 'F', 'J', '<',   'B', 'G', 'W', 'X', 'Y',
-                               '\0',  '\n']));
-
-pub static TEST: Layout = Layout(
-	Layer([	'b', 'y', 'o', 'u', 'j',   'f', 'g', 'd', 'l', 'v', '-',
-			'h', 'i', 'e', 'a', '\'',  'p', 't', 's', 'n', 'r', ';',
-			'z', 'x', 'q', ',', '.',   'k', 'c', 'w', 'm', '/',
-								'(',   ' ']),
-	Layer([	'B', 'Y', 'O', 'U', 'J',   'F', 'G', 'D', 'L', 'V', '_',
-			'H', 'i', 'E', 'A', '"',   'P', 'T', 'S', 'N', 'R', ':',
-			'Z', 'X', 'Q', '<', '>',   'K', 'C', 'W', 'M', '?',
-                                ')',   '\n']));
-
-/*
-pub static SWAPPABLE_MAP: KeyMap<bool>= [
-	false,  false,  false,  false,  true,       false,  false,  false,  false,  false,  false,
-	false,  false,  false,  false,  false,       false,  false,  false,  false,  false,  false,
-	true,  true,  true,  false,  false,       false,  true,  true,  false,  true,
-								false, 		true
-];
-*/
-pub static SWAPPABLE_MAP: KeyMap<bool>= [
-	true,  true,  true,  true,  true,       true,  true,  true,  true,  true,  true,
-	true,  true,  true,  true,  true,       true,  true,  true,  true,  true,  true,
-	true,  true,  true,  true,  true,       true,  true,  true,  true,  true,
-								false, 		false
-];
- 
-
-static KEY_FINGERS: KeyMap<Finger> = [
-	Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
-	Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
-	Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky,
-	Finger::Thumb, Finger::Thumb
-];
-static KEY_HANDS: KeyMap<Hand> = [
-	Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
-	Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
-	Hand::Left, Hand::Left, Hand::Left, Hand::Left, Hand::Left,    Hand::Right, Hand::Right, Hand::Right, Hand::Right, Hand::Right,
-	Hand::Thumb, Hand::Thumb
-];
-static KEY_ROWS: KeyMap<Row> = [
-	Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,       Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,    Row::Top,
-	Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,      Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,
-	Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom,    Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom,
-	Row::Thumb, Row::Thumb
-];
-static KEY_CENTER_COLUMN: KeyMap<bool> = [
-	false, false, false, false, true,    true, false, false, false, false, false,
-	false, false, false, false, true,    true, false, false, false, false, false,
-	false, false, false, false, true,    true, false, false, false, false,
-	false, false
+pub const NUM_OF_KEYS: usize = 34;
+
+// pub static _: StaticLayout = StaticLayout(
+// 	['', '', '', '', '',   '', '', '', '', '', '',
+// 	 '', '', '', '', '',   '', '', '', '', '', '',
+// 	 '', '', '', '', '',   '', '', '', '', '',
+// 	                 '',   ''],
+// 	['', '', '', '', '',   '', '', '', '', '', '',
+// 	 '', '', '', '', '',   '', '', '', '', '', '',
+// 	 '', '', '', '', '',   '', '', '', '', '',
+// 	                 '',   '']);
+
+pub static DABEST: StaticLayout = StaticLayout(
+	['b', 'y', 'o', 'u', '/',   'f', 'g', 'd', 'l', 'V', '-',
+	 'h', 'i', 'e', 'a', ',',   'd', 't', 's', 'n', 'r', '\'',
+	 'q', 'x', 'z', '.', ';',   'k', 'w', 'c', 'm', 'j',
+	                     '\0',  ' '],
+	['B', 'Y', 'O', 'U', '?',   'F', 'G', 'D', 'L', 'V', '_',
+	 'H', 'I', 'E', 'A', '<',   'D', 'T', 'S', 'N', 'R', '"',
+	 'Q', 'X', 'Z', '>', ':',   'K', 'W', 'C', 'M', 'J',
+	                     '\0',  '\n']);
+
+pub static X1: StaticLayout = StaticLayout(
+	['k', 'y', 'o', 'u', '/',   'f', 'c', 'l', 'p', 'v', '-',
+	 'h', 'i', 'e', 'a', ',',   'd', 's', 't', 'n', 'r', '\'',
+	 'q', 'x', 'z', '.', ';',   'w', 'g', 'm', 'b', 'j',
+	                     '\0',  ' '],
+	['K', 'Y', 'O', 'U', '?',   'F', 'C', 'L', 'P', 'V', '_',
+	 'H', 'I', 'E', 'A', '<',   'D', 'S', 'T', 'N', 'R', '"',
+	 'Q', 'X', 'Z', '>', ':',   'W', 'G', 'M', 'B', 'J',
+	                     '\0',  '\n']);
+
+pub static QWERTY_LAYOUT: StaticLayout = StaticLayout(
+	['q', 'w', 'e', 'r', 't',   'y', 'u', 'i', 'o', 'p', '-',
+	 'a', 's', 'd', 'f', 'g',   'h', 'j', 'k', 'l', ';', '\'',
+	 'z', 'x', 'c', 'v', 'b',   'n', 'm', ',', '.', '/',
+	                     '\0',  ' '],
+	['Q', 'W', 'E', 'R', 'T',   'Y', 'U', 'I', 'O', 'P', '_',
+	 'A', 'S', 'D', 'F', 'G',   'H', 'J', 'K', 'L', ':', '"',
+	 'Z', 'X', 'C', 'V', 'B',   'N', 'M', '<', '>', '?',
+	                     '\0',  '\n']);
+
+pub static DVORAK_LAYOUT: StaticLayout = StaticLayout(
+	['\'', ',', '.', 'p', 'y',  'f', 'g', 'c', 'r', 'l', '/',
+	 'a', 'o', 'e', 'u', 'i',   'd', 'h', 't', 'n', 's', '-',
+	 ';', 'q', 'j', 'k', 'x',   'b', 'm', 'w', 'v', 'z',
+	                     '\0',  ' '],
+	['"', ',', '.', 'P', 'Y',   'F', 'G', 'C', 'R', 'L', '?',
+	 'A', 'O', 'E', 'U', 'I',   'D', 'H', 'T', 'N', 'S', '_',
+	 ':', 'Q', 'J', 'K', 'X',   'B', 'M', 'W', 'V', 'Z',
+	                     '\0',  '\n']);
+
+pub static COLEMAK_LAYOUT: StaticLayout = StaticLayout(
+	['q', 'w', 'f', 'p', 'g',   'j', 'l', 'u', 'y', ';', '-',
+	 'a', 'r', 's', 't', 'd',   'h', 'n', 'e', 'i', 'o', '\'',
+	 'z', 'x', 'c', 'v', 'b',   'k', 'm', ',', '.', '/',
+	                     '\0',  ' '],
+	['Q', 'W', 'F', 'P', 'G',   'J', 'L', 'U', 'Y', ':', '_',
+	 'A', 'R', 'S', 'T', 'D',   'H', 'N', 'E', 'I', 'O', '"',
+	 'Z', 'X', 'C', 'V', 'B',   'K', 'M', '<', '>', '?',
+	                     '\0',  '\n']);
+
+pub static RSTHD: StaticLayout = StaticLayout(
+	['j', 'c', 'y', 'f', 'k',   'z', 'l', ',', 'u', 'q', '=',
+	 'r', 's', 't', 'h', 'd',   'm', 'n', 'a', 'i', 'o',  '\'',
+	 '/', 'v', 'g', 'p', 'b',   'x', 'w', '.', ';', '-',
+	                     'e',   ' '],
+	['J', 'C', 'Y', 'F', 'K',   'Z', 'L', '<', 'U', 'Q', '+',
+	 'R', 'S', 'T', 'H', 'D',   'M', 'N', 'A', 'I', 'O', '"',
+	 '?', 'V', 'G', 'P', 'B',   'X', 'W', '>', ':', '_',
+	                     'E',   ' ']);
+
+pub static QGMLWY_LAYOUT: StaticLayout = StaticLayout(
+	['q', 'g', 'm', 'l', 'w',   'y', 'f', 'u', 'b', ';', '-',
+	 'd', 's', 't', 'n', 'r',   'i', 'a', 'e', 'o', 'h', '\'',
+	 'z', 'x', 'c', 'v', 'j',   'k', 'p', ',', '.', '/',
+	                     '\0',  ' '],
+	['Q', 'G', 'M', 'L', 'W',   'Y', 'F', 'U', 'B', ':', '_',
+	 'D', 'S', 'T', 'N', 'R',   'I', 'A', 'E', 'O', 'H', '"',
+	 'Z', 'X', 'C', 'V', 'J',   'K', 'P', '<', '>', '?',
+	                     '\0',  ' ']);
+
+#[allow(dead_code)]
+pub static WORKMAN_LAYOUT: StaticLayout = StaticLayout(
+	['q', 'd', 'r', 'w', 'b',   'j', 'f', 'u', 'p', ';', '-',
+	 'a', 's', 'h', 't', 'g',   'y', 'n', 'e', 'o', 'i', '\'',
+	 'z', 'x', 'm', 'c', 'v',   'k', 'l', ',', '.', '/',
+	                     '\0',  ' '],
+	['Q', 'D', 'R', 'W', 'B',   'J', 'F', 'U', 'P', ':', '_',
+	 'A', 'S', 'H', 'T', 'G',   'Y', 'N', 'E', 'O', 'I', '"',
+	 'Z', 'X', 'M', 'C', 'V',   'K', 'L', '<', '>', '?',
+	                     '\0',  ' ']);
+
+pub static MALTRON_LAYOUT: StaticLayout = StaticLayout(
+	['q', 'p', 'y', 'c', 'b',   'v', 'm', 'u', 'z', 'l', '=',
+	 'a', 'n', 'i', 's', 'f',   'd', 't', 'h', 'o', 'r', '\'',
+	 ',', '.', 'j', 'g', '/',   ';', 'w', 'k', '-', 'x',
+	                     'e',   ' '],
+	['Q', 'P', 'Y', 'C', 'B',   'V', 'M', 'U', 'Z', 'L', '+',
+	 'A', 'N', 'I', 'S', 'F',   'D', 'T', 'H', 'O', 'R', '"',
+	 '<', '>', 'J', 'G', '?',   ':', 'W', 'K', '_', 'X',
+	                     'E',   ' ']);
+
+pub static MTGAP_LAYOUT: StaticLayout = StaticLayout(
+	['y', 'p', 'o', 'u', '-',    'b', 'd', 'l', 'c', 'k', 'j',
+	 'i', 'n', 'e', 'a', ',',    'm', 'h', 't', 's', 'r', 'v',
+	 '(', '"', '\'', '.', '_',   ')', 'f', 'w', 'g', 'x',
+	                      'z',   ' '],
+	['Y', 'P', 'O', 'U', ':',   'B', 'D', 'L', 'C', 'K', 'J',
+	 'I', 'N', 'E', 'A', ';',   'M', 'H', 'T', 'S', 'R', 'V',
+	 '&', '?', '*', '=', '<',   '>', 'F', 'W', 'G', 'X',
+	                     'Z',   '\n']);
+
+pub static CAPEWELL_LAYOUT: StaticLayout = StaticLayout(
+	['.', 'y', 'w', 'd', 'f',   'j', 'p', 'l', 'u', 'q', '/',
+	 'a', 'e', 'r', 's', 'g',   'b', 't', 'n', 'i', 'o', '-',
+	 'x', 'z', 'c', 'v', ';',   'k', 'w', 'h', ',', '\'',
+	                     '\0',  ' '],
+	['>', 'Y', 'W', 'D', 'F',   'J', 'P', 'L', 'U', 'Q', '?',
+	 'A', 'E', 'R', 'S', 'G',   'B', 'T', 'N', 'I', 'O', '_',
+	 'X', 'Z', 'C', 'V', ':',   'K', 'W', 'H', '<', '"',
+	                     '\0',  ' ']);
+
+pub static ARENSITO_LAYOUT: StaticLayout = StaticLayout(
+	['q', 'l', ',', 'p', '\0',  '\0', 'f', 'u', 'd', 'k', '\0',
+	 'a', 'r', 'e', 'n', 'b',   'g', 's', 'i', 't', 'o', '\0',
+	 'z', 'w', '.', 'h', 'j',   'v', 'c', 'y', 'm', 'x',
+	                     '\0',  ' '],
+	['Q', 'L', '<', 'P', '\0',  '\0', 'F', 'U', 'D', 'K', '\0',
+	 'A', 'R', 'E', 'N', 'B',   'G', 'S', 'I', 'T', 'O', '\0',
+	 'Z', 'W', '>', 'H', 'J',   'V', 'C', 'Y', 'M', 'X',
+	                     '\0',  ' ']);
+
+pub static THE_ONE: StaticLayout = StaticLayout(
+	['k', 'm', 'l', 'u', '!',   'v', 'd', 'r', '\'', 'q', '\\',
+	 'a', 't', 'h', 'e', '.',   'c', 's', 'n', 'o', 'i', '_',
+	 'z', 'p', 'f', 'j', ',',   'b', 'g', 'w', 'x', 'y',
+	                     '\0',  ' '],
+	['K', 'M', 'L', 'U', '?',   'V', 'D', 'R', '"', 'Q', '|',
+	 'A', 'T', 'H', 'E', '>',   'C', 'S', 'N', 'O', 'I', '-',
+	 'Z', 'P', 'F', 'J', '<',   'B', 'G', 'W', 'X', 'Y',
+	                     '\0',  '\n']);
+
+#[allow(dead_code)]
+pub static TEST: StaticLayout = StaticLayout(
+	['b', 'y', 'o', 'u', 'j',   'f', 'g', 'd', 'l', 'v', '-',
+	 'h', 'i', 'e', 'a', '\'',  'p', 't', 's', 'n', 'r', ';',
+	 'z', 'x', 'q', ',', '.',   'k', 'c', 'w', 'm', '/',
+	                     '(',   ' '],
+	['B', 'Y', 'O', 'U', 'J',   'F', 'G', 'D', 'L', 'V', '_',
+	 'H', 'i', 'E', 'A', '"',   'P', 'T', 'S', 'N', 'R', ':',
+	 'Z', 'X', 'Q', '<', '>',   'K', 'C', 'W', 'M', '?',
+	                     ')',   '\n']);
+
+// The layouts `run-ref` scores and `validate` checks, in that order.
+pub static REFERENCE_LAYOUTS: [(&str, &StaticLayout); 12] = [
+	("QWERTY", &QWERTY_LAYOUT),
+	("DVORAK", &DVORAK_LAYOUT),
+	("MTGAP", &MTGAP_LAYOUT),
+	("COLEMAK", &COLEMAK_LAYOUT),
+	("QGMLWY", &QGMLWY_LAYOUT),
+	("ARENSITO", &ARENSITO_LAYOUT),
+	("MALTRON", &MALTRON_LAYOUT),
+	("RSTHD", &RSTHD),
+	("CAPEWELL", &CAPEWELL_LAYOUT),
+	("DABEST", &DABEST),
+	("THE_ONE", &THE_ONE),
+	("X1", &X1),
 ];
 
 pub static KP_NONE: Option<KeyPress> = None;
 
-static LAYOUT_FILE_IDXS: KeyMap<usize> = [
-	0,  1,  2,  3,  4,     6,  7,  8,  9,  10, 11,
-	13, 14, 15, 16, 17,    19, 20, 21, 22, 23, 24,
-	26, 27, 28, 29, 30,    32, 33, 34, 35, 36, 
-	37, 38];
-
 /* ----- *
  * IMPLS *
  * ----- */
 
 impl Layout
 {
-	pub fn from_string(s: &str)
-	-> Layout
+	// Reads a layout file: the character of each key sits at the key's offset
+	// in the geometry, each layer `geometry.layer` characters after the last.
+	// Anything between the keys has to be whitespace, and no character may be
+	// on two keys of a layer.
+	pub fn from_string(s: &str, geometry: &Arc<Geometry>)
+	-> Result<Layout, String>
+	{
+		Layout::parse(s, geometry, false)
+	}
+
+	// Reads a layout file like `from_string`, but lets a character be on two
+	// keys of a layer, for `validate` to report.
+	pub fn from_string_lenient(s: &str, geometry: &Arc<Geometry>)
+	-> Result<Layout, String>
 	{
-		let s: Vec<char> = s.chars().collect();
-		let mut lower: [char; 34] = ['\0'; 34];
-		let mut upper: [char; 34] = ['\0'; 34];
-		
-		for i in 0..34 {
-			let file_i = LAYOUT_FILE_IDXS[i];
-			lower[i] = *s.get(file_i).unwrap_or(&'\0');
-			upper[i] = *s.get(file_i + 40).unwrap_or(&'\0');
+		Layout::parse(s, geometry, true)
+	}
+
+	fn parse(s: &str, geometry: &Arc<Geometry>, lenient: bool)
+	-> Result<Layout, String>
+	{
+		let s: Vec<char> = s.replace("\r\n", "\n").chars().collect();
+		let offsets = |n: usize| geometry.keys.iter().map(move |k| n * geometry.layer + k.offset);
+
+		let mut is_key = vec![false; s.len()];
+		for pos in (0..geometry.layers.len()).flat_map(offsets) {
+			if pos < s.len() {
+				is_key[pos] = true;
+			}
+		}
+		if let Some(pos) = (0..s.len()).find(|&pos| !is_key[pos] && !s[pos].is_whitespace()) {
+			return Err(format!("{}: `{}` is not on a key of the geometry", line_col(&s, pos), s[pos]));
 		}
 
-		Layout(Layer(lower), Layer(upper))
+		let mut layers = Vec::with_capacity(geometry.layers.len());
+		for n in 0..geometry.layers.len() {
+			let positions: Vec<usize> = offsets(n).collect();
+			let mut keys: Vec<char> = Vec::with_capacity(geometry.len());
+			for (i, &pos) in positions.iter().enumerate() {
+				let c = match s.get(pos) {
+					Some(&c) => c,
+					None => return Err(format!("{}: the file ends before key {} of layer {}", line_col(&s, pos), i, n)),
+				};
+				if let Some(j) = keys.iter().position(|&k| k == c && k != '\0' && !lenient) {
+					return Err(format!(
+						"{}: `{}` is on key {} of layer {} already ({})",
+						line_col(&s, pos), c.escape_debug(), j, n, line_col(&s, positions[j])));
+				}
+				keys.push(c);
+			}
+			layers.push(Layer(keys));
+		}
+
+		Ok(Layout(layers, geometry.clone()))
+	}
+
+	// A layout from the characters of each layer, one per key of the geometry.
+	pub fn from_layers(layers: Vec<Vec<char>>, geometry: &Arc<Geometry>)
+	-> Layout
+	{
+		Layout(layers.into_iter().map(Layer).collect(), geometry.clone())
 	}
 
-	pub fn shuffle(&mut self, times: usize)
+	pub fn geometry(&self)
+	-> &Arc<Geometry>
+	{
+		&self.1
+	}
+
+	// Makes `times` random swaps of the keys `mask` leaves free.
+	pub fn shuffle<R: Rng>(&mut self, times: usize, mask: &LayoutShuffleMask, rng: &mut R)
 	{
 		for _ in 0..times {
-			let (i, j) = Layout::shuffle_position();
-			let Layout(ref mut lower, ref mut upper) = *self;
-			lower.swap(i, j);
-			upper.swap(i, j);
+			let (i, j) = Layout::shuffle_position(mask, rng);
+			self.swap(i, j);
 		}
 	}
 
+	// Swaps keys `i` and `j` on every layer.
+	pub fn swap(&mut self, i: usize, j: usize)
+	{
+		for layer in &mut self.0 {
+			layer.swap(i, j);
+		}
+	}
+
+	pub fn layers(&self)
+	-> &[Layer]
+	{
+		&self.0
+	}
+
+	// The characters of `layer` by row of the geometry, thumb keys last.
+	pub fn rows(&self, layer: &Layer)
+	-> Vec<Vec<char>>
+	{
+		let Layer(ref keys) = *layer;
+		self.1.rows().iter()
+			.map(|row| row.iter().map(|&i| keys[i]).collect())
+			.collect()
+	}
+
+	// The characters on key `pos`, base layer first.
+	pub fn chars_at(&self, pos: usize)
+	-> Vec<char>
+	{
+		self.0.iter().map(|Layer(keys)| keys[pos]).collect()
+	}
+
+	// The keys listed in `keys`, a comma-separated list of key positions (see
+	// the KeyMap format) and single characters. A character stands for
+	// whichever key holds it, on any layer.
+	pub fn find_keys(&self, keys: &str)
+	-> Result<Vec<usize>, String>
+	{
+		let len = self.1.len();
+		let mut found = Vec::new();
+		for key in keys.split(',').filter(|k| !k.is_empty()) {
+			if let Ok(pos) = key.parse::<usize>() {
+				if pos >= len {
+					return Err(format!("key position {} is out of range (0-{})", pos, len - 1));
+				}
+				found.push(pos);
+				continue;
+			}
+
+			let mut chars = key.chars();
+			let c = match (chars.next(), chars.next()) {
+				(Some(c), None) => c,
+				_ => return Err(format!("`{}` is neither a key position nor a single character", key)),
+			};
+			match (0..len).find(|&i| self.chars_at(i).contains(&c)) {
+				Some(pos) => found.push(pos),
+				None => return Err(format!("`{}` is not on the layout", c)),
+			}
+		}
+		Ok(found)
+	}
+
+	// The characters on more than one key of a layer, with the layer and the
+	// keys. Only the last of those keys is ever typed.
+	pub fn duplicates(&self)
+	-> Vec<(char, usize, Vec<usize>)>
+	{
+		let mut duplicates = Vec::new();
+		for (n, Layer(keys)) in self.0.iter().enumerate() {
+			for (i, &c) in keys.iter().enumerate() {
+				if c == '\0' || keys[..i].contains(&c) {
+					continue;
+				}
+				let on: Vec<usize> = (i..keys.len()).filter(|&j| keys[j] == c).collect();
+				if on.len() > 1 {
+					duplicates.push((c, n, on));
+				}
+			}
+		}
+		duplicates
+	}
+
+	// The keys without a character, as (layer, key). Layer keys are left out.
+	pub fn empty_keys(&self)
+	-> Vec<(usize, usize)>
+	{
+		let layer_keys = self.1.layer_keys();
+		let mut empty = Vec::new();
+		for (n, Layer(keys)) in self.0.iter().enumerate() {
+			for (i, &c) in keys.iter().enumerate() {
+				if c == '\0' && !layer_keys.contains(&i) {
+					empty.push((n, i));
+				}
+			}
+		}
+		empty
+	}
+
 	pub fn get_position_map(&self) -> LayoutPosMap
 	{
-		let Layout(ref lower, ref upper) = *self;
-		let mut map = [None; 128];
-		lower.fill_position_map(&mut map);
-		upper.fill_position_map(&mut map);
+		let Layout(ref layers, ref geometry) = *self;
+		let mut map = LayoutPosMap {
+			ascii: [None; 128],
+			other: HashMap::new(),
+			layers: geometry.layers.iter()
+				.map(|&a| {
+					let key = |pos| Some(KeyPress::at('\0', pos, 0, geometry));
+					match a {
+						Activation::None => (a, [None, None]),
+						Activation::Hold(pos) | Activation::OneShot(pos) => (a, [key(pos), key(pos)]),
+						Activation::Shift(left, right) => (a, [key(right), key(left)]),
+					}
+				})
+				.collect(),
+			split: geometry.split(),
+		};
+		// A character on several layers is typed from the lowest one.
+		for (n, layer) in layers.iter().enumerate().rev() {
+			layer.fill_position_map(&mut map, n, geometry);
+		}
 
-		LayoutPosMap(map)
+		map
 	}
 
-	fn  shuffle_position() -> (usize, usize)
+	// Brings `map` up to date for `chars` after they have been moved.
+	pub fn update_position_map(&self, map: &mut LayoutPosMap, chars: &[char])
 	{
-		let mut i = random::<usize>() % NUM_OF_KEYS;
-		let mut j = random::<usize>() % NUM_OF_KEYS;
+		for &c in chars {
+			map.set(c, None);
+		}
+		let Layout(ref layers, ref geometry) = *self;
+		for (n, layer) in layers.iter().enumerate().rev() {
+			layer.fill_position_map_for(map, chars, n, geometry);
+		}
+	}
+
+	pub fn shuffle_position<R: Rng>(mask: &LayoutShuffleMask, rng: &mut R) -> (usize, usize)
+	{
+		let LayoutShuffleMask(ref mask) = *mask;
+		let mut i = rng.gen::<usize>() % mask.len();
+		let mut j = rng.gen::<usize>() % mask.len();
 
-		while !SWAPPABLE_MAP[i] {
-			i = random::<usize>() % NUM_OF_KEYS;
+		while !mask[i] {
+			i = rng.gen::<usize>() % mask.len();
 		}
-		while !SWAPPABLE_MAP[j] {
-			j = random::<usize>() % NUM_OF_KEYS;
+		while !mask[j] {
+			j = rng.gen::<usize>() % mask.len();
 		}
 		(i,j)
 	}
 }
 
+impl PartialEq for Layout
+{
+	fn eq(&self, other: &Layout)
+	-> bool
+	{
+		self.0 == other.0 && (Arc::ptr_eq(&self.1, &other.1) || self.1 == other.1)
+	}
+}
+
+impl StaticLayout
+{
+	// The layout on the default geometry.
+	pub fn layout(&self, geometry: &Arc<Geometry>)
+	-> Layout
+	{
+		let StaticLayout(ref lower, ref upper) = *self;
+		Layout(vec![Layer(lower.to_vec()), Layer(upper.to_vec())], geometry.clone())
+	}
+}
+
+impl LayoutShuffleMask
+{
+	// Every key of `geometry` that is not marked as fixed.
+	pub fn new(geometry: &Geometry)
+	-> LayoutShuffleMask
+	{
+		LayoutShuffleMask(geometry.keys.iter().map(|k| !k.fixed).collect())
+	}
+
+	// Pins the keys listed in `pins`, as for `Layout::find_keys`.
+	pub fn pin(&mut self, layout: &Layout, pins: &str)
+	-> Result<(), String>
+	{
+		let LayoutShuffleMask(ref mut mask) = *self;
+		for pos in layout.find_keys(pins)? {
+			mask[pos] = false;
+		}
+		Ok(())
+	}
+
+	// Whether shuffles and swap searches may move the key at `pos`.
+	pub fn is_free(&self, pos: usize)
+	-> bool
+	{
+		let LayoutShuffleMask(ref mask) = *self;
+		mask[pos]
+	}
+
+	pub fn positions(&self)
+	-> Vec<usize>
+	{
+		let LayoutShuffleMask(ref mask) = *self;
+		(0..mask.len()).filter(|&i| mask[i]).collect()
+	}
+}
+
+impl SwapCombinations
+{
+	pub fn new(max_swaps: usize, mask: &LayoutShuffleMask)
+	-> SwapCombinations
+	{
+		let swappable = mask.positions();
+		let mut swaps = Vec::new();
+		for (n, &i) in swappable.iter().enumerate() {
+			for &j in &swappable[n + 1..] {
+				swaps.push((i, j));
+			}
+		}
+
+		SwapCombinations {
+			swaps,
+			idx: Vec::new(),
+			max_swaps,
+		}
+	}
+
+	// Advance `idx` to the next combination of swaps, moving on to one more
+	// swap once every combination of the current size has been produced.
+	fn advance(&mut self)
+	-> bool
+	{
+		let n = self.swaps.len();
+		let k = self.idx.len();
+		let mut i = k;
+		while i > 0 {
+			i -= 1;
+			if self.idx[i] < n - k + i {
+				self.idx[i] += 1;
+				for j in (i + 1)..k {
+					self.idx[j] = self.idx[j - 1] + 1;
+				}
+				return true;
+			}
+		}
+
+		if k >= self.max_swaps || k >= n {
+			return false;
+		}
+		self.idx = (0..k + 1).collect();
+		true
+	}
+}
+
+impl Iterator for SwapCombinations
+{
+	type Item = Vec<(usize, usize)>;
+
+	fn next(&mut self)
+	-> Option<Vec<(usize, usize)>>
+	{
+		if !self.advance() {
+			return None;
+		}
+		Some(self.idx.iter().map(|&s| self.swaps[s]).collect())
+	}
+}
+
 impl Layer
 {
 	fn swap(&mut self, i: usize, j: usize)
 	{
 		let Layer(ref mut layer) = *self;
-		let temp = layer[i];
-		layer[i] = layer[j];
-		layer[j] = temp;
+		layer.swap(i, j);
 	}
 
-	fn fill_position_map(&self, map: &mut [Option<KeyPress>; 128])
+	fn fill_position_map(&self, map: &mut LayoutPosMap, n: usize, geometry: &Geometry)
 	{
 		let Layer(ref layer) = *self;
-		for (i, c) in layer.into_iter().enumerate() {
-			if *c < (128 as char) {
-				map[*c as usize] = Some(KeyPress {
-					kc: *c,
-					pos: i,
-					finger: KEY_FINGERS[i],
-					hand: KEY_HANDS[i],
-					row: KEY_ROWS[i],
-					center: KEY_CENTER_COLUMN[i],
-				});
+		for (i, c) in layer.iter().enumerate() {
+			map.set(*c, Some(KeyPress::at(*c, i, n, geometry)));
+		}
+	}
+
+	fn fill_position_map_for(&self, map: &mut LayoutPosMap, chars: &[char], n: usize, geometry: &Geometry)
+	{
+		let Layer(ref layer) = *self;
+		for (i, c) in layer.iter().enumerate() {
+			if chars.contains(c) {
+				map.set(*c, Some(KeyPress::at(*c, i, n, geometry)));
 			}
 		}
 	}
 }
 
+impl KeyPress
+{
+	fn at(kc: char, pos: usize, layer: usize, geometry: &Geometry)
+	-> KeyPress
+	{
+		let key = &geometry.keys[pos];
+		KeyPress {
+			kc,
+			pos,
+			layer,
+			finger: key.finger,
+			hand: key.hand,
+			row: key.row,
+			col: key.col,
+			center: key.center,
+			stretch: key.stretch,
+		}
+	}
+}
+
 impl LayoutPosMap
 {
+	fn set(&mut self, kc: char, kp: Option<KeyPress>)
+	{
+		if kc.is_ascii() {
+			self.ascii[kc as usize] = kp;
+		} else {
+			self.other.insert(kc, kp);
+		}
+	}
+
+	// How the layer of `kp` is reached, and the key press that activates it
+	// for `kp`.
+	pub fn layer_key(&self, kp: &KeyPress)
+	-> (Activation, &Option<KeyPress>)
+	{
+		let (activation, ref keys) = self.layers[kp.layer];
+		let right = match kp.hand {
+			Hand::Left => false,
+			Hand::Right => true,
+			Hand::Thumb => kp.col >= self.split,
+		};
+		(activation, &keys[right as usize])
+	}
+
 	pub fn get_key_position(&self, kc: char)
 	-> &Option<KeyPress>
 	{
-		let LayoutPosMap(ref map) = *self;
-		if kc < (128 as char) {
-			&map[kc as usize]
+		if kc.is_ascii() {
+			&self.ascii[kc as usize]
 		} else {
-			&KP_NONE
+			self.other.get(&kc).unwrap_or(&KP_NONE)
 		}
 	}
 }
@@ -379,30 +722,119 @@ impl LayoutPosMap
 
 impl fmt::Display for Layout
 {
+	// Draws the lower layer row by row, with a bar between the two halves.
 	fn fmt(&self, f: &mut fmt::Formatter)
 	-> fmt::Result
 	{
-		let Layout(ref lower, _) = *self;
-		lower.fmt(f)
+		let Layout(ref layers, ref geometry) = *self;
+		let Layer(ref lower) = layers[0];
+		let split = geometry.split();
+		for (n, row) in geometry.rows().iter().enumerate() {
+			if n > 0 {
+				writeln!(f)?;
+			}
+			let mut line = String::new();
+			let mut bar = false;
+			for &i in row {
+				let col = geometry.keys[i].col;
+				if !bar && col >= split {
+					pad(&mut line, 2 * split);
+					line.push('|');
+					bar = true;
+				}
+				pad(&mut line, 2 * col + if bar { 2 } else { 0 });
+				line.push(lower[i]);
+			}
+			write!(f, "{}", line)?;
+		}
+		Ok(())
 	}
 }
 
-impl fmt::Display for Layer
+// Where character `pos` of a file is, or would be, counting from 1.
+fn line_col(s: &[char], pos: usize)
+-> String
 {
-	fn fmt(&self, f: &mut fmt::Formatter)
-	-> fmt::Result
+	let before = &s[..pos.min(s.len())];
+	let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+	let start = before.iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1);
+	format!("line {}, column {}", line, pos - start + 1)
+}
+
+// Pads `line` with spaces to `width` characters.
+fn pad(line: &mut String, width: usize)
+{
+	let len = line.chars().count();
+	if len < width {
+		line.push_str(&" ".repeat(width - len));
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::sync::Arc;
+
+	use geometry::Geometry;
+	use super::*;
+
+	// `layout` as a layout file, with spaces between the keys.
+	fn to_file(layout: &Layout)
+	-> String
 	{
-		let Layer(ref layer) = *self;
-		write!(f, "{} {} {} {} {} | {} {} {} {} {} {}
-{} {} {} {} {} | {} {} {} {} {} {}
-{} {} {} {} {} | {} {} {} {} {}
-        {} | {}",
-			layer[0], layer[1], layer[2], layer[3], layer[4],
-			layer[5], layer[6], layer[7], layer[8], layer[9], layer[10],
-			layer[11], layer[12], layer[13], layer[14], layer[15],
-			layer[16], layer[17], layer[18], layer[19], layer[20], layer[21],
-			layer[22], layer[23], layer[24], layer[25], layer[26],
-			layer[27], layer[28], layer[29], layer[30], layer[31],
-			layer[32], layer[33])
+		let geometry = layout.geometry();
+		let mut s = vec![' '; geometry.layers.len() * geometry.layer];
+		for (n, layer) in layout.layers().iter().enumerate() {
+			let Layer(ref keys) = *layer;
+			for (k, &c) in geometry.keys.iter().zip(keys) {
+				s[n * geometry.layer + k.offset] = c;
+			}
+		}
+		s.into_iter().collect()
+	}
+
+	#[test]
+	fn unicode_round_trip()
+	{
+		let geometry = Arc::new(Geometry::default());
+		let mut layers: Vec<Vec<char>> = QWERTY_LAYOUT.layout(&geometry).layers().iter()
+			.map(|Layer(keys)| keys.clone())
+			.collect();
+		// Two bytes, three bytes and four bytes in UTF-8, on both layers, in
+		// place of Q, W and E.
+		let a = 0;
+		layers[0][a] = 'é';
+		layers[1][a] = 'É';
+		layers[0][a + 1] = 'ß';
+		layers[1][a + 1] = '€';
+		layers[0][a + 2] = '😀';
+		let layout = Layout::from_layers(layers, &geometry);
+
+		let read = Layout::from_string(&to_file(&layout), &geometry).expect("could not read the layout");
+		assert!(read == layout);
+		assert_eq!(read.chars_at(a), vec!['é', 'É']);
+		assert_eq!(read.chars_at(a + 1), vec!['ß', '€']);
+
+		let map = read.get_position_map();
+		for &(c, pos, layer) in &[('é', a, 0), ('É', a, 1), ('€', a + 1, 1), ('😀', a + 2, 0)] {
+			let kp = map.get_key_position(c).as_ref().expect("character not on the layout");
+			assert_eq!((kp.pos, kp.layer), (pos, layer), "{}", c);
+		}
+		assert!(map.get_key_position('q').is_none());
+	}
+
+	#[test]
+	fn unicode_duplicates_are_errors()
+	{
+		let geometry = Arc::new(Geometry::default());
+		let mut layers: Vec<Vec<char>> = QWERTY_LAYOUT.layout(&geometry).layers().iter()
+			.map(|Layer(keys)| keys.clone())
+			.collect();
+		layers[0][0] = 'ñ';
+		layers[0][1] = 'ñ';
+		let layout = Layout::from_layers(layers, &geometry);
+
+		let err = Layout::from_string(&to_file(&layout), &geometry).err().expect("a duplicate was read");
+		assert!(err.contains("`ñ` is on key 0 of layer 0 already"), "{}", err);
 	}
 }
@@ -0,0 +1,66 @@
+//! Scoring and optimising keyboard layouts.
+//!
+//! A `Layout` puts characters on the keys of a `Geometry`. A `Scorer`, such as
+//! the penalty `Model`, scores it on the quartads of a corpus, and an
+//! `Optimizer` searches for better layouts, telling its caller how it is
+//! getting on through a `Progress` callback:
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use keygen::{corpus, layout, Error, Geometry, LayoutShuffleMask, Model, Optimizer, OptimizerOptions, Progress};
+//! # fn main() -> Result<(), Error> {
+//! let geometry = Arc::new(Geometry::default());
+//! let layout = layout::QWERTY_LAYOUT.layout(&geometry);
+//! let corpus = corpus::load("corpus/books.short.txt", 1.0)?;
+//! let quartads = corpus::blend(&[corpus]);
+//! let model = Model::new(&geometry);
+//! let mask = LayoutShuffleMask::new(&geometry);
+//! let options = OptimizerOptions { seed: 1, ..OptimizerOptions::default() };
+//! let optimizer = Optimizer::new(&model, &quartads, &mask, &options);
+//! let best = optimizer.anneal(&layout, &|progress| {
+//!     if let Progress::Iteration(i) = progress {
+//!         println!("iteration {}", i);
+//!     }
+//! })?;
+//! println!("{}", best[0].penalty.total);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `Optimizer::evolve` searches with a genetic algorithm instead, whose
+//! operators are in `genetic`, `climb` with tabu search or late acceptance
+//! hill climbing from `search`, and `optimize` with whichever the options say.
+//! `Optimizer::pareto` anneals for several objectives at once and returns the
+//! layouts that trade them off best; see `pareto`.
+//!
+//! The `keygen` binary is a command line around this library.
+
+pub mod error;
+pub mod layout;
+pub mod import;
+pub mod export;
+pub mod corpus;
+pub mod ngrams;
+pub mod geometry;
+pub mod penalty;
+pub mod model;
+pub mod annealing;
+pub mod genetic;
+pub mod pareto;
+pub mod search;
+pub mod evaluator;
+pub mod scorer;
+pub mod simulator;
+pub mod permute;
+pub mod checkpoint;
+pub mod validate;
+
+pub use corpus::Corpus;
+pub use error::Error;
+pub use geometry::Geometry;
+pub use layout::{Layout, LayoutShuffleMask};
+pub use model::Model;
+pub use penalty::{BestLayoutsEntry, QuartadList};
+pub use scorer::Scorer;
+pub use simulator::{AnnealState, Optimizer, OptimizerOptions, Progress, Refined, Search};
+pub use validate::Validation;
@@ -1,28 +1,41 @@
 extern crate getopts;
+extern crate keygen;
+#[cfg(unix)]
+extern crate libc;
+extern crate rand;
 
-mod layout;
-mod penalty;
-mod annealing;
-mod simulator;
+mod report;
 
 
 use std::env;
-use std::fs::File;
-use std::io::Read;
-use getopts::Options;
-use penalty::QuartadList;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use getopts::{Matches, Options};
+use keygen::{annealing, corpus, error, export, import, layout, penalty, permute};
+use keygen::error::Error;
+use keygen::corpus::{Corpus, CorpusData, CorpusOptions, Newline, Unsupported};
+use keygen::geometry::Geometry;
+use keygen::import::LayoutFormat;
+use keygen::export::ExportFormat;
+use keygen::penalty::QuartadList;
+use keygen::model::Model;
+use keygen::annealing::Annealing;
+use keygen::checkpoint::Checkpoint;
+use keygen::genetic::Crossover;
+use keygen::pareto::Objectives;
+use keygen::search;
+use keygen::simulator::{AnnealState, Optimizer, OptimizerOptions, Progress, Search};
+use keygen::validate::Validation;
+use report::OutputFormat;
 
 
 
 //  made thumbs their own hand, 
 //  as they dont really matter from strain perspective when analysing alternation/rolls/etc
 
-/* TODO:
-		add penalities for uneven finger load
-		add penalities for uneven hand load
-		unicode support
-		cache layout-position-map and apply swaps directly to it, so it doesnt have to realocate every cycle
-*/
 
 /* running options
 
@@ -32,167 +45,732 @@ use penalty::QuartadList;
 	cargo run -- run-ref corpus/books.short.txt
 		test reference layouts
 
+	cargo run -- run corpus/books.short.txt layouts/ --seed-refs
+		anneals from every layout in layouts/ and the reference layouts, a different one per thread
+
+	cargo run -- run corpus/books.short.txt --optimizer ga --population 64 --generations 200 --crossover cycle
+		evolves layouts with a genetic algorithm in place of annealing, for 200 generations
+
+	cargo run -- run corpus/books.short.txt --objectives same_finger:2,roll_reversal
+		keeps the layouts that trade off same finger, roll reversal and finger load best
+		(or anneals on their weighted sum with --scalarize)
+
+	cargo run -- run corpus/books.short.txt --optimizer lahc --history 2000
+		climbs with late acceptance (or tabu search: --optimizer tabu --tabu-tenure 50)
+
+	cargo run -- refine corpus/books.short.txt layout.txt -s 1
+		hill-climbs from layout.txt until no swap improves it
+
+	cargo run -- permute corpus/books.short.txt layout.txt --keys "',.,;,/,-"
+		tries every arrangement of the listed keys that could be best, the rest staying put
+
+	cargo run -- corpus-stats corpus/books.short.txt -o books.ngrams
+		writes the n-gram counts of the corpus, to be used in its place
+
+	cargo run -- run corpus/books.short.txt --checkpoint run.ckpt
+	cargo run -- resume run.ckpt
+		saves the state of the run every 20000 cycles and on Ctrl-C, and carries it on
+
+	cargo run -- validate corpus/books.short.txt layout.txt
+		lists duplicate characters, empty keys and the characters layout.txt cannot type
+
+	cargo run -- export layout.txt --to qmk -o keymap.c
+		writes layout.txt as a keymap for firmware or an operating system
+
 
 */
+
+// Set by Ctrl-C during a run with checkpoints.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Where `run` writes its checkpoints, and the state it carries on from.
+struct Checkpointing
+{
+	file:   String,
+	// The command line of the run, without the program name.
+	args:   Vec<String>,
+	resume: Option<AnnealState>,
+}
+
 fn main()
+{
+	let args: Vec<String> = env::args().collect();
+	if let Err(e) = keygen(&args, None) {
+		eprintln!("Error: {}", e);
+		process::exit(e.exit_code());
+	}
+}
+
+// Runs the command line `args`. `resume` is the checkpoint file the command
+// line was read from, if any.
+fn keygen(args: &[String], resume: Option<(&str, &str)>)
+-> Result<(), Error>
 {
 	let mut opts = Options::new();
 	opts.optflag("h", "help", "print this help menu");
 	opts.optflag("d", "debug", "show debug logging");
 	opts.optopt("t", "top", "number of top layouts to print (default: 1)", "TOP_LAYOUTS");
-	opts.optopt("s", "swaps-per-iteration", "maximum number of swaps per iteration (default: 3)", "SWAPS");
+	opts.optopt("s", "swaps-per-iteration", "maximum number of swaps per iteration or refine step (default: 2)", "SWAPS");
+	opts.optopt("g", "geometry", "keyboard geometry file (default: built-in 34-key board)", "GEOMETRY");
+	opts.optopt("m", "model", "penalty model file (default: built-in weights)", "MODEL");
+	opts.optopt("f", "format", "output format: text, json or csv (default: text)", "FORMAT");
+	opts.optopt("", "optimizer", "how `run` searches: sa (simulated annealing), ga (genetic algorithm), tabu (tabu search) or lahc (late acceptance hill climbing) (default: sa)", "OPTIMIZER");
+	opts.optopt("", "population", "layouts in each generation of the genetic algorithm (default: 64)", "SIZE");
+	opts.optopt("", "crossover", "crossover of the genetic algorithm: pmx or cycle (default: pmx)", "CROSSOVER");
+	opts.optopt("", "generations", "generations the genetic algorithm breeds (default: 200)", "COUNT");
+	opts.optopt("", "tabu-tenure", "swaps the tabu list of tabu search remembers (default: 50)", "SWAPS");
+	opts.optopt("", "history", "cycles late acceptance hill climbing looks back (default: 1000)", "CYCLES");
+	opts.optopt("", "cycles", "annealing cycles per thread and iteration (default: 205000)", "CYCLES");
+	opts.optopt("", "iterations", "annealing iterations (default: 5)", "ITERATIONS");
+	opts.optopt("", "schedule", "cooling schedule: exponential, linear, logarithmic, restart or reheat (default: exponential)", "SCHEDULE");
+	opts.optopt("", "t0", "starting temperature of the schedule (default: 1.5)", "T0");
+	opts.optopt("", "cooling-rate", "cooling rate k of the schedule (default: 10)", "K");
+	opts.optopt("", "p0", "acceptance probability of an equally good layout (default: 1)", "P0");
+	opts.optopt("", "schedule-length", "length N in cycles of the schedule, or of each restart (default: 20000)", "N");
+	opts.optopt("", "seed", "seed for the random number generator (default: random)", "SEED");
+	opts.optflag("", "seed-refs", "start `run` from the reference layouts too");
+	opts.optopt("", "warm-up", "random swaps each worker makes to its starting layout before `run` anneals it (default: 0)", "SWAPS");
+	opts.optflag("", "lowercase", "fold the corpus to lower case");
+	opts.optflag("", "normalise-whitespace", "turn CRLF into LF, tabs into spaces and runs of spaces into one");
+	opts.optopt("", "newline", "type newlines with their own key or as spaces: key or space (default: key)", "NEWLINE");
+	opts.optopt("", "unsupported", "characters the layout cannot type: keep, strip or map (default: keep)", "UNSUPPORTED");
+	opts.optopt("o", "output", "file to write the output of corpus-stats or export to (default: standard output)", "FILE");
+	opts.optopt("", "to", "format to export to: qmk, zmk, xkb, keylayout or klc", "FORMAT");
+	opts.optopt("", "layout-format", "format of the layout file: native, kle, kbd or qmk (default: guessed from the file name)", "FORMAT");
+	opts.optmulti("p", "pin", "comma-separated key positions or characters to keep in place", "KEYS");
+	opts.optmulti("", "keys", "comma-separated key positions or characters for permute to arrange", "KEYS");
+	opts.optopt("", "checkpoint", "file to save the state of `run` to, to carry it on with `resume`", "FILE");
+	opts.optopt("", "checkpoint-every", "cycles between checkpoints (default: 20000)", "CYCLES");
+	opts.optopt("", "objectives", "comma-separated penalties, each with an optional :weight, for `run` to keep a Pareto front over along with finger load", "PENALTIES");
+	opts.optflag("", "scalarize", "anneal on the weighted sum of --objectives instead of keeping a front");
 
-	let args: Vec<String> = env::args().collect();
 	let progname = &args[0];
-	if args.len() < 2 {
+	if args.len() < 2 || args[1] == "-h" || args[1] == "--help" {
 		print_usage(progname, opts);
-		return;
+		return Ok(());
 	}
 	let command = &args[1];
-	let matches = match opts.parse(&args[2..]) {
-		Ok(m) => { m }
-		Err(f) => { panic!(f.to_string()) }
-	};
+	let matches = opts.parse(&args[2..])?;
 
 	// --help
 	if matches.opt_present("h") {
 		print_usage(progname, opts);
-		return;
+		return Ok(());
+	}
+	if command == "resume" {
+		return resume_run(progname, &matches.free);
+	}
+	if !["run", "run-ref", "refine", "permute", "corpus-stats", "validate", "export"].contains(&command.as_ref()) {
+		return Err(Error::Usage(format!("unknown command `{}` (expected run, run-ref, refine, permute, corpus-stats, validate, export or resume)", command)));
+	}
+	for option in &["checkpoint", "seed-refs", "objectives", "scalarize"] {
+		if command != "run" && matches.opt_present(option) {
+			return Err(Error::Usage(format!("--{} only applies to run", option)));
+		}
+	}
+	if command != "permute" && matches.opt_present("keys") {
+		return Err(Error::Usage("--keys only applies to permute".to_string()));
 	}
 
-	// Read corpus.
-	let corpus_filename = match matches.free.get(0) {
-		Some(f) => f,
-		None => {
-			print_usage(progname, opts);
-			return;
+	// Sort out the free arguments: the first is a corpus, and so is every
+	// `path:weight` after it. The first one without a weight after that is the
+	// layout. `export` only takes a layout, and `run` takes any number of
+	// layouts and directories of layouts to start from.
+	let export = command == "export";
+	let mut corpus_args: Vec<(String, f64)> = Vec::new();
+	let mut layout_args: Vec<&String> = Vec::new();
+	for (i, arg) in matches.free.iter().enumerate() {
+		match corpus::parse_weighted(arg) {
+			_ if export && layout_args.is_empty() => layout_args.push(arg),
+			Some(corpus) if layout_args.is_empty() => corpus_args.push(corpus),
+			_ if i == 0 => corpus_args.push((arg.clone(), 1.0)),
+			_ if layout_args.is_empty() || command == "run" => layout_args.push(arg),
+			_ => return Err(Error::Usage(format!("unexpected argument {}", arg))),
+		}
+	}
+	let layout_filenames = layout_files(&layout_args)?;
+	let layout_filename = layout_filenames.first();
+	if corpus_args.is_empty() && !export {
+		return Err(Error::Usage(format!("{} needs a corpus; see {} --help", command, progname)));
+	}
+
+	// Read corpora.
+	let mut corpora: Vec<Corpus> = Vec::new();
+	for (corpus_filename, weight) in corpus_args {
+		corpora.push(corpus::load(&corpus_filename, weight)?);
+	}
+
+	// Read keyboard geometry, if applicable.
+	let geometry = Arc::new(match matches.opt_str("g") {
+		None => Geometry::default(),
+		Some(geometry_filename) => {
+			let geometry_str = error::read_file(&geometry_filename)?;
+			Geometry::from_string(&geometry_str).map_err(|e| Error::Parse(geometry_filename, e))?
 		},
+	});
+
+	let seed_refs = matches.opt_present("seed-refs");
+	if seed_refs && *geometry != Geometry::default() {
+		return Err(Error::Usage("--seed-refs needs the built-in geometry".to_string()));
+	}
+
+	// Read layouts, if applicable. The first is the one to score, refine or
+	// export, and the one to pin keys of.
+	let layout_format = match matches.opt_str("layout-format") {
+		None => None,
+		Some(f) => Some(f.parse::<LayoutFormat>().map_err(|e| Error::Usage(format!("--layout-format: {}", e)))?),
 	};
-	let mut f = match File::open(corpus_filename) {
-		Ok(f) => f,
-		Err(e) => {
-			println!("Error: {}", e);
-			panic!("could not read corpus");
+	// `validate` reports the duplicates that the other commands refuse.
+	let mut population: Vec<(&str, layout::Layout)> = Vec::new();
+	for layout_filename in &layout_filenames {
+		let format = layout_format.unwrap_or_else(|| LayoutFormat::from_filename(layout_filename));
+		population.push((layout_filename, import::load(layout_filename, format, &geometry, command == "validate")?));
+	}
+	if population.is_empty() {
+		if *geometry != Geometry::default() {
+			return Err(Error::Usage("a layout file is required with a custom geometry".to_string()));
+		}
+		if !seed_refs {
+			population.push(("QWERTY", layout::QWERTY_LAYOUT.layout(&geometry)));
+		}
+	}
+	let given = population.len();
+	if seed_refs {
+		// A layout with a character on two keys never types one of them, and
+		// annealing from it finds layouts that are only good for that.
+		let mut skipped = Vec::new();
+		for &(name, l) in layout::REFERENCE_LAYOUTS.iter() {
+			let layout = l.layout(&geometry);
+			if layout.duplicates().is_empty() {
+				population.push((name, layout));
+			} else {
+				skipped.push(name);
+			}
+		}
+		if !skipped.is_empty() {
+			eprintln!("Warning: not starting from {}, which have characters on two keys of a layer", skipped.join(", "));
+		}
+	}
+	let layout = &population[0].1.clone();
+
+	if export {
+		return export_layout(layout, matches.opt_str("to"), matches.opt_str("o"));
+	}
+
+	// Read penalty model, if applicable.
+	let model = match matches.opt_str("m") {
+		None => Model::new(&geometry),
+		Some(model_filename) => {
+			let model_str = error::read_file(&model_filename)?;
+			Model::from_string(&model_str, &geometry).map_err(|e| Error::Parse(model_filename, e))?
 		},
 	};
-	let mut corpus = String::new();
-	match f.read_to_string(&mut corpus) {
-		Ok(_) => (),
-		Err(e) => {
-			println!("Error: {}", e);
-			panic!("could not read corpus");
+
+	// Preprocess the corpus, if applicable.
+	let corpus_options = CorpusOptions {
+		lowercase: matches.opt_present("lowercase"),
+		whitespace: matches.opt_present("normalise-whitespace"),
+		newline: parseopt(&matches, "newline", Newline::Key)?,
+		unsupported: parseopt(&matches, "unsupported", Unsupported::Keep)?,
+	};
+
+	// Parse options.
+	let debug = matches.opt_present("d");
+	let top   = numopt(&matches, "t", 1usize)?;
+	let swaps = numopt(&matches, "s", 2usize)?;
+	if swaps < 1 {
+		return Err(Error::Usage("-s: at least one swap is needed".to_string()));
+	}
+	let format = parseopt(&matches, "f", OutputFormat::Text)?;
+	let seed = match matches.opt_str("seed") {
+		None => rand::random::<u64>(),
+		Some(_) => numopt(&matches, "seed", 0u64)?,
+	};
+	let cycles     = numopt(&matches, "cycles", 205_000usize)?;
+	let iterations = numopt(&matches, "iterations", 5usize)?;
+	let schedule   = matches.opt_str("schedule").unwrap_or_else(|| "exponential".to_string());
+	let annealing = Annealing::new(
+		&schedule,
+		numopt(&matches, "t0", annealing::T0)?,
+		numopt(&matches, "cooling-rate", annealing::K)?,
+		numopt(&matches, "p0", annealing::P0)?,
+		numopt(&matches, "schedule-length", annealing::N)?)
+		.map_err(Error::Usage)?;
+	let checkpoint = matches.opt_str("checkpoint");
+	let checkpoint_cycles = match checkpoint {
+		None => 0,
+		Some(_) => numopt(&matches, "checkpoint-every", 20_000usize)?,
+	};
+	let search = parseopt(&matches, "optimizer", Search::Annealing)?;
+	if search != Search::Annealing && checkpoint.is_some() {
+		return Err(Error::Usage("--checkpoint only applies to --optimizer sa".to_string()));
+	}
+	let options = OptimizerOptions {
+		search,
+		top_layouts: top,
+		num_swaps: swaps,
+		seed,
+		cycles,
+		iterations,
+		annealing,
+		warm_up: numopt(&matches, "warm-up", 0usize)?,
+		population: numopt(&matches, "population", 64usize)?,
+		crossover: parseopt(&matches, "crossover", Crossover::Pmx)?,
+		generations: numopt(&matches, "generations", 200usize)?,
+		tabu_tenure: numopt(&matches, "tabu-tenure", search::TENURE)?,
+		history: numopt(&matches, "history", search::HISTORY)?,
+		checkpoint_cycles,
+	};
+
+	// Several objectives, if applicable: `run` keeps a front of layouts over
+	// them, or anneals on their weighted sum in place of the model's total.
+	let objectives = match matches.opt_str("objectives") {
+		None if matches.opt_present("scalarize") => return Err(Error::Usage("--scalarize needs --objectives".to_string())),
+		None => None,
+		Some(s) => Some(s.parse::<Objectives>().map_err(|e| Error::Usage(format!("--objectives: {}", e)))?),
+	};
+	let (model, objectives) = match objectives {
+		Some(o) if matches.opt_present("scalarize") => (o.model(&model, &vec![1.0; o.keys().len()]), None),
+		o => (model, o),
+	};
+	if objectives.is_some() && search != Search::Annealing {
+		return Err(Error::Usage("--objectives only applies to --optimizer sa, unless with --scalarize".to_string()));
+	}
+	if objectives.is_some() && checkpoint.is_some() {
+		return Err(Error::Usage("--checkpoint cannot be used with --objectives, unless with --scalarize".to_string()));
+	}
+
+	// Pin keys, if applicable.
+	let mut mask = layout::LayoutShuffleMask::new(&geometry);
+	for pins in matches.opt_strs("p") {
+		mask.pin(layout, &pins).map_err(|e| Error::Usage(format!("--pin {}: {}", pins, e)))?;
+	}
+	if mask.positions().len() < 2 {
+		return Err(Error::Usage("at least two keys must be left unpinned".to_string()));
+	}
+	let keys = layout.find_keys(&matches.opt_strs("keys").join(",")).map_err(|e| Error::Usage(format!("--keys: {}", e)))?;
+	if command == "permute" {
+		if keys.len() < 2 {
+			return Err(Error::Usage(format!("permute needs at least two keys to arrange with --keys; see {} --help", progname)));
 		}
+		if let Some(pos) = keys.iter().find(|&&pos| !mask.is_free(pos)) {
+			return Err(Error::Usage(format!("--keys: key {} is fixed or pinned", pos)));
+		}
+		if let Some(n) = (1..keys.len()).find(|&n| keys[..n].contains(&keys[n])) {
+			return Err(Error::Usage(format!("--keys: key {} is listed twice", keys[n])));
+		}
+	}
+
+	if corpus_options != CorpusOptions::default() {
+		let report = format == OutputFormat::Text && command != "corpus-stats";
+		for corpus in &mut corpora {
+			// N-gram files are counted already, so there is no text left to
+			// prepare.
+			let text = match corpus.data {
+				CorpusData::Text(ref mut text) => text,
+				CorpusData::Ngrams(_) => return Err(Error::Usage(format!(
+					"{} is an n-gram file: --lowercase, --normalise-whitespace, --newline and --unsupported only apply to text corpora",
+					corpus.name))),
+			};
+			let (prepared, stats) = corpus::prepare(text, layout, &corpus_options);
+			if report {
+				println!(
+					"Corpus {}: {} characters read, {} kept, {} mapped, {} dropped because the layout cannot type them",
+					corpus.name, stats.read, stats.kept, stats.mapped, stats.dropped);
+			}
+			*text = prepared;
+		}
+		if report {
+			println!();
+		}
+	}
+	if command == "corpus-stats" {
+		let table = corpus::blend_ngrams(&corpora).to_string();
+		return error::write_output(matches.opt_str("o").as_ref().map(String::as_ref), table.as_bytes());
+	}
+	if command == "validate" {
+		return validate_layouts(&corpora, layout_filename, layout);
+	}
+	if command != "run-ref" {
+		// The reference layouts are what they are.
+		let chars = corpus::blend_chars(&corpora);
+		for &(name, ref layout) in &population[..given] {
+			let validation = Validation::new(layout, &chars);
+			if !validation.is_complete() {
+				eprintln!("Warning: {}: {}; see `{} validate`", name, validation.summary(), progname);
+			}
+		}
+	}
+	let quartads = corpus::blend(&corpora);
+
+	// Pick up the state of the run, if resuming.
+	let resume = match resume {
+		None => None,
+		Some((filename, contents)) => {
+			let checkpoint = Checkpoint::from_string(contents, &geometry).map_err(|e| Error::Parse(filename.to_string(), e))?;
+			if checkpoint.quartads != quartads.map.values().sum::<i64>() {
+				return Err(Error::Invalid(format!("{}: the corpus has changed since the checkpoint was written", filename)));
+			}
+			Some(checkpoint.state)
+		},
 	};
+	// A run that drew its seed saves it, so that `resume` carries on with the
+	// same random numbers.
+	let mut saved_args = args[1..].to_vec();
+	if !matches.opt_present("seed") {
+		saved_args.extend(["--seed".to_string(), seed.to_string()]);
+	}
+	let checkpointing = checkpoint.as_ref().map(|file| Checkpointing { file: file.clone(), args: saved_args, resume });
 
+	let population: Vec<layout::Layout> = population.into_iter().map(|(_, layout)| layout).collect();
+	let mut optimizer = Optimizer::new(&model, &quartads, &mask, &options);
+	match command.as_ref() {
+		"run" => {
+			if checkpointing.is_some() {
+				catch_interrupt();
+				optimizer.interrupt_on(&INTERRUPTED);
+			}
+			run(&quartads, &corpora, &population, &optimizer, objectives.as_ref(), format, checkpointing).map_err(|e| match (e, checkpoint) {
+				(Error::Interrupted(msg), Some(file)) => Error::Interrupted(format!("{}; carry on with `{} resume {}`", msg, progname, file)),
+				(e, _) => e,
+			})
+		},
+		"run-ref" => run_ref(&quartads, &corpora, &geometry, &model, format),
+		"permute" => permute_keys(&quartads, &corpora, layout, &model, &keys, top, format),
+		_ => refine(&quartads, &corpora, layout, &model, &optimizer, format, debug),
+	}
+}
 
-	// Read layout, if applicable.
-	let _layout;
-	let layout = match matches.free.get(1) {
-		None => &layout::QWERTY_LAYOUT,
-		Some(layout_filename) => {
-			let mut f = match File::open(layout_filename) {
-				Ok(f) => f,
-				Err(e) => {
-					println!("Error: {}", e);
-					panic!("could not read layout");
+// Carries on the run saved in the checkpoint file `free[0]`.
+fn resume_run(progname: &str, free: &[String])
+-> Result<(), Error>
+{
+	let filename = match free {
+		[filename] => filename,
+		_ => return Err(Error::Usage(format!("resume takes a checkpoint file and nothing else; see {} --help", progname))),
+	};
+	let contents = error::read_file(filename)?;
+	let saved = Checkpoint::args(&contents).map_err(|e| Error::Parse(filename.clone(), e))?;
+	let mut args = vec![progname.to_string()];
+	args.extend(saved);
+	if args.get(1).map(String::as_ref) != Some("run") {
+		return Err(Error::Parse(filename.clone(), "the checkpoint is not of a run".to_string()));
+	}
+	keygen(&args, Some((filename, &contents)))
+}
+
+// Anneals from the layouts of `population`, or carries on from a checkpoint.
+// With `objectives`, prints the Pareto front over them in place of the best
+// layouts.
+fn run(quartads: &QuartadList, corpora: &[Corpus], population: &[layout::Layout], optimizer: &Optimizer<Model>, objectives: Option<&Objectives>, format: OutputFormat, checkpointing: Option<Checkpointing>)
+-> Result<(), Error>
+{
+	let model = optimizer.scorer();
+	let text = format == OutputFormat::Text;
+	let layout = &population[0];
+	let (checkpointing, resume) = match checkpointing {
+		Some(Checkpointing { file, args, resume }) => (Some((file, args)), resume),
+		None => (None, None),
+	};
+	let progress = |progress: Progress| {
+		if let Progress::Checkpoint(state) = progress {
+			if let Some((ref file, ref args)) = checkpointing {
+				let checkpoint = Checkpoint { args: args.clone(), quartads: quartads.map.values().sum(), state: state.clone() };
+				if let Err(e) = write_checkpoint(file, &checkpoint) {
+					eprintln!("Warning: {}", e);
 				}
-			};
-			let mut layout_str = String::new();
-			match f.read_to_string(&mut layout_str) {
-				Ok(_) => (),
-				Err(e) => {
-					println!("Error: {}", e);
-					panic!("could not read layout");
+			}
+			return;
+		}
+		if !text {
+			return;
+		}
+		match progress {
+			Progress::Iteration(i) => println!("iteration: {}", i),
+			Progress::Best(layout) => report::print_result(&penalty::calculate_penalty(quartads, layout, model)),
+			Progress::Worker(start, _) => report::print_result(start),
+			Progress::Generation(i, total) => println!("generation {}: {:.2}", i, total),
+			Progress::Round(..) | Progress::Checkpoint(_) => (),
+		}
+	};
+
+	let best_layouts = match resume {
+		None => {
+			run_ref(quartads, corpora, layout.geometry(), model, format)?;
+			if text {
+				println!("seed: {}", optimizer.options().seed);
+				if population.len() > 1 {
+					println!("starting from {} layouts", population.len());
 				}
-			};
-			_layout = layout::Layout::from_string(&layout_str[..]);
-			&_layout
+			}
+			match objectives {
+				Some(objectives) => optimizer.pareto(population, objectives, &progress),
+				None => optimizer.optimize(population, &progress),
+			}
+		},
+		Some(state) => {
+			report::print_header(format, layout.layers().len());
+			if text {
+				println!("resuming iteration {} of {}", state.iteration, optimizer.options().iterations);
+			}
+			optimizer.resume(state, &progress)
 		},
 	};
+	let best_layouts = best_layouts?;
 
-	// Parse options.
-	let debug = matches.opt_present("d");
-	let top   = numopt(matches.opt_str("t"), 1usize);
-	let swaps = numopt(matches.opt_str("s"), 2usize);
+	let kind = if objectives.is_some() { "Front" } else { "Best" };
+	if text {
+		println!("................................................");
+		if let Some(objectives) = objectives {
+			println!(
+				"Pareto front over {}: {} layouts, best weighted sum first",
+				objectives.keys().join(", "), best_layouts.len());
+		}
+		for entry in &best_layouts {
+			if let Some(objectives) = objectives {
+				report::print_objectives(entry, objectives);
+			}
+			report::print_result(entry);
+		}
+	} else {
+		for (i, entry) in best_layouts.iter().enumerate() {
+			report::print_report(kind, &(i + 1).to_string(), entry, format);
+		}
+	}
+	print_breakdown(&best_layouts[0].layout, corpora, model, format)
+}
 
-	match command.as_ref() {
-		"run" => run(&corpus[..], layout, debug, top, swaps),
-		"run-ref" => run_ref(&corpus[..], None),
-		_ => print_usage(progname, opts),
-		//"refine" => ,//refine(&corpus[..], layout, debug, top, swaps),
-	};
+// Writes `checkpoint` to `file` by way of a temporary file, so that a crash
+// leaves the last checkpoint whole.
+fn write_checkpoint(file: &str, checkpoint: &Checkpoint)
+-> Result<(), Error>
+{
+	let tmp = format!("{}.tmp", file);
+	fs::write(&tmp, checkpoint.to_string()).map_err(|e| Error::Io(tmp.clone(), e))?;
+	fs::rename(&tmp, file).map_err(|e| Error::Io(file.to_string(), e))
 }
 
-fn run(s: &str, layout: &layout::Layout, debug: bool, top: usize, swaps: usize)
+// Makes the first Ctrl-C set INTERRUPTED, so that the run can write a
+// checkpoint before it stops. A second one stops keygen at once.
+#[cfg(unix)]
+fn catch_interrupt()
 {
-	let init_pos_map = layout.get_position_map();
-	let quartads = penalty::prepare_quartad_list(s);
-	
-	run_ref(s, Some(&quartads));
-	simulator::simulate(&quartads, layout, debug, top, swaps);
-	
+	extern "C" fn on_interrupt(_: libc::c_int)
+	{
+		INTERRUPTED.store(true, Ordering::SeqCst);
+		unsafe {
+			libc::signal(libc::SIGINT, libc::SIG_DFL);
+		}
+	}
+	unsafe {
+		libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+	}
+}
+
+#[cfg(not(unix))]
+fn catch_interrupt()
+{
+}
+
+// The layout files `args` name: files as they are, and the files of
+// directories in the order of their names.
+fn layout_files(args: &[&String])
+-> Result<Vec<String>, Error>
+{
+	let mut files = Vec::new();
+	for &arg in args {
+		if !Path::new(arg).is_dir() {
+			files.push(arg.clone());
+			continue;
+		}
+		let mut names = Vec::new();
+		for entry in fs::read_dir(arg).map_err(|e| Error::Io(arg.clone(), e))? {
+			let path = entry.map_err(|e| Error::Io(arg.clone(), e))?.path();
+			let hidden = path.file_name().is_none_or(|name| name.to_string_lossy().starts_with('.'));
+			if path.is_file() && !hidden {
+				names.push(path.to_string_lossy().into_owned());
+			}
+		}
+		if names.is_empty() {
+			return Err(Error::Invalid(format!("{}: no layout files in the directory", arg)));
+		}
+		names.sort();
+		files.extend(names);
+	}
+	Ok(files)
+}
+
+fn run_ref(quartads: &QuartadList, corpora: &[Corpus], geometry: &Arc<Geometry>, model: &Model, format: OutputFormat)
+-> Result<(), Error>
+{
+	report::print_header(format, geometry.layers.len());
+
+	// The reference layouts are only written down for the built-in board.
+	if **geometry != Geometry::default() {
+		if format == OutputFormat::Text {
+			println!("No reference layouts for a custom geometry.");
+			println!();
+		}
+		return Ok(());
+	}
+
+	for &(name, l) in layout::REFERENCE_LAYOUTS.iter() {
+		let penalty = penalty::score(quartads, &l.layout(geometry), model)?;
+		report::print_report("Reference", name, &penalty, format);
+		if format == OutputFormat::Text {
+			println!();
+		}
+		print_breakdown(&penalty.layout, corpora, model, format)?;
+	}
+	Ok(())
+}
+
+fn refine(quartads: &QuartadList, corpora: &[Corpus], layout: &layout::Layout, model: &Model, optimizer: &Optimizer<Model>, format: OutputFormat, debug: bool)
+-> Result<(), Error>
+{
+	report::print_header(format, layout.layers().len());
+	let initial = penalty::score(quartads, layout, model)?;
+	report::print_report("Initial layout", "", &initial, format);
+
+	let refined = optimizer.refine(layout, &|progress| {
+		if let Progress::Round(round, from, to) = progress {
+			if debug && format == OutputFormat::Text {
+				println!("round {}: {} -> {}", round, from, to);
+			}
+		}
+	})?;
+
+	if format == OutputFormat::Text {
+		println!("Top layouts:");
+		for entry in &refined.top_layouts {
+			report::print_result(entry);
+		}
+
+		println!();
+		println!("Ultimate winner:");
+		report::print_result(&refined.winner);
+	} else {
+		for (i, entry) in refined.top_layouts.iter().enumerate() {
+			report::print_report("Top layout", &(i + 1).to_string(), entry, format);
+		}
+		report::print_report("Ultimate winner", "", &refined.winner, format);
+	}
+	print_breakdown(&refined.winner.layout, corpora, model, format)
+}
+
+// Tries every arrangement of the keys at `keys` of `layout` that could be among
+// the `top` best.
+fn permute_keys(quartads: &QuartadList, corpora: &[Corpus], layout: &layout::Layout, model: &Model, keys: &[usize], top: usize, format: OutputFormat)
+-> Result<(), Error>
+{
+	report::print_header(format, layout.layers().len());
+	let initial = penalty::score(quartads, layout, model)?;
+	report::print_report("Initial layout", "", &initial, format);
+
+	let permutations = permute::permute(quartads, layout, keys, model, top)?;
+
+	if format == OutputFormat::Text {
+		let keys: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+		println!(
+			"Keys {}: searched {} of {} arrangements",
+			keys.join(", "), permutations.searched, permutations.arrangements);
+		println!();
+		println!("Best arrangements:");
+		for entry in &permutations.best {
+			report::print_result(entry);
+		}
+	} else {
+		for (i, entry) in permutations.best.iter().enumerate() {
+			report::print_report("Arrangement", &(i + 1).to_string(), entry, format);
+		}
+	}
+	print_breakdown(&permutations.best[0].layout, corpora, model, format)
 }
 
-fn run_ref(s: &str,quartads:Option<&QuartadList> )
-{
-	let run_ref_ = |quartads|{  // making typechecker happy
-		
-		let ref_test = |s:&str, l:&layout::Layout|{
-			println!("Reference: {}", s);
-			let init_pos_map = l.get_position_map();
-			let penalty= penalty::calculate_penalty(quartads, &l);
-			simulator::print_result(&penalty);
-			println!("");
-		};
-		ref_test("QWERTY", &layout::QWERTY_LAYOUT);
-		ref_test("DVORAK", &layout::DVORAK_LAYOUT);
-		ref_test("MTGAP", &layout::MTGAP_LAYOUT);
-		ref_test("COLEMAK", &layout::COLEMAK_LAYOUT);
-		ref_test("QGMLWY", &layout::QGMLWY_LAYOUT);
-		ref_test("ARENSITO", &layout::ARENSITO_LAYOUT);
-		ref_test("MALTRON", &layout::MALTRON_LAYOUT);
-		ref_test("RSTHD", &layout::RSTHD);
-		ref_test("CAPEWELL", &layout::CAPEWELL_LAYOUT);
-		ref_test("DABEST", &layout::DABEST);
-		ref_test("THE_ONE", &layout::THE_ONE);
-		ref_test("X1", &layout::X1);
+// Checks the layout given on the command line, or every reference layout
+// without one.
+fn validate_layouts(corpora: &[Corpus], layout_filename: Option<&String>, layout: &layout::Layout)
+-> Result<(), Error>
+{
+	let layouts: Vec<(&str, layout::Layout)> = match layout_filename {
+		Some(name) => vec![(name, layout.clone())],
+		None => layout::REFERENCE_LAYOUTS.iter()
+			.map(|&(name, l)| (name, l.layout(layout.geometry())))
+			.collect(),
 	};
-	
-	match  quartads {
-		Some(quartads) => run_ref_(quartads),
-		None => run_ref_(&penalty::prepare_quartad_list(s)),
+
+	let chars = corpus::blend_chars(corpora);
+	let mut incomplete = Vec::new();
+	for (i, (name, layout)) in layouts.iter().enumerate() {
+		let validation = Validation::new(layout, &chars);
+		if i > 0 {
+			println!();
+		}
+		println!("Layout: {}", name);
+		print!("{}", validation);
+		if !validation.is_complete() {
+			incomplete.push(*name);
+		}
+	}
+
+	if incomplete.is_empty() {
+		Ok(())
+	} else {
+		Err(Error::Invalid(format!("incomplete layout: {}", incomplete.join(", "))))
 	}
-	
-	
 }
 
-fn refine(s: &str, layout: &layout::Layout, debug: bool, top: usize, swaps: usize)
+fn export_layout(layout: &layout::Layout, format: Option<String>, output: Option<String>)
+-> Result<(), Error>
 {
-	let init_pos_map = layout::QWERTY_LAYOUT.get_position_map();
-	let quartads = penalty::prepare_quartad_list(s);
-	let len = s.len();
+	let format = match format {
+		Some(f) => f.parse::<ExportFormat>().map_err(|e| Error::Usage(format!("--to: {}", e)))?,
+		None => return Err(Error::Usage("export needs --to qmk|zmk|xkb|keylayout|klc".to_string())),
+	};
+	let keymap = export::export(layout, format).map_err(Error::Invalid)?;
+	error::write_output(output.as_ref().map(String::as_ref), &keymap)
+}
 
-	//simulator::refine(&quartads, len, layout, &penalties, debug, top, swaps);
+// Shows how `layout` does on each corpus, if there is more than one.
+fn print_breakdown(layout: &layout::Layout, corpora: &[Corpus], model: &Model, format: OutputFormat)
+-> Result<(), Error>
+{
+	if corpora.len() > 1 {
+		report::print_breakdown(layout, corpora, model, format)?;
+	}
+	Ok(())
 }
 
-fn print_usage(progname: &String, opts: Options)
+fn print_usage(progname: &str, opts: Options)
 {
-	let brief = format!("Usage: {} (run|run-ref) <corpus> [OPTIONS]", progname);
+	let brief = format!("Usage: {} (run|run-ref|refine|permute|corpus-stats|validate) <corpus>[:weight] [<corpus>:<weight> ...] [layout] [OPTIONS]\n       {} run <corpus>[:weight] ... [layout|directory ...] [OPTIONS]\n       {} export [layout] --to <format> [OPTIONS]\n       {} resume <checkpoint>", progname, progname, progname, progname);
 	print!("{}", opts.usage(&brief));
 }
 
-fn numopt<T>(s: Option<String>, default: T)
--> T
-where T: std::str::FromStr + std::fmt::Display
+// The value of option `name`, or `default` without one.
+fn parseopt<T>(matches: &Matches, name: &str, default: T)
+-> Result<T, Error>
+where T: std::str::FromStr<Err = String>
 {
-	match s {
-		None => default,
-		Some(num) => match num.parse::<T>() {
-			Ok(n) => n,
-			Err(_) => {
-				println!("Error: invalid option value {}. Using default value {}.", num, default);
-				default
-			},
-		},
+	match matches.opt_str(name) {
+		None => Ok(default),
+		Some(value) => value.parse::<T>().map_err(|e| Error::Usage(format!("{}: {}", optname(name), e))),
 	}
 }
+
+// The number given with option `name`, or `default` without one.
+fn numopt<T>(matches: &Matches, name: &str, default: T)
+-> Result<T, Error>
+where T: std::str::FromStr
+{
+	match matches.opt_str(name) {
+		None => Ok(default),
+		Some(num) => num.parse::<T>().map_err(|_| Error::Usage(format!("{}: invalid number `{}`", optname(name), num))),
+	}
+}
+
+fn optname(name: &str)
+-> String
+{
+	if name.len() == 1 { format!("-{}", name) } else { format!("--{}", name) }
+}
@@ -0,0 +1,286 @@
+//! Tunable parameters of the penalty model.
+//!
+//! A model file is a small subset of TOML:
+//!
+//! ```text
+//! # comment
+//! base = [5.0, 0.5, ...]      # one base penalty per key position
+//!
+//! [weights]
+//! same_finger = 15.0
+//!
+//! [show]
+//! same_finger = true
+//!
+//! [load]
+//! fingers = [8.0, 11.0, 16.0, 20.0, 8.0, 11.0, 16.0, 20.0]
+//! hands = [50.0, 50.0]
+//! ```
+//!
+//! Keys in `[weights]` and `[show]` are the penalty keys listed in
+//! `penalty::PENALTY_DESCRIPTIONS`. Anything left out keeps its built-in value;
+//! `base` defaults to the base penalties of the keyboard geometry and must have
+//! one value per key of it.
+
+use geometry::Geometry;
+use penalty::{FINGER_LOAD_TARGETS, HAND_LOAD_TARGETS, PENALTY_DESCRIPTIONS};
+
+#[derive(Clone)]
+pub struct Model
+{
+	pub base:    Vec<f64>,
+	pub weights: Vec<f64>,
+	pub show:    Vec<bool>,
+
+	// Load targets in percent; see `penalty::FINGER_LOAD_TARGETS`.
+	pub finger_targets: [f64; 8],
+	pub hand_targets:   [f64; 2],
+}
+
+/* ----- *
+ * IMPLS *
+ * ----- */
+
+impl Model
+{
+	// The built-in model for `geometry`.
+	pub fn new(geometry: &Geometry)
+	-> Model
+	{
+		Model {
+			base: geometry.keys.iter().map(|k| k.base).collect(),
+			weights: PENALTY_DESCRIPTIONS.iter().map(|d| d.weight).collect(),
+			show: PENALTY_DESCRIPTIONS.iter().map(|d| d.show).collect(),
+			finger_targets: FINGER_LOAD_TARGETS,
+			hand_targets: HAND_LOAD_TARGETS,
+		}
+	}
+
+	pub fn from_string(s: &str, geometry: &Geometry)
+	-> Result<Model, String>
+	{
+		let mut model = Model::new(geometry);
+		let mut section = String::new();
+		let mut lines = s.lines().enumerate();
+
+		while let Some((i, line)) = lines.next() {
+			let line = strip_comment(line).trim();
+			if line.is_empty() {
+				continue;
+			}
+			if line.starts_with('[') && line.ends_with(']') {
+				section = line[1..line.len() - 1].trim().to_string();
+				continue;
+			}
+
+			let (key, value) = match line.find('=') {
+				Some(eq) => (line[..eq].trim(), line[eq + 1..].trim().to_string()),
+				None => return Err(format!("line {}: expected `key = value`", i + 1)),
+			};
+
+			// Arrays may span several lines.
+			let mut value = value;
+			if value.starts_with('[') {
+				while !value.ends_with(']') {
+					match lines.next() {
+						Some((_, more)) => {
+							value.push(' ');
+							value.push_str(strip_comment(more).trim());
+						},
+						None => return Err(format!("line {}: unterminated array", i + 1)),
+					}
+				}
+			}
+
+			let res = match section.as_ref() {
+				"" => model.set_top_level(key, &value),
+				"weights" => penalty_index(key)
+					.and_then(|idx| parse_f64(&value).map(|w| model.weights[idx] = w)),
+				"show" => penalty_index(key)
+					.and_then(|idx| parse_bool(&value).map(|b| model.show[idx] = b)),
+				"load" => model.set_load(key, &value),
+				_ => Err(format!("unknown section [{}]", section)),
+			};
+			if let Err(e) = res {
+				return Err(format!("line {}: {}", i + 1, e));
+			}
+		}
+
+		Ok(model)
+	}
+
+	fn set_top_level(&mut self, key: &str, value: &str)
+	-> Result<(), String>
+	{
+		match key {
+			"base" => {
+				let values = parse_f64_array(value)?;
+				if values.len() != self.base.len() {
+					return Err(format!("base has {} values, expected {}", values.len(), self.base.len()));
+				}
+				self.base.copy_from_slice(&values);
+				Ok(())
+			},
+			_ => Err(format!("unknown key `{}`", key)),
+		}
+	}
+
+	fn set_load(&mut self, key: &str, value: &str)
+	-> Result<(), String>
+	{
+		let target: &mut [f64] = match key {
+			"fingers" => &mut self.finger_targets,
+			"hands" => &mut self.hand_targets,
+			_ => return Err(format!("unknown key `{}`", key)),
+		};
+		let values = parse_f64_array(value)?;
+		if values.len() != target.len() {
+			return Err(format!("{} has {} values, expected {}", key, values.len(), target.len()));
+		}
+		// The targets are shares of the load, so they cannot be negative and
+		// cannot all be zero.
+		if let Some(v) = values.iter().find(|v| !v.is_finite() || **v < 0.0) {
+			return Err(format!("{} has the target {}, expected a number of at least 0", key, v));
+		}
+		if values.iter().all(|&v| v == 0.0) {
+			return Err(format!("{} has no target above 0", key));
+		}
+		target.copy_from_slice(&values);
+		Ok(())
+	}
+}
+
+/* ------- *
+ * HELPERS *
+ * ------- */
+
+fn strip_comment(line: &str)
+-> &str
+{
+	match line.find('#') {
+		Some(i) => &line[..i],
+		None => line,
+	}
+}
+
+fn penalty_index(key: &str)
+-> Result<usize, String>
+{
+	PENALTY_DESCRIPTIONS.iter()
+		.position(|d| d.key == key)
+		.ok_or_else(|| format!("unknown penalty `{}`", key))
+}
+
+fn parse_f64(s: &str)
+-> Result<f64, String>
+{
+	s.parse::<f64>().map_err(|_| format!("invalid number `{}`", s))
+}
+
+fn parse_bool(s: &str)
+-> Result<bool, String>
+{
+	match s {
+		"true" => Ok(true),
+		"false" => Ok(false),
+		_ => Err(format!("invalid boolean `{}`", s)),
+	}
+}
+
+fn parse_f64_array(s: &str)
+-> Result<Vec<f64>, String>
+{
+	if !s.starts_with('[') || !s.ends_with(']') {
+		return Err(format!("expected an array, found `{}`", s));
+	}
+	s[1..s.len() - 1]
+		.split(',')
+		.map(|v| v.trim())
+		.filter(|v| !v.is_empty())
+		.map(parse_f64)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+	use geometry::Geometry;
+	use super::*;
+
+	#[test]
+	fn weights_and_show()
+	{
+		let geometry = Geometry::default();
+		let file = "# a model\n[weights]\nsame_finger = 20.5  # more than usual\nroll_out = -1\n\n[show]\nroll_out = false\ntwist = true\n";
+		let model = Model::from_string(file, &geometry).expect("could not read the model");
+		let builtin = Model::new(&geometry);
+		for (i, desc) in PENALTY_DESCRIPTIONS.iter().enumerate() {
+			match desc.key {
+				"same_finger" => assert_eq!(model.weights[i], 20.5),
+				"roll_out" => {
+					assert_eq!(model.weights[i], -1.0);
+					assert!(!model.show[i]);
+				},
+				"twist" => assert!(model.show[i]),
+				_ => {
+					assert_eq!(model.weights[i], builtin.weights[i], "{}", desc.key);
+					assert_eq!(model.show[i], builtin.show[i], "{}", desc.key);
+				},
+			}
+		}
+		assert_eq!(model.base, builtin.base);
+	}
+
+	#[test]
+	fn base()
+	{
+		let geometry = Geometry::default();
+		let values: Vec<String> = (0..geometry.keys.len()).map(|i| format!("{}.5", i)).collect();
+		let file = format!("base = [\n  {},  # the left half\n  {}\n]\n",
+			values[..10].join(", "), values[10..].join(", "));
+		let model = Model::from_string(&file, &geometry).expect("could not read the model");
+		assert_eq!(model.base, (0..geometry.keys.len()).map(|i| i as f64 + 0.5).collect::<Vec<f64>>());
+		assert_eq!(model.weights, Model::new(&geometry).weights);
+
+		let err = Model::from_string("\nbase = [1.0, 2.0]\n", &geometry).err().expect("a short base was read");
+		assert_eq!(err, format!("line 2: base has 2 values, expected {}", geometry.keys.len()));
+	}
+
+	#[test]
+	fn errors()
+	{
+		let geometry = Geometry::default();
+		for &(file, message) in &[
+			("colour = 1\n", "line 1: unknown key `colour`"),
+			("same_finger\n", "line 1: expected `key = value`"),
+			("base = [1.0,\n2.0\n", "line 1: unterminated array"),
+			("base = 1.0\n", "line 1: expected an array, found `1.0`"),
+			("[weights]\nsame_fingers = 1\n", "line 2: unknown penalty `same_fingers`"),
+			("[weights]\n\nsame_finger = lots\n", "line 3: invalid number `lots`"),
+			("[show]\nsame_finger = yes\n", "line 2: invalid boolean `yes`"),
+			("[load]\nfeet = [1, 2]\n", "line 2: unknown key `feet`"),
+			("[colours]\nred = 1\n", "line 2: unknown section [colours]"),
+		] {
+			let err = Model::from_string(file, &geometry).err().expect("a bad model was read");
+			assert_eq!(err, message, "{:?}", file);
+		}
+	}
+
+	#[test]
+	fn load_targets()
+	{
+		let geometry = Geometry::default();
+		let model = Model::from_string("[load]\nhands = [60, 40]\n", &geometry).expect("could not read the model");
+		assert_eq!(model.hand_targets, [60.0, 40.0]);
+
+		for &(file, message) in &[
+			("[load]\nhands = [0, 0]\n", "line 2: hands has no target above 0"),
+			("[load]\nhands = [50, -50]\n", "line 2: hands has the target -50"),
+			("[load]\nfingers = [8, 11, 16, 20, 8, 11, 16, nan]\n", "line 2: fingers has the target NaN"),
+			("[load]\nfingers = [0, 0, 0, 0, 0, 0, 0, 0]\n", "line 2: fingers has no target above 0"),
+		] {
+			let err = Model::from_string(file, &geometry).err().expect("bad targets were read");
+			assert!(err.starts_with(message), "{}", err);
+		}
+	}
+}
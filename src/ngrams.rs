@@ -0,0 +1,264 @@
+//! N-gram frequency tables, and the file format they are shared in.
+//!
+//! `keygen corpus-stats` writes the monogram to quadgram counts of a corpus,
+//! so that the other commands can score layouts without the text itself:
+//!
+//! ```text
+//! # keygen n-grams
+//! 1 21733 e
+//! ...
+//! 4 1291 \sthe
+//! ```
+//!
+//! Each line holds the order of the n-gram, its count and the n-gram itself.
+//! Backslashes, spaces, tabs, newlines and carriage returns are written as
+//! `\\`, `\s`, `\t`, `\n` and `\r`, any other control or whitespace character
+//! as `\u{<hex>}`. The first line marks the file; other lines starting with
+//! `#` are comments.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use corpus;
+use penalty::QuartadList;
+
+pub const HEADER: &str = "# keygen n-grams";
+
+#[derive(Clone)]
+pub struct NgramTable
+{
+	// Counts of the n-grams of order n + 1.
+	counts: Vec<HashMap<String, i64>>,
+}
+
+impl NgramTable
+{
+	// Counts every monogram to quadgram of `text`.
+	pub fn from_text(text: &str)
+	-> NgramTable
+	{
+		let mut counts = vec![HashMap::new(); 4];
+		let mut window: Vec<char> = Vec::with_capacity(4);
+		for c in text.chars() {
+			if window.len() == 4 {
+				window.remove(0);
+			}
+			window.push(c);
+			for (n, grams) in counts.iter_mut().enumerate().take(window.len()) {
+				let gram: String = window[window.len() - n - 1..].iter().collect();
+				*grams.entry(gram).or_insert(0) += 1;
+			}
+		}
+		NgramTable { counts }
+	}
+
+	// Whether `s` is an n-gram file rather than corpus text.
+	pub fn is_ngram_file(s: &str)
+	-> bool
+	{
+		s.starts_with(HEADER)
+	}
+
+	pub fn from_string(s: &str)
+	-> Result<NgramTable, String>
+	{
+		if !NgramTable::is_ngram_file(s) {
+			return Err(format!("not an n-gram file (expected `{}` on the first line)", HEADER));
+		}
+
+		let mut counts = vec![HashMap::new(); 4];
+		for (i, line) in s.lines().enumerate() {
+			if line.starts_with('#') || line.trim().is_empty() {
+				continue;
+			}
+			let fields: Vec<&str> = line.split_whitespace().collect();
+			let (n, count, gram) = match fields[..] {
+				[n, count, gram] => (n, count, gram),
+				_ => return Err(format!("line {}: expected `<order> <count> <n-gram>`", i + 1)),
+			};
+			let n = match n.parse::<usize>() {
+				Ok(n) if (1..=4).contains(&n) => n,
+				_ => return Err(format!("line {}: invalid order `{}` (expected 1-4)", i + 1, n)),
+			};
+			let count = match count.parse::<i64>() {
+				Ok(c) if c >= 0 => c,
+				_ => return Err(format!("line {}: invalid count `{}`", i + 1, count)),
+			};
+			let gram = unescape(gram).map_err(|e| format!("line {}: {}", i + 1, e))?;
+			if gram.chars().count() != n {
+				return Err(format!("line {}: `{}` is not an n-gram of order {}", i + 1, escape(&gram), n));
+			}
+			*counts[n - 1].entry(gram).or_insert(0) += count;
+		}
+
+		Ok(NgramTable { counts })
+	}
+
+	// The tables of all of `tables`, weighted as `corpus::blend_counts` does.
+	pub fn blend(tables: &[(NgramTable, f64)])
+	-> NgramTable
+	{
+		let counts = (0..4)
+			.map(|n| {
+				let maps: Vec<(&HashMap<String, i64>, f64)> = tables.iter()
+					.map(|&(ref table, weight)| (&table.counts[n], weight))
+					.collect();
+				corpus::blend_counts(&maps)
+			})
+			.collect();
+		NgramTable { counts }
+	}
+
+	// The count of each character, from the monograms.
+	pub fn chars(&self)
+	-> HashMap<char, i64>
+	{
+		self.counts[0].iter()
+			.filter_map(|(gram, &count)| gram.chars().next().map(|c| (c, count)))
+			.collect()
+	}
+
+	pub fn quartads(&self)
+	-> QuartadList
+	{
+		let map = self.counts[3].iter()
+			.map(|(gram, &count)| {
+				let mut chars = ['\0'; 4];
+				for (key, c) in chars.iter_mut().zip(gram.chars()) {
+					*key = c;
+				}
+				(chars, count)
+			})
+			.collect();
+		QuartadList { map }
+	}
+}
+
+impl fmt::Display for NgramTable
+{
+	// Writes the table in the n-gram file format, most frequent first.
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		writeln!(f, "{}", HEADER)?;
+		for (n, grams) in self.counts.iter().enumerate() {
+			let mut sorted: Vec<(&String, &i64)> = grams.iter().collect();
+			sorted.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+			for (gram, count) in sorted {
+				writeln!(f, "{} {} {}", n + 1, count, escape(gram))?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/* ------- *
+ * HELPERS *
+ * ------- */
+
+// Escapes `s` as n-gram files do, so that it has no whitespace.
+pub fn escape(s: &str)
+-> String
+{
+	let mut out = String::new();
+	for c in s.chars() {
+		match c {
+			'\\' => out.push_str("\\\\"),
+			' ' => out.push_str("\\s"),
+			'\t' => out.push_str("\\t"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			c if c.is_control() || c.is_whitespace() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+			c => out.push(c),
+		}
+	}
+	out
+}
+
+// Takes back `escape`.
+pub fn unescape(s: &str)
+-> Result<String, String>
+{
+	let mut out = String::new();
+	let mut chars = s.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			out.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('\\') => out.push('\\'),
+			Some('s') => out.push(' '),
+			Some('t') => out.push('\t'),
+			Some('n') => out.push('\n'),
+			Some('r') => out.push('\r'),
+			Some('u') => {
+				let rest = chars.as_str();
+				let end = match rest.find('}') {
+					Some(end) if rest.starts_with('{') => end,
+					_ => return Err(format!("invalid escape in `{}`", s)),
+				};
+				let c = u32::from_str_radix(&rest[1..end], 16).ok().and_then(std::char::from_u32);
+				match c {
+					Some(c) => out.push(c),
+					None => return Err(format!("invalid escape in `{}`", s)),
+				}
+				chars = rest[end + 1..].chars();
+			},
+			_ => return Err(format!("invalid escape in `{}`", s)),
+		}
+	}
+	Ok(out)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use penalty;
+
+	#[test]
+	fn escapes()
+	{
+		let s = "a\\b c\td\ne\rf\u{0}g\u{a0}h\u{2028}éß😀";
+		let escaped = escape(s);
+		assert_eq!(escaped, "a\\\\b\\sc\\td\\ne\\rf\\u{0}g\\u{a0}h\\u{2028}éß😀");
+		assert!(!escaped.contains(char::is_whitespace));
+		assert_eq!(unescape(&escaped).unwrap(), s);
+
+		for bad in &["\\x", "\\", "\\u", "\\u12", "\\u{12", "\\u{zz}", "\\u{110000}", "\\u{d800}"] {
+			assert_eq!(unescape(bad), Err(format!("invalid escape in `{}`", bad)));
+		}
+	}
+
+	#[test]
+	fn file_round_trip()
+	{
+		let text = "the cat\tsat on\r\nthe mat\u{a0}— \\o/ 😀 the end\n";
+		let table = NgramTable::from_text(text);
+		let file = table.to_string();
+		assert!(file.starts_with("# keygen n-grams\n"));
+		assert!(file.contains("\n4 3 the\\s\n"), "{}", file);
+
+		let read = NgramTable::from_string(&file).expect("could not read the table");
+		assert_eq!(read.to_string(), file);
+		assert_eq!(read.quartads().map, penalty::prepare_quartad_list(text).map);
+		assert_eq!(read.chars()[&'t'], 6);
+		assert_eq!(read.chars()[&'😀'], 1);
+	}
+
+	#[test]
+	fn file_errors()
+	{
+		for &(file, message) in &[
+			("1 2 a\n", "not an n-gram file (expected `# keygen n-grams` on the first line)"),
+			("# keygen n-grams\n1 2\n", "line 2: expected `<order> <count> <n-gram>`"),
+			("# keygen n-grams\n5 2 abcde\n", "line 2: invalid order `5` (expected 1-4)"),
+			("# keygen n-grams\n1 -2 a\n", "line 2: invalid count `-2`"),
+			("# keygen n-grams\n# comment\n2 2 a\\s\\s\n", "line 3: `a\\s\\s` is not an n-gram of order 2"),
+			("# keygen n-grams\n1 1 \\q\n", "line 2: invalid escape in `\\q`"),
+		] {
+			assert_eq!(NgramTable::from_string(file).err().as_deref(), Some(message), "{:?}", file);
+		}
+	}
+}
@@ -0,0 +1,195 @@
+//! Several objectives at once, for `Optimizer::pareto`.
+//!
+//! The objectives are a few penalty categories of the model, such as
+//! `same_finger`, and always the finger load, each measured by its total in
+//! the penalty breakdown. One layout dominates another if it is no worse on
+//! any objective and better on at least one; the Pareto front is the layouts
+//! that no other dominates, and so the trade-offs between the objectives
+//! worth looking at.
+//!
+//! Each objective has a weight as well, 1 unless given. The weighted sum of a
+//! layout is its total with the penalty of each objective multiplied by the
+//! objective's weight: the total of the model with those weights scaled, which
+//! `Objectives::model` gives. It orders the front, best first, and is what to
+//! anneal on for a single answer instead of a front.
+
+use std::str::FromStr;
+
+use model::Model;
+use penalty::{BestLayoutsEntry, FINGER_LOAD, PENALTY_DESCRIPTIONS};
+
+// The penalty categories to compare layouts on, as indices into
+// `PENALTY_DESCRIPTIONS`, and their weights.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Objectives(Vec<(usize, f64)>);
+
+// The layouts no other layout added dominates, and their objectives.
+pub struct Front
+{
+	objectives: Objectives,
+	members:    Vec<(BestLayoutsEntry, Vec<f64>)>,
+}
+
+impl FromStr for Objectives
+{
+	type Err = String;
+
+	// Reads a comma-separated list of penalty keys, each with an optional
+	// weight after a colon, e.g. `same_finger:2,roll_reversal`.
+	fn from_str(s: &str)
+	-> Result<Objectives, String>
+	{
+		let mut objectives: Vec<(usize, f64)> = Vec::new();
+		for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+			let (key, weight) = match item.find(':') {
+				Some(colon) => {
+					let weight = &item[colon + 1..];
+					match weight.parse::<f64>() {
+						Ok(w) if w.is_finite() && w > 0.0 => (&item[..colon], w),
+						_ => return Err(format!("invalid weight `{}` (expected a number above 0)", weight)),
+					}
+				},
+				None => (item, 1.0),
+			};
+			let index = match PENALTY_DESCRIPTIONS.iter().position(|d| d.key == key) {
+				Some(index) => index,
+				None => return Err(format!("unknown penalty `{}`", key)),
+			};
+			if objectives.iter().any(|&(i, _)| i == index) {
+				return Err(format!("`{}` is given twice", key));
+			}
+			objectives.push((index, weight));
+		}
+		if !objectives.iter().any(|&(i, _)| i == FINGER_LOAD) {
+			objectives.push((FINGER_LOAD, 1.0));
+		}
+		Ok(Objectives(objectives))
+	}
+}
+
+impl Objectives
+{
+	// The keys of the objectives, in order.
+	pub fn keys(&self)
+	-> Vec<&'static str>
+	{
+		self.0.iter().map(|&(i, _)| PENALTY_DESCRIPTIONS[i].key).collect()
+	}
+
+	// The penalty of `entry` on each objective.
+	pub fn values(&self, entry: &BestLayoutsEntry)
+	-> Vec<f64>
+	{
+		self.0.iter().map(|&(i, _)| entry.penalty.penalties[i].total).collect()
+	}
+
+	// The total of `entry` with the penalty of each objective multiplied by its
+	// weight.
+	pub fn weighted_sum(&self, entry: &BestLayoutsEntry)
+	-> f64
+	{
+		let penalties = &entry.penalty.penalties;
+		self.0.iter().fold(entry.penalty.total, |sum, &(i, w)| sum + (w - 1.0) * penalties[i].total)
+	}
+
+	// `model` with the weight of each objective multiplied by its weight here
+	// and by `scales`, one per objective.
+	pub fn model(&self, model: &Model, scales: &[f64])
+	-> Model
+	{
+		let mut model = model.clone();
+		for (&(i, w), scale) in self.0.iter().zip(scales) {
+			model.weights[i] *= w * scale;
+		}
+		model
+	}
+}
+
+impl Front
+{
+	pub fn new(objectives: &Objectives)
+	-> Front
+	{
+		Front { objectives: objectives.clone(), members: Vec::new() }
+	}
+
+	// Adds `entry` unless a member dominates it or is as good on every
+	// objective, and drops the members it dominates. Returns whether it was
+	// added.
+	pub fn insert(&mut self, entry: BestLayoutsEntry)
+	-> bool
+	{
+		let values = self.objectives.values(&entry);
+		if self.members.iter().any(|(_, v)| *v == values || dominates(v, &values)) {
+			return false;
+		}
+		self.members.retain(|(_, v)| !dominates(&values, v));
+		self.members.push((entry, values));
+		true
+	}
+
+	pub fn len(&self)
+	-> usize
+	{
+		self.members.len()
+	}
+
+	pub fn is_empty(&self)
+	-> bool
+	{
+		self.members.is_empty()
+	}
+
+	// The members, best weighted sum first.
+	pub fn members(&self)
+	-> Vec<&BestLayoutsEntry>
+	{
+		let mut members: Vec<(&BestLayoutsEntry, f64)> = self.members.iter()
+			.map(|(entry, _)| (entry, self.objectives.weighted_sum(entry)))
+			.collect();
+		members.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(::std::cmp::Ordering::Equal));
+		members.into_iter().map(|(entry, _)| entry).collect()
+	}
+}
+
+// Whether `a` is no worse than `b` on every objective and better on one.
+pub fn dominates(a: &[f64], b: &[f64])
+-> bool
+{
+	a.iter().zip(b).all(|(x, y)| x <= y) && a.iter().zip(b).any(|(x, y)| x < y)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn objectives()
+	{
+		let objectives: Objectives = "same_finger:2, roll_reversal".parse().expect("could not read the objectives");
+		assert_eq!(objectives, Objectives(vec![(1, 2.0), (6, 1.0), (FINGER_LOAD, 1.0)]));
+		assert_eq!(objectives.keys(), vec!["same_finger", "roll_reversal", "finger_load"]);
+		assert_eq!(PENALTY_DESCRIPTIONS[FINGER_LOAD].key, "finger_load");
+		let objectives: Objectives = "finger_load:3".parse().expect("could not read the objectives");
+		assert_eq!(objectives, Objectives(vec![(FINGER_LOAD, 3.0)]));
+
+		for &(s, message) in &[
+			("same_fingers", "unknown penalty `same_fingers`"),
+			("same_finger:0", "invalid weight `0` (expected a number above 0)"),
+			("same_finger:x", "invalid weight `x` (expected a number above 0)"),
+			("twist,twist:2", "`twist` is given twice"),
+		] {
+			assert_eq!(s.parse::<Objectives>().err().as_deref(), Some(message), "{}", s);
+		}
+	}
+
+	#[test]
+	fn domination()
+	{
+		assert!(dominates(&[1.0, 2.0], &[1.0, 3.0]));
+		assert!(!dominates(&[1.0, 2.0], &[1.0, 2.0]));
+		assert!(!dominates(&[1.0, 4.0], &[2.0, 3.0]));
+		assert!(!dominates(&[2.0, 3.0], &[1.0, 4.0]));
+	}
+}
@@ -1,17 +1,20 @@
+//! Methods for calculating the penalty of a keyboard layout given an input
+//! corpus string.
+
 use std;
 use std::collections::HashMap;
 use std::fmt;
-use std::ops::Range;
-/// Methods for calculating the penalty of a keyboard layout given an input
-/// corpus string.
-//use layout;
 use std::vec::Vec;
 
+use error::Error;
 use layout::*;
+use model::Model;
 
 pub struct KeyPenaltyDescription {
-    name: &'static str,
-    show: bool,
+    pub name: &'static str,
+    pub key: &'static str,
+    pub weight: f64,
+    pub show: bool,
 }
 
 #[derive(Clone)]
@@ -26,26 +29,37 @@ pub struct Penalty {
     pub penalties: Vec<KeyPenalty>,
     pub fingers: [i64; 8],
     pub hands: [i64; 2],
+    // Every keystroke, layer keys and thumbs included. The finger and hand
+    // loads are shares of these.
+    pub keystrokes: i64,
     pub total: f64,
     pub len: i64,
+    // Presses of layer keys, and the part of `total` they and same hand
+    // chords add.
+    pub layer_times: f64,
+    pub layer_total: f64,
 }
 impl Penalty {
-    pub fn new() -> Penalty {
-        let mut penalties = Vec::new();
-        for desc in PenaltyDescriptions.into_iter() {
-            penalties.push(KeyPenalty {
+    pub fn new(model: &Model) -> Penalty {
+        let penalties = PENALTY_DESCRIPTIONS
+            .iter()
+            .zip(&model.show)
+            .map(|(desc, &show)| KeyPenalty {
                 name: desc.name,
-                show: desc.show,
+                show,
                 total: 0.0,
                 times: 0.0,
-            });
-        }
+            })
+            .collect();
         Penalty {
-            penalties: penalties,
+            penalties,
             fingers: [0; 8],
             hands: [0; 2],
+            keystrokes: 0,
             total: 0.0,
             len : 0,
+            layer_times: 0.0,
+            layer_total: 0.0,
         }
     }
 }
@@ -70,11 +84,12 @@ impl PartialEq for BestLayoutsEntry {
 impl Eq for BestLayoutsEntry {}
 impl PartialOrd for BestLayoutsEntry {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.penalty.total.partial_cmp(&other.penalty.total)
+        Some(self.cmp(other))
     }
 }
-pub struct QuartadList<'a> {
-    pub map: HashMap<&'a str, i64>,
+// How often each run of four characters occurs in a corpus.
+pub struct QuartadList {
+    pub map: HashMap<[char; 4], i64>,
 }
 impl fmt::Display for KeyPenalty {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -82,368 +97,595 @@ impl fmt::Display for KeyPenalty {
     }
 }
 
-static BASE_PENALTY: KeyMap<f64> = [
+// Base penalty of each key of the default geometry.
+pub static BASE_PENALTY: KeyMap<f64> = [
     5.0, 0.5, 0.5, 1.5, 2.5, 2.5, 1.5, 0.5, 0.5, 5.0, 5.0, 2.5, 0.0, 0.0, 0.0, 1.0, 1.5, 0.0, 0.0,
     0.0, 1.5, 5.0, 20.0, 2.0, 1.5, 1.0, 5.0, 5.0, 1.0, 1.5, 2.0, 20.0, 0.0, 0.0,
 ];
 
-static PenaltyDescriptions: [KeyPenaltyDescription; 15] = [
-    // Base penalty.
+// Indices into `PENALTY_DESCRIPTIONS` of the penalties that are scored apart
+// from the rest, and that other modules refer to.
+pub const FINGER_LOAD: usize = 15;
+pub const HAND_LOAD: usize = 16;
+pub const SAME_HAND_CHORD: usize = 17;
+pub const LAYER_KEY: usize = 18;
+
+pub static PENALTY_DESCRIPTIONS: [KeyPenaltyDescription; 19] = [
+    // 0: Base penalty, scaled by the base penalty of the key position.
     KeyPenaltyDescription {
         name: "Base",
+        key: "base",
+        weight: 0.2,
         show: true,
     },
-    // Penalise 5 points for using the same finger twice on different keys.
-    // An extra 5 points for using the centre column.
+    // 1: Using the same finger twice on different keys.
     KeyPenaltyDescription {
         name: "Same finger",
+        key: "same_finger",
+        weight: 15.0,
         show: true,
     },
-    // Penalise 1 point for jumping from top to bottom row or from bottom to
-    // top row on the same hand.
+    // 2: Jumping from top to bottom row or from bottom to top row on the same
+    // hand.
     KeyPenaltyDescription {
         name: "Long jump hand",
+        key: "long_jump_hand",
+        weight: 5.0,
         show: false,
     },
-    // Penalise 10 points for jumping from top to bottom row or from bottom to
-    // top row on the same finger.
+    // 3: Jumping from top to bottom row or from bottom to top row on the same
+    // finger.
     KeyPenaltyDescription {
         name: "Long jump",
+        key: "long_jump",
+        weight: 20.0,
         show: false,
     },
-    // Penalise 5 points for jumping from top to bottom row or from bottom to
-    // top row on consecutive fingers, except for middle finger-top row ->
-    // index finger-bottom row.
+    // 4: Jumping from top to bottom row or from bottom to top row on
+    // consecutive fingers, except for middle finger-top row -> index
+    // finger-bottom row.
     KeyPenaltyDescription {
         name: "Long jump consecutive",
+        key: "long_jump_consecutive",
+        weight: 5.0,
         show: false,
     },
-    // Penalise 10 points for awkward pinky/ring combination where the pinky
-    // reaches above the ring finger, e.g. QA/AQ, PL/LP, ZX/XZ, ;./.; on Qwerty.
+    // 5: Awkward pinky/ring combination where the pinky reaches above the ring
+    // finger, e.g. QA/AQ, PL/LP, ZX/XZ, ;./.; on Qwerty.
     KeyPenaltyDescription {
         name: "Rinky/ring twist",
+        key: "pinky_ring_twist",
+        weight: 10.0,
         show: false,
     },
-    // Penalise 20 points for reversing a roll at the end of the hand, i.e.
-    // using the ring, pinky, then middle finger of the same hand, or the
-    // middle, pinky, then ring of the same hand.
+    // 6: Reversing a roll at the end of the hand, i.e. using the ring, pinky,
+    // then middle finger of the same hand, or the middle, pinky, then ring of
+    // the same hand.
     KeyPenaltyDescription {
         name: "Roll reversal",
+        key: "roll_reversal",
+        weight: 10.0,
         show: false,
     },
-    //7
+    // 7: Rolling outwards while jumping from top to bottom row or from bottom
+    // to top row.
     KeyPenaltyDescription {
         name: "Long roll out",
+        key: "long_roll_out",
+        weight: 10.5,
         show: false,
     },
+    // 8: Switching hands. Negative, so it is an award.
     KeyPenaltyDescription {
         name: "Alternation",
+        key: "alternation",
+        weight: -0.1,
         show: true,
     },
-    // Penalise 0.125 points for rolling outwards.
+    // 9: Rolling outwards.
     KeyPenaltyDescription {
         name: "Roll out",
+        key: "roll_out",
+        weight: 1.0,
         show: true,
     },
-    // Award 0.125 points for rolling inwards.
+    // 10: Rolling inwards. Negative, so it is an award.
     KeyPenaltyDescription {
         name: "Roll in",
+        key: "roll_in",
+        weight: -0.5,
         show: true,
     },
-    // Penalise 3 points for jumping from top to bottom row or from bottom to
-    // top row on the same finger with a keystroke in between.
+    // 11: Jumping from top to bottom row or from bottom to top row on the same
+    // finger with a keystroke in between.
     KeyPenaltyDescription {
         name: "long jump sandwich",
+        key: "long_jump_sandwich",
+        weight: 3.0,
         show: false,
     },
-    // Penalise 10 points for three consecutive keystrokes going up or down the
-    // three rows of the keyboard in a roll.
+    // 12: Three consecutive keystrokes going up or down the three rows of the
+    // keyboard in a roll.
     KeyPenaltyDescription {
         name: "twist",
+        key: "twist",
+        weight: 5.0,
         show: false,
     },
-    //13
+    // 13: Four keystrokes in a row on the same hand.
     KeyPenaltyDescription {
         name: "4 times no alternation",
+        key: "no_alternation_4",
+        weight: 1.2,
         show: false,
     },
-    //14
+    // 14: Four keystrokes in a row alternating hands.
     KeyPenaltyDescription {
         name: "4 alternations in a row",
+        key: "alternation_4",
+        weight: 0.01,
+        show: false,
+    },
+    // 15: Keystrokes on a finger beyond its target share of the load.
+    KeyPenaltyDescription {
+        name: "Finger load",
+        key: "finger_load",
+        weight: 5.0,
+        show: true,
+    },
+    // 16: Keystrokes by which the left/right split misses its target.
+    KeyPenaltyDescription {
+        name: "Hand load",
+        key: "hand_load",
+        weight: 5.0,
+        show: true,
+    },
+    // 17: Holding a layer key with the hand that types the key it modifies.
+    KeyPenaltyDescription {
+        name: "Same hand chord",
+        key: "same_hand_chord",
+        weight: 5.0,
+        show: false,
+    },
+    // 18: Pressing a layer key, scaled by one plus the base penalty of its
+    // position, for the finger travelling there and back. Takes the place of
+    // the base penalty for layer keys.
+    KeyPenaltyDescription {
+        name: "Layer key",
+        key: "layer_key",
+        weight: 0.5,
         show: false,
     },
 ];
 
-pub fn prepare_quartad_list<'a>(
-    string: &'a str
-) -> QuartadList<'a> {
-    let mut quartads: HashMap<&str, i64> = HashMap::new();
+// Maximum share of all keystrokes, in percent, for each finger in the same
+// order as `Penalty.fingers`: left pinky to index, then right pinky to index.
+pub static FINGER_LOAD_TARGETS: [f64; 8] = [8.0, 11.0, 16.0, 20.0, 8.0, 11.0, 16.0, 20.0];
 
-    for i in 0..string.chars().count() - 4 {
-        let slice = &string[i..i + 4];
-        if slice
-            .chars()
-            .all(|c| (c as i32) <= 128)
-        {
-            let entry = quartads.entry(slice).or_insert(0);
-            *entry += 1;
+// Target share of the left and right hand, in percent.
+pub static HAND_LOAD_TARGETS: [f64; 2] = [50.0, 50.0];
+
+pub fn prepare_quartad_list(string: &str) -> QuartadList {
+    let mut quartads: HashMap<[char; 4], i64> = HashMap::new();
+
+    // Each quartad is four Unicode scalar values, regardless of how many
+    // bytes they take.
+    let mut quartad = ['\0'; 4];
+    for (i, c) in string.chars().enumerate() {
+        quartad = [quartad[1], quartad[2], quartad[3], c];
+        if i >= 3 {
+            *quartads.entry(quartad).or_insert(0) += 1;
         }
     }
     QuartadList { map: quartads }
 }
 
-pub fn calculate_penalty<'a>(quartads: &QuartadList<'a>, layout: &Layout) -> BestLayoutsEntry {
-    let mut result = Penalty::new();
+// Scores `layout` like `calculate_penalty`, once it is clear that there is
+// something to score and that `model` is for the geometry of `layout`.
+pub fn score(quartads: &QuartadList, layout: &Layout, model: &Model) -> Result<BestLayoutsEntry, Error> {
+    if quartads.map.is_empty() {
+        return Err(Error::Invalid(
+            "nothing to score: the corpus has no quartads left".to_string(),
+        ));
+    }
+    if model.base.len() != layout.geometry().len() {
+        return Err(Error::Invalid(format!(
+            "the model has base penalties for {} keys, the geometry has {}",
+            model.base.len(),
+            layout.geometry().len()
+        )));
+    }
+    Ok(calculate_penalty(quartads, layout, model))
+}
+
+pub fn calculate_penalty(quartads: &QuartadList, layout: &Layout, model: &Model) -> BestLayoutsEntry {
+    let mut result = Penalty::new(model);
     let position_map = layout.get_position_map();
 
-    for (string, count) in &quartads.map {
-        let mut chars = string.chars().into_iter();
-
-        let old3 = chars
-            .next()
-            .map(|c| position_map.get_key_position(c))
-            .unwrap_or(&KP_NONE);
-
-        let old2 = chars
-            .next()
-            .map(|c| position_map.get_key_position(c))
-            .unwrap_or(&KP_NONE);
-
-        let old1 = chars
-            .next()
-            .map(|c| position_map.get_key_position(c))
-            .unwrap_or(&KP_NONE);
-
-        let curr = match chars.next() {
-            Some(c) => match position_map.get_key_position(c) {
-                &Some(ref kp) => kp,
-                &None => continue,
-            },
-            None => panic!("unreachable"),
-        };
-        result.len += count;
-
-        let useFinger = |result: &mut Penalty, i: usize| match curr.finger {
-            Finger::Pinky => result.fingers[i] += count,
-            Finger::Ring => result.fingers[i + 1] += count,
-            Finger::Middle => result.fingers[i + 2] += count,
-            Finger::Index => result.fingers[i + 3] += count,
-            Finger::Thumb => {}
+    // Sum in a fixed order so that runs with the same seed give the same
+    // totals, down to the last bit.
+    let mut sorted: Vec<(&[char; 4], &i64)> = quartads.map.iter().collect();
+    sorted.sort_unstable();
+
+    for (chars, count) in sorted {
+        score_quartad(&mut result, &position_map, chars, *count, model);
+    }
+    log_load_penalties(&mut result, model);
+
+    BestLayoutsEntry {
+        layout: layout.clone(),
+        penalty: result,
+    }
+}
+
+// The keystrokes it takes to type a few characters, layer keys included.
+pub struct Strokes {
+    keys: [Option<KeyPress>; 8],
+    layer_key: [bool; 8],
+    len: usize,
+    // Where the keystrokes of the last character start.
+    last: usize,
+    // The layer key held down while typing the last character.
+    held: Option<KeyPress>,
+}
+
+impl Strokes {
+    // Expands `chars` into keystrokes on `position_map`. A character on a held
+    // layer only needs the layer key if the character before it was typed
+    // with another key held, or none; a one-shot layer key is tapped every
+    // time.
+    pub fn new(position_map: &LayoutPosMap, chars: &[char]) -> Strokes {
+        let mut strokes = Strokes {
+            keys: [None; 8],
+            layer_key: [false; 8],
+            len: 0,
+            last: 0,
+            held: None,
         };
-        match curr.hand {
-            Hand::Left => {
-                result.hands[0] += count;
-                useFinger(&mut result, 0);
+        let mut prev_held: Option<usize> = None;
+        for (i, &c) in chars.iter().enumerate() {
+            if i + 1 == chars.len() {
+                strokes.last = strokes.len;
             }
-            Hand::Right => {
-                result.hands[1] += count;
-                useFinger(&mut result, 4);
+            let kp = *position_map.get_key_position(c);
+            let mut held = None;
+            if let Some(ref kp) = kp {
+                let (activation, layer_key) = position_map.layer_key(kp);
+                let press = match activation {
+                    Activation::None => false,
+                    Activation::Hold(_) | Activation::Shift(..) => {
+                        held = *layer_key;
+                        held.map(|k| k.pos) != prev_held
+                    }
+                    Activation::OneShot(_) => true,
+                };
+                if press {
+                    strokes.layer_key[strokes.len] = true;
+                    strokes.push(*layer_key);
+                }
             }
-            _ => {}
+            strokes.push(kp);
+            prev_held = held.map(|k| k.pos);
+            strokes.held = held;
         }
-        let mut log = |i: usize, penalty: f64| {
-            let p = penalty * *count as f64;
-            //println!("{}; {}", i, penalty);
-            result.penalties[i].times += *count as f64;
-            result.penalties[i].total += p;
-            result.total += p;
-        };
+        strokes
+    }
 
-        let count = *count as f64;
-        // 0: Base penalty.
-        log(0, BASE_PENALTY[curr.pos] / 5.0);
+    fn push(&mut self, kp: Option<KeyPress>) {
+        self.keys[self.len] = kp;
+        self.len += 1;
+    }
 
-        let old1 = match *old1 {
-            Some(ref o) => o,
-            None => continue,
-        };
+    // The keystrokes of the last character, or nothing if it is not on the
+    // layout.
+    pub fn last(&self) -> &[Option<KeyPress>] {
+        match self.keys[self.len - 1] {
+            Some(_) => &self.keys[self.last..self.len],
+            None => &[],
+        }
+    }
+}
 
-        if curr.hand == old1.hand && curr.hand != Hand::Thumb {
-            // 1: Same finger.
-            if curr.finger == old1.finger && curr.pos != old1.pos {
-                let penalty =
-                    15.0 ;//+ if curr.center { 5.0 } else { 0.0 } ;
-                log(1, penalty );
-            }
+// Adds the penalties for typing the last of `chars` after the other three,
+// `count` times. A negative count takes back a quartad scored earlier.
+pub fn score_quartad(result: &mut Penalty, position_map: &LayoutPosMap, chars: &[char; 4], count: i64, model: &Model) {
+    let strokes = Strokes::new(position_map, chars);
+    if strokes.last().is_empty() {
+        return;
+    }
+    result.len += count;
 
-            // 2: Long jump hand.
-            if curr.row == Row::Top && old1.row == Row::Bottom
-                || curr.row == Row::Bottom && old1.row == Row::Top
-            {
-                log(2, 5.0);
+    // Each keystroke of the last character is scored against the three
+    // keystrokes before it.
+    for j in strokes.last..strokes.len {
+        let mut keys = [&KP_NONE; 4];
+        for (n, key) in keys.iter_mut().enumerate() {
+            if j + n >= 3 {
+                *key = &strokes.keys[j + n - 3];
             }
+        }
+        if strokes.layer_key[j] {
+            let total = result.total;
+            score_keys(result, keys, true, count, model);
+            result.layer_times += count as f64;
+            result.layer_total += result.total - total;
+        } else {
+            score_keys(result, keys, false, count, model);
+        }
+    }
 
-            // 3: Long jump.
-            if curr.finger == old1.finger {
-                if curr.row == Row::Top && old1.row == Row::Bottom
-                    || curr.row == Row::Bottom && old1.row == Row::Top
-                {
-                    log(3, 20.0);
-                }
-            }
-            
-            // 4: Long jump consecutive.
-            if curr.row == Row::Top && old1.row == Row::Bottom
-                || curr.row == Row::Bottom && old1.row == Row::Top
-            {
-                if curr.finger == Finger::Ring && old1.finger == Finger::Pinky
-                    || curr.finger == Finger::Pinky && old1.finger == Finger::Ring
-                    || curr.finger == Finger::Middle && old1.finger == Finger::Ring
-                    || curr.finger == Finger::Ring && old1.finger == Finger::Middle
-                    || (curr.finger == Finger::Index
-                        && (old1.finger == Finger::Middle || old1.finger == Finger::Ring)
-                        && curr.row == Row::Top
-                        && old1.row == Row::Bottom)
-                {
-                    log(4, 5.0);
-                }
-            }
+    // 17: Same hand chord.
+    if let (Some(held), Some(curr)) = (strokes.held, strokes.keys[strokes.len - 1]) {
+        if held.hand == curr.hand && curr.hand != Hand::Thumb {
+            let p = model.weights[SAME_HAND_CHORD] * count as f64;
+            result.penalties[SAME_HAND_CHORD].times += count as f64;
+            result.penalties[SAME_HAND_CHORD].total += p;
+            result.total += p;
+            result.layer_total += p;
+        }
+    }
+}
 
-            // 5: Pinky/ring twist.
-            if (curr.finger == Finger::Ring
-                && old1.finger == Finger::Pinky
-                && (curr.row == Row::Home && old1.row == Row::Top
-                    || curr.row == Row::Bottom && old1.row == Row::Top))
-                || (curr.finger == Finger::Pinky
-                    && old1.finger == Finger::Ring
-                    && (curr.row == Row::Top && old1.row == Row::Home
-                        || curr.row == Row::Top && old1.row == Row::Bottom))
-            {
-                log(5, 10.0);
-            }
-            
-            // 9: Roll out.
-            if is_roll_out(curr.finger, old1.finger) {
-                log(9, 1.0);
-                if curr.row == Row::Top && old1.row == Row::Bottom
-                    || curr.row == Row::Bottom && old1.row == Row::Top{
-                    log(7, 10.5);
-                }
-            }
+// Counts `count` keystrokes of `kp` towards the finger and hand load, and the
+// keystrokes they are a share of.
+pub fn count_load(
+    fingers: &mut [i64; 8],
+    hands: &mut [i64; 2],
+    keystrokes: &mut i64,
+    kp: &KeyPress,
+    count: i64,
+) {
+    *keystrokes += count;
+    let offset = match kp.hand {
+        Hand::Left => 0,
+        Hand::Right => 4,
+        Hand::Thumb => return,
+    };
+    hands[offset / 4] += count;
+    match kp.finger {
+        Finger::Pinky => fingers[offset] += count,
+        Finger::Ring => fingers[offset + 1] += count,
+        Finger::Middle => fingers[offset + 2] += count,
+        Finger::Index => fingers[offset + 3] += count,
+        Finger::Thumb => {}
+    }
+}
 
-            // 10: Roll in.
-            if is_roll_in(curr.finger, old1.finger) {
-                if old1.row!= Row::Bottom&&!(
-                    curr.row == Row::Top && old1.row == Row::Bottom
-                    || curr.row == Row::Bottom && old1.row == Row::Top
-                ){
-                    log(10, -0.5);
-                }
-                else{
+// Adds the penalties for the keystroke `keys[3]` after the other three.
+// `layer_key` says whether `keys[3]` is the press of a layer key.
+fn score_keys(
+    result: &mut Penalty,
+    keys: [&Option<KeyPress>; 4],
+    layer_key: bool,
+    count: i64,
+    model: &Model,
+) {
+    let weights = &model.weights;
+    let [old3, old2, old1, curr] = keys;
+    let curr = match *curr {
+        Some(ref kp) => kp,
+        None => return,
+    };
 
-                }
+    count_load(&mut result.fingers, &mut result.hands, &mut result.keystrokes, curr, count);
+    let mut log = |i: usize, scale: f64| {
+        let p = weights[i] * scale * count as f64;
+        result.penalties[i].times += count as f64;
+        result.penalties[i].total += p;
+        result.total += p;
+    };
 
-                if is_roll_in2(curr.finger, old1.finger) {
-                    //result[10].times+=count;
-                }
-                if is_roll_out2(curr.finger, old1.finger) {
-                    //result[9].times+=count;
-                }
-            }
-            
+    if layer_key {
+        // 18: Layer key.
+        log(LAYER_KEY, 1.0 + model.base[curr.pos]);
+    } else {
+        // 0: Base penalty.
+        log(0, model.base[curr.pos]);
+    }
+
+    let old1 = match *old1 {
+        Some(ref o) => o,
+        None => return,
+    };
+
+    if curr.hand == old1.hand && curr.hand != Hand::Thumb {
+        // 1: Same finger.
+        if curr.finger == old1.finger && curr.pos != old1.pos {
+            log(1, 1.0);
         }
-        let old2 = match *old2 {
-            Some(ref o) => o,
-            None => continue,
-        };
-        // Three key penalties.
-        let old3 = match *old3 {
-            Some(ref o) => o,
-            None => continue,
-        };
 
-        if curr.hand == old1.hand && old1.hand == old2.hand && old2.hand == old3.hand {
-            // 13: 4 no alternation
-            log(13, 1.2);
-        } else if curr.hand != old1.hand
-            && old1.hand != old2.hand
-            && old2.hand != old3.hand
-            && curr.hand != Hand::Thumb
-            && old1.hand != Hand::Thumb
-            && old2.hand != Hand::Thumb
-            && old3.hand != Hand::Thumb
-        {
-            // 14: 4 alternations in a row.
-            log(14, 0.01);
+        // 2: Long jump hand.
+        if is_long_jump(curr.row, old1.row) {
+            log(2, 1.0);
         }
-        //8: Alternation
-        if curr.hand != old1.hand {
-            log(8, -0.1);
+
+        // 3: Long jump.
+        if curr.finger == old1.finger && is_long_jump(curr.row, old1.row) {
+            log(3, 1.0);
         }
 
-        if curr.hand == old1.hand && old1.hand == old2.hand {
-            // 6: Roll reversal.
-            if (curr.finger == Finger::Middle
-                && old1.finger == Finger::Pinky
-                && old2.finger == Finger::Ring)
-                || curr.finger == Finger::Ring
-                    && old1.finger == Finger::Pinky
-                    && old2.finger == Finger::Middle
-            {
-                log(6, 10.0);
-            }
+        // 4: Long jump consecutive.
+        if is_long_jump(curr.row, old1.row)
+            && (curr.finger == Finger::Ring && old1.finger == Finger::Pinky
+                || curr.finger == Finger::Pinky && old1.finger == Finger::Ring
+                || curr.finger == Finger::Middle && old1.finger == Finger::Ring
+                || curr.finger == Finger::Ring && old1.finger == Finger::Middle
+                || (curr.finger == Finger::Index
+                    && (old1.finger == Finger::Middle || old1.finger == Finger::Ring)
+                    && curr.row == Row::Top
+                    && old1.row == Row::Bottom))
+        {
+            log(4, 1.0);
+        }
 
-            // 12: Twist.
-            if ((curr.row == Row::Top && old1.row == Row::Home && old2.row == Row::Bottom)
-                || (curr.row == Row::Bottom && old1.row == Row::Home && old2.row == Row::Top))
-                && ((is_roll_out(curr.finger, old1.finger)
-                    && is_roll_out(old1.finger, old2.finger))
-                    || (is_roll_in(curr.finger, old1.finger)
-                        && is_roll_in(old1.finger, old2.finger)))
-            {
-                log(12, 5.0);
-            }
+        // 5: Pinky/ring twist.
+        if (curr.finger == Finger::Ring
+            && old1.finger == Finger::Pinky
+            && old1.row == Row::Top
+            && (curr.row == Row::Home || curr.row == Row::Bottom))
+            || (curr.finger == Finger::Pinky
+                && old1.finger == Finger::Ring
+                && curr.row == Row::Top
+                && (old1.row == Row::Home || old1.row == Row::Bottom))
+        {
+            log(5, 1.0);
         }
 
-        // 11: Long jump sandwich.
-        if curr.hand == old2.hand && curr.finger == old2.finger {
-            if curr.row == Row::Top && old2.row == Row::Bottom
-                || curr.row == Row::Bottom && old2.row == Row::Top
-            {
-                log(11, 3.0);
+        // 9: Roll out.
+        if is_roll_out(curr.finger, old1.finger) {
+            log(9, 1.0);
+            // 7: Long roll out.
+            if is_long_jump(curr.row, old1.row) {
+                log(7, 1.0);
             }
         }
 
-        fn is_roll_out(curr: Finger, prev: Finger) -> bool {
-            match curr {
-                Finger::Middle => prev == Finger::Index,
-                Finger::Ring => {
-                    prev != Finger::Pinky && prev != Finger::Ring && prev != Finger::Thumb
-                }
-                Finger::Pinky => prev != Finger::Pinky && prev != Finger::Thumb,
-                _ => false,
-            }
+        // 10: Roll in.
+        if is_roll_in(curr.finger, old1.finger)
+            && old1.row != Row::Bottom
+            && !is_long_jump(curr.row, old1.row)
+        {
+            log(10, 1.0);
         }
-        // my restricted roll-in, as not all inward rolls feel good
-        fn is_roll_in(curr: Finger, prev: Finger) -> bool {
-            match curr {
-                Finger::Index => prev != Finger::Thumb && prev != Finger::Index,
-                Finger::Middle => prev == Finger::Pinky || prev == Finger::Ring,
-                _ => false,
-            }
+    }
+    let old2 = match *old2 {
+        Some(ref o) => o,
+        None => return,
+    };
+    // Three key penalties.
+    let old3 = match *old3 {
+        Some(ref o) => o,
+        None => return,
+    };
+
+    if curr.hand == old1.hand && old1.hand == old2.hand && old2.hand == old3.hand {
+        // 13: 4 no alternation
+        log(13, 1.0);
+    } else if curr.hand != old1.hand
+        && old1.hand != old2.hand
+        && old2.hand != old3.hand
+        && curr.hand != Hand::Thumb
+        && old1.hand != Hand::Thumb
+        && old2.hand != Hand::Thumb
+        && old3.hand != Hand::Thumb
+    {
+        // 14: 4 alternations in a row.
+        log(14, 1.0);
+    }
+    //8: Alternation
+    if curr.hand != old1.hand {
+        log(8, 1.0);
+    }
+
+    if curr.hand == old1.hand && old1.hand == old2.hand {
+        // 6: Roll reversal.
+        if (curr.finger == Finger::Middle
+            && old1.finger == Finger::Pinky
+            && old2.finger == Finger::Ring)
+            || curr.finger == Finger::Ring
+                && old1.finger == Finger::Pinky
+                && old2.finger == Finger::Middle
+        {
+            log(6, 1.0);
         }
-        fn is_roll_out2(curr: Finger, prev: Finger) -> bool {
-            match curr {
-                Finger::Thumb => false,
-                Finger::Index => prev == Finger::Thumb,
-                Finger::Middle => prev == Finger::Thumb || prev == Finger::Index,
-                Finger::Ring => prev != Finger::Pinky && prev != Finger::Ring,
-                Finger::Pinky => prev != Finger::Pinky,
-            }
+
+        // 12: Twist.
+        if ((curr.row == Row::Top && old1.row == Row::Home && old2.row == Row::Bottom)
+            || (curr.row == Row::Bottom && old1.row == Row::Home && old2.row == Row::Top))
+            && ((is_roll_out(curr.finger, old1.finger)
+                && is_roll_out(old1.finger, old2.finger))
+                || (is_roll_in(curr.finger, old1.finger)
+                    && is_roll_in(old1.finger, old2.finger)))
+        {
+            log(12, 1.0);
         }
-        // all roll-ins
-        fn is_roll_in2(curr: Finger, prev: Finger) -> bool {
-            match curr {
-                Finger::Thumb => prev != Finger::Thumb,
-                Finger::Index => prev != Finger::Thumb && prev != Finger::Index,
-                Finger::Middle => prev == Finger::Pinky || prev == Finger::Ring,
-                Finger::Ring => prev == Finger::Pinky,
-                Finger::Pinky => false,
-            }
+    }
+
+    // 11: Long jump sandwich.
+    if curr.hand == old2.hand
+        && curr.finger == old2.finger
+        && is_long_jump(curr.row, old2.row)
+    {
+        log(11, 1.0);
+    }
+}
+
+// 15, 16: Compare the finger and hand load with the model's targets. These
+// depend on the whole distribution, so they are added once all quartads have
+// been counted.
+pub fn log_load_penalties(result: &mut Penalty, model: &Model) {
+    let (finger_excess, hand_excess) =
+        load_excess(&result.fingers, &result.hands, result.keystrokes, model);
+
+    let mut log = |i: usize, times: f64| {
+        let p = model.weights[i] * times;
+        result.penalties[i].times += times;
+        result.penalties[i].total += p;
+        result.total += p;
+    };
+    log(FINGER_LOAD, finger_excess);
+    log(HAND_LOAD, hand_excess);
+}
+
+// Keystrokes above the finger load targets, as shares of `keystrokes`, and
+// keystrokes by which the hand split misses its target.
+pub fn load_excess(fingers: &[i64; 8], hands: &[i64; 2], keystrokes: i64, model: &Model) -> (f64, f64) {
+    let keystrokes = keystrokes as f64;
+    let hands_len = (hands[0] + hands[1]) as f64;
+    if keystrokes <= 0.0 || hands_len == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let mut finger_excess = 0.0;
+    for (&count, &target) in fingers.iter().zip(&model.finger_targets) {
+        let excess = count as f64 - target / 100.0 * keystrokes;
+        if excess > 0.0 {
+            finger_excess += excess;
         }
     }
-    BestLayoutsEntry {
-        layout: layout.clone(),
-        penalty: result,
+
+    let left_share = hands[0] as f64 / hands_len;
+    let hand_target = model.hand_targets[0] / (model.hand_targets[0] + model.hand_targets[1]);
+    let hand_excess = (left_share - hand_target).abs() * hands_len;
+
+    (finger_excess, hand_excess)
+}
+
+fn is_long_jump(curr: Row, prev: Row) -> bool {
+    curr == Row::Top && prev == Row::Bottom || curr == Row::Bottom && prev == Row::Top
+}
+
+fn is_roll_out(curr: Finger, prev: Finger) -> bool {
+    match curr {
+        Finger::Middle => prev == Finger::Index,
+        Finger::Ring => prev != Finger::Pinky && prev != Finger::Ring && prev != Finger::Thumb,
+        Finger::Pinky => prev != Finger::Pinky && prev != Finger::Thumb,
+        _ => false,
+    }
+}
+
+// my restricted roll-in, as not all inward rolls feel good
+fn is_roll_in(curr: Finger, prev: Finger) -> bool {
+    match curr {
+        Finger::Index => prev != Finger::Thumb && prev != Finger::Index,
+        Finger::Middle => prev == Finger::Pinky || prev == Finger::Ring,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quartads_count_characters_not_bytes() {
+        let quartads = prepare_quartad_list("ça été");
+        assert_eq!(quartads.map.len(), 3);
+        assert_eq!(quartads.map[&['ç', 'a', ' ', 'é']], 1);
+        assert_eq!(quartads.map[&['a', ' ', 'é', 't']], 1);
+        assert_eq!(quartads.map[&[' ', 'é', 't', 'é']], 1);
+
+        let quartads = prepare_quartad_list("😀😀😀😀😀");
+        assert_eq!(quartads.map[&['😀'; 4]], 2);
     }
 }
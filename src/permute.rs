@@ -0,0 +1,447 @@
+//! Exhaustive search of the arrangements of a few keys: `keygen permute`.
+//!
+//! Moving the keys at a handful of positions only changes the score of the
+//! quartads that contain one of their characters. The search places the keys
+//! one position at a time, best looking first, and gives up on a partial
+//! arrangement as soon as a lower bound of its total is worse than the best
+//! whole arrangements found so far. The bound counts
+//!
+//! * each quartad whose keys are all placed at its score, and each other one
+//!   at the lowest score it has with its keys anywhere on the positions;
+//! * the finger load of the keystrokes placed so far, which the rest can only
+//!   add to;
+//! * the least hand load the keystrokes still to place can leave.
+//!
+//! so the arrangements it returns are the best there are, not just the best
+//! it happened to try.
+
+use std::collections::HashMap;
+
+use error::Error;
+use layout::{Layout, LayoutPosMap};
+use model::Model;
+use penalty::{self, BestLayoutsEntry, Penalty, QuartadList, Strokes};
+
+// The most keys `permute` arranges. Even with pruning, 16! arrangements are
+// far more than it could try.
+pub const MAX_KEYS: usize = 16;
+
+pub struct Permutations
+{
+	// The best arrangements, best first.
+	pub best:         Vec<BestLayoutsEntry>,
+	// The number of different arrangements of the keys, and how many of them
+	// the search got to the end of.
+	pub arrangements: u64,
+	pub searched:     u64,
+}
+
+// Finds the `top` best arrangements of the keys at `positions` of `layout`
+// under `model`, the rest of the layout staying as it is.
+pub fn permute(quartads: &QuartadList, layout: &Layout, positions: &[usize], model: &Model, top: usize)
+-> Result<Permutations, Error>
+{
+	penalty::score(quartads, layout, model)?;
+	if positions.len() > MAX_KEYS {
+		return Err(Error::Invalid(format!("permute arranges at most {} keys, not {}", MAX_KEYS, positions.len())));
+	}
+	if model.weights[penalty::FINGER_LOAD] < 0.0 || model.weights[penalty::HAND_LOAD] < 0.0 {
+		return Err(Error::Invalid("permute cannot bound negative finger or hand load weights".to_string()));
+	}
+	for (n, &pos) in positions.iter().enumerate() {
+		if positions[..n].contains(&pos) {
+			return Err(Error::Invalid(format!("key {} is listed twice", pos)));
+		}
+		// With a character on two keys, moving one can change which of them
+		// it is typed from.
+		for c in layout.chars_at(pos).into_iter().filter(|&c| c != '\0') {
+			if (0..layout.geometry().len()).any(|i| i != pos && layout.chars_at(i).contains(&c)) {
+				return Err(Error::Invalid(format!("{:?} is on more than one key", c)));
+			}
+		}
+	}
+
+	let mut search = Search::new(quartads, layout, positions, model, top.max(1));
+	search.lower_bounds();
+	search.search(0);
+
+	let mut best = Vec::with_capacity(search.best.len());
+	for (_, layout) in &search.best {
+		best.push(penalty::score(quartads, layout, model)?);
+	}
+	best.sort();
+	Ok(Permutations {
+		best,
+		arrangements: search.arrangements(),
+		searched: search.searched,
+	})
+}
+
+// A quartad or pair of characters with a character on one of the keys being
+// arranged.
+struct Touched<K>
+{
+	chars: K,
+	count: i64,
+	// The keys its characters are on, as bits.
+	keys:  u32,
+}
+
+// What placing a key changed, to take it back.
+struct Placed
+{
+	key:     usize,
+	other:   usize,
+	sum:     f64,
+	fingers:    [i64; 8],
+	hands:      [i64; 2],
+	keystrokes: i64,
+	pending:    i64,
+}
+
+struct Search<'a>
+{
+	model:     &'a Model,
+	// The positions being arranged, and the keys on them: key `k` is the one
+	// on `slots[k]` to begin with.
+	slots:     Vec<usize>,
+	key_chars: Vec<Vec<char>>,
+	// The first key with the same characters as each key, to try only one of
+	// them in each slot.
+	same_as:   Vec<usize>,
+	layout:    Layout,
+	map:       LayoutPosMap,
+	at:        Vec<usize>,
+	slot_of:   Vec<usize>,
+	placed:    u32,
+
+	quartads:         Vec<Touched<[char; 4]>>,
+	lower:            Vec<f64>,
+	quartads_by_key:  Vec<Vec<usize>>,
+	pairs:            Vec<Touched<[char; 2]>>,
+	pairs_by_key:     Vec<Vec<usize>>,
+
+	// The scores of the quartads that are settled and the lowest scores of
+	// the rest; the finger and hand load of the settled pairs and their
+	// keystrokes; and the most keystrokes the rest can add.
+	sum:        f64,
+	fingers:    [i64; 8],
+	hands:      [i64; 2],
+	keystrokes: i64,
+	pending:    i64,
+	scratch:    Penalty,
+
+	top:       usize,
+	best:      Vec<(f64, Layout)>,
+	searched:  u64,
+}
+
+impl<'a> Search<'a>
+{
+	fn new(quartads: &QuartadList, layout: &Layout, positions: &[usize], model: &'a Model, top: usize)
+	-> Search<'a>
+	{
+		let key_chars: Vec<Vec<char>> = positions.iter()
+			.map(|&pos| {
+				let mut chars: Vec<char> = Vec::new();
+				for c in layout.chars_at(pos) {
+					if c != '\0' && !chars.contains(&c) {
+						chars.push(c);
+					}
+				}
+				chars
+			})
+			.collect();
+		let key_of: HashMap<char, usize> = key_chars.iter().enumerate()
+			.flat_map(|(k, chars)| chars.iter().map(move |&c| (c, k)))
+			.collect();
+		let keys = |chars: &[char]| chars.iter().filter_map(|c| key_of.get(c)).fold(0u32, |keys, &k| keys | 1 << k);
+
+		let mut search = Search {
+			model,
+			slots: positions.to_vec(),
+			same_as: (0..positions.len()).map(|k| (0..k + 1).find(|&j| key_chars[j] == key_chars[k]).unwrap()).collect(),
+			key_chars,
+			layout: layout.clone(),
+			map: layout.get_position_map(),
+			at: (0..positions.len()).collect(),
+			slot_of: (0..positions.len()).collect(),
+			placed: 0,
+			quartads: Vec::new(),
+			lower: Vec::new(),
+			quartads_by_key: vec![Vec::new(); positions.len()],
+			pairs: Vec::new(),
+			pairs_by_key: vec![Vec::new(); positions.len()],
+			sum: 0.0,
+			fingers: [0; 8],
+			hands: [0; 2],
+			keystrokes: 0,
+			pending: 0,
+			scratch: Penalty::new(model),
+			top,
+			best: Vec::new(),
+			searched: 0,
+		};
+
+		// Quartads without a character of the keys score the same whatever
+		// the arrangement. Sum in a fixed order, as everywhere else.
+		let mut list: Vec<([char; 4], i64)> = quartads.map.iter().map(|(&chars, &count)| (chars, count)).collect();
+		list.sort_unstable();
+		let mut pair_counts: HashMap<[char; 2], i64> = HashMap::new();
+		for &(chars, count) in &list {
+			*pair_counts.entry([chars[2], chars[3]]).or_insert(0) += count;
+			match keys(&chars) {
+				0 => search.sum += search.score(&chars, count),
+				keys => search.quartads.push(Touched { chars, count, keys }),
+			}
+		}
+		let mut pairs: Vec<([char; 2], i64)> = pair_counts.into_iter().collect();
+		pairs.sort_unstable();
+		for (chars, count) in pairs {
+			match keys(&chars) {
+				0 => search.add_load(&chars, count),
+				keys => {
+					// A character takes at most two keystrokes: its layer key
+					// and its own.
+					search.pending += 2 * count;
+					search.pairs.push(Touched { chars, count, keys });
+				},
+			}
+		}
+
+		for k in 0..positions.len() {
+			search.quartads_by_key[k] = (0..search.quartads.len()).filter(|&q| search.quartads[q].keys & 1 << k != 0).collect();
+			search.pairs_by_key[k] = (0..search.pairs.len()).filter(|&p| search.pairs[p].keys & 1 << k != 0).collect();
+		}
+		search
+	}
+
+	// Works out the lowest score of every quartad with its keys anywhere on
+	// the slots, and starts the sum off with them.
+	fn lower_bounds(&mut self)
+	{
+		let mut by_keys: HashMap<u32, Vec<usize>> = HashMap::new();
+		for (i, q) in self.quartads.iter().enumerate() {
+			by_keys.entry(q.keys).or_default().push(i);
+		}
+		let mut by_keys: Vec<(u32, Vec<usize>)> = by_keys.into_iter().collect();
+		by_keys.sort_unstable();
+
+		self.lower = vec![f64::INFINITY; self.quartads.len()];
+		for (keys, quartads) in by_keys {
+			let keys: Vec<usize> = (0..self.slots.len()).filter(|&k| keys & 1 << k != 0).collect();
+			for slots in placements(keys.len(), self.slots.len()) {
+				for (&key, &slot) in keys.iter().zip(&slots) {
+					self.put(key, slot);
+				}
+				for &q in &quartads {
+					let (chars, count) = (self.quartads[q].chars, self.quartads[q].count);
+					let score = self.score(&chars, count);
+					if score < self.lower[q] {
+						self.lower[q] = score;
+					}
+				}
+			}
+		}
+		for k in 0..self.slots.len() {
+			self.put(k, k);
+		}
+		self.sum += self.lower.iter().sum::<f64>();
+	}
+
+	// Places the rest of the keys from slot `depth` on.
+	fn search(&mut self, depth: usize)
+	{
+		if depth == self.slots.len() {
+			self.searched += 1;
+			let total = self.bound();
+			if self.worth(total) {
+				let at = self.best.iter().position(|&(t, _)| t > total).unwrap_or(self.best.len());
+				self.best.insert(at, (total, self.layout.clone()));
+				self.best.truncate(self.top);
+			}
+			return;
+		}
+
+		let mut children: Vec<(f64, usize)> = Vec::new();
+		for key in 0..self.slots.len() {
+			let unplaced = |k: usize| self.placed & 1 << k == 0;
+			if !unplaced(key) || (0..key).any(|j| unplaced(j) && self.same_as[j] == self.same_as[key]) {
+				continue;
+			}
+			let placed = self.place(key, depth);
+			children.push((self.bound(), key));
+			self.take_back(placed);
+		}
+		children.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal).then(a.1.cmp(&b.1)));
+
+		for (bound, key) in children {
+			if !self.worth(bound) {
+				break;
+			}
+			let placed = self.place(key, depth);
+			self.search(depth + 1);
+			self.take_back(placed);
+		}
+	}
+
+	// Whether an arrangement that totals at least `bound` could be among the
+	// best. Sums in a different order round differently, so only give up on
+	// one that is clearly worse.
+	fn worth(&self, bound: f64)
+	-> bool
+	{
+		match self.best.get(self.top - 1) {
+			None => true,
+			Some(&(worst, _)) => bound <= worst + worst.abs().max(1.0) * 1e-9,
+		}
+	}
+
+	// The lower bound of the total of the arrangements that go on from here,
+	// which is their total once every key is placed.
+	fn bound(&self)
+	-> f64
+	{
+		// The load of each finger only grows with more keystrokes, and the
+		// share it is allowed is largest with all the pending ones.
+		let (finger_excess, _) = penalty::load_excess(&self.fingers, &self.hands, self.keystrokes + self.pending, self.model);
+
+		// The pending keystrokes add up to `pending` to the hand total, and at
+		// most that many to the left hand. The distance to the target split
+		// is linear in both, so it is least at a corner or zero between.
+		let target = self.model.hand_targets[0] / (self.model.hand_targets[0] + self.model.hand_targets[1]);
+		let (left, total, pending) = (self.hands[0] as f64, (self.hands[0] + self.hands[1]) as f64, self.pending as f64);
+		let corners = [
+			left - target * total,
+			left - target * (total + pending),
+			left + pending - target * (total + pending),
+		];
+		let hand_excess = if corners.iter().any(|&x| x <= 0.0) && corners.iter().any(|&x| x >= 0.0) {
+			0.0
+		} else {
+			corners.iter().map(|x| x.abs()).fold(f64::INFINITY, f64::min)
+		};
+		let hand_excess = if total + pending == 0.0 { 0.0 } else { hand_excess };
+
+		self.sum + self.model.weights[penalty::FINGER_LOAD] * finger_excess + self.model.weights[penalty::HAND_LOAD] * hand_excess
+	}
+
+	// Puts `key` in slot `depth` and counts in everything that settles.
+	fn place(&mut self, key: usize, depth: usize)
+	-> Placed
+	{
+		let placed = Placed {
+			key,
+			other: self.at[depth],
+			sum: self.sum,
+			fingers: self.fingers,
+			hands: self.hands,
+			keystrokes: self.keystrokes,
+			pending: self.pending,
+		};
+		self.put(key, depth);
+		self.placed |= 1 << key;
+
+		// What settles are the quartads and pairs on `key` whose other keys
+		// are placed already.
+		for n in 0..self.quartads_by_key[key].len() {
+			let q = self.quartads_by_key[key][n];
+			if self.quartads[q].keys & !self.placed == 0 {
+				let (chars, count) = (self.quartads[q].chars, self.quartads[q].count);
+				self.sum += self.score(&chars, count) - self.lower[q];
+			}
+		}
+		for n in 0..self.pairs_by_key[key].len() {
+			let p = self.pairs_by_key[key][n];
+			if self.pairs[p].keys & !self.placed == 0 {
+				let (chars, count) = (self.pairs[p].chars, self.pairs[p].count);
+				self.add_load(&chars, count);
+				self.pending -= 2 * count;
+			}
+		}
+		placed
+	}
+
+	fn take_back(&mut self, placed: Placed)
+	{
+		self.placed &= !(1 << placed.key);
+		let slot = self.slot_of[placed.key];
+		self.put(placed.other, slot);
+		self.sum = placed.sum;
+		self.fingers = placed.fingers;
+		self.hands = placed.hands;
+		self.keystrokes = placed.keystrokes;
+		self.pending = placed.pending;
+	}
+
+	// Moves `key` to `slot`, and the key there to where `key` was.
+	fn put(&mut self, key: usize, slot: usize)
+	{
+		let from = self.slot_of[key];
+		if from == slot {
+			return;
+		}
+		let other = self.at[slot];
+		self.layout.swap(self.slots[from], self.slots[slot]);
+		self.at.swap(from, slot);
+		self.slot_of[key] = slot;
+		self.slot_of[other] = from;
+		let moved: Vec<char> = self.key_chars[key].iter().chain(&self.key_chars[other]).cloned().collect();
+		self.layout.update_position_map(&mut self.map, &moved);
+	}
+
+	// The penalty of typing `chars` `count` times, without the load terms.
+	fn score(&mut self, chars: &[char; 4], count: i64)
+	-> f64
+	{
+		self.scratch.total = 0.0;
+		penalty::score_quartad(&mut self.scratch, &self.map, chars, count, self.model);
+		self.scratch.total
+	}
+
+	fn add_load(&mut self, chars: &[char; 2], count: i64)
+	{
+		let strokes = Strokes::new(&self.map, chars);
+		for kp in strokes.last().iter().flatten() {
+			penalty::count_load(&mut self.fingers, &mut self.hands, &mut self.keystrokes, kp, count);
+		}
+	}
+
+	// The number of different arrangements: n! over the ways to order each
+	// group of keys with the same characters.
+	fn arrangements(&self)
+	-> u64
+	{
+		let mut count: u64 = 1;
+		let mut seen: HashMap<usize, u64> = HashMap::new();
+		for (n, &same) in self.same_as.iter().enumerate() {
+			let twins = seen.entry(same).or_insert(0);
+			*twins += 1;
+			count = count * (n as u64 + 1) / *twins;
+		}
+		count
+	}
+}
+
+/* ------- *
+ * HELPERS *
+ * ------- */
+
+// Every way to put `m` keys in different slots of `n`.
+fn placements(m: usize, n: usize)
+-> Vec<Vec<usize>>
+{
+	let mut all = vec![Vec::new()];
+	for _ in 0..m {
+		all = all.iter()
+			.flat_map(|slots| {
+				(0..n).filter(move |s| !slots.contains(s)).map(move |s| {
+					let mut slots = slots.clone();
+					slots.push(s);
+					slots
+				})
+			})
+			.collect();
+	}
+	all
+}
@@ -0,0 +1,330 @@
+//! Result output: the text tables, and machine-readable JSON or CSV.
+//!
+//! JSON output is one object per line (JSON Lines), CSV output one row per
+//! layout after a header row. Both carry the same fields: the keys of every
+//! layer, the total and scaled score, the times and total of each penalty,
+//! the presses and total of the layer keys, and the finger and hand load in
+//! percent.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use keygen::corpus::Corpus;
+use keygen::error::Error;
+use keygen::layout::{Activation, Layout};
+use keygen::model::Model;
+use keygen::pareto::Objectives;
+use keygen::penalty::{self, BestLayoutsEntry, PENALTY_DESCRIPTIONS};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat
+{
+	Text,
+	Json,
+	Csv,
+}
+
+impl FromStr for OutputFormat
+{
+	type Err = String;
+
+	fn from_str(s: &str)
+	-> Result<OutputFormat, String>
+	{
+		match s {
+			"text" => Ok(OutputFormat::Text),
+			"json" => Ok(OutputFormat::Json),
+			"csv" => Ok(OutputFormat::Csv),
+			_ => Err(format!("unknown format `{}` (expected text, json or csv)", s)),
+		}
+	}
+}
+
+// Prints anything that has to come before the first result, for layouts with
+// `layers` layers.
+pub fn print_header(format: OutputFormat, layers: usize)
+{
+	if format != OutputFormat::Csv {
+		return;
+	}
+
+	let mut columns: Vec<String> = vec!["kind".to_string(), "name".to_string()];
+	for n in 0..layers {
+		columns.push(format!("layer_{}", n));
+	}
+	columns.push("total".to_string());
+	columns.push("scaled".to_string());
+	for desc in PENALTY_DESCRIPTIONS.iter() {
+		columns.push(format!("{}_times", desc.key));
+		columns.push(format!("{}_total", desc.key));
+	}
+	columns.push("layer_keys_times".to_string());
+	columns.push("layer_keys_total".to_string());
+	for hand in &["left", "right"] {
+		for finger in &["pinky", "ring", "middle", "index"] {
+			columns.push(format!("{}_{}", hand, finger));
+		}
+	}
+	columns.push("left_hand".to_string());
+	columns.push("right_hand".to_string());
+	println!("{}", columns.join(","));
+}
+
+// Prints `item` as the result of the given kind, e.g. "Reference", and name,
+// e.g. "QWERTY". Text output heads the usual table with "kind: name".
+pub fn print_report(kind: &str, name: &str, item: &BestLayoutsEntry, format: OutputFormat)
+{
+	match format {
+		OutputFormat::Text => {
+			if name.is_empty() {
+				print!("{}:\n{}", kind, format_result(item));
+			} else {
+				print!("{}: {}\n{}", kind, name, format_result(item));
+			}
+		},
+		OutputFormat::Json => print_json(kind, name, item),
+		OutputFormat::Csv => print_csv(kind, name, item),
+	}
+}
+
+// Prints `item` as a table of its penalties, with the finger and hand loads
+// below. The table goes out in one piece, so that the reports of optimiser
+// threads do not interleave.
+pub fn print_result(item: &BestLayoutsEntry)
+{
+	print!("{}", format_result(item));
+}
+
+fn format_result(item: &BestLayoutsEntry)
+-> String
+{
+	let layout = &item.layout;
+	let total = item.penalty.total;
+	let len = item.penalty.len;
+	let penalties = &item.penalty.penalties;
+	let penalty = &item.penalty;
+	let fingers = &penalty.fingers;
+	let keystrokes = penalty.keystrokes as f64;
+	let show_all = false;
+
+	let mut out = String::new();
+	write!(out, "\n{}\n", layout).unwrap();
+	writeln!(out, "total: {0:<10.2}; scaled: {1:<10.4}", total, total / (len as f64)).unwrap();
+	writeln!(out, "\n{:<30} | {:^7} | {:^7} | {:^8} | {:<10}", "Name", "% times", "Avg", "% Total", "Total").unwrap();
+	writeln!(out, "----------------------------------------------------------------------").unwrap();
+	for (i, penalty) in penalties.iter().enumerate() {
+		// The loads count keystrokes beyond their targets, not quartads.
+		let times = if i == penalty::FINGER_LOAD || i == penalty::HAND_LOAD { keystrokes } else { len as f64 };
+		if penalty.show || show_all {
+			writeln!(
+				out,
+				"{:<30} | {:<7.2} | {:<7.3} | {:<8.3} | {:<10.0}",
+				penalty.name,
+				(100.0 * penalty.times / times),
+				penalty.total / (len as f64),
+				100.0 * penalty.total / total,
+				penalty.total).unwrap();
+		}
+	}
+	writeln!(out, "----------------------------------------------------------------------").unwrap();
+	writeln!(
+		out,
+		"\n{:^5.1} {:^5.1} {:^5.1} {:^5.1} | {:^5.1} {:^5.1} {:^5.1} {:^5.1}",
+		fingers[0] as f64 * 100.0 / keystrokes,
+		fingers[1] as f64 * 100.0 / keystrokes,
+		fingers[2] as f64 * 100.0 / keystrokes,
+		fingers[3] as f64 * 100.0 / keystrokes,
+		fingers[7] as f64 * 100.0 / keystrokes,
+		fingers[6] as f64 * 100.0 / keystrokes,
+		fingers[5] as f64 * 100.0 / keystrokes,
+		fingers[4] as f64 * 100.0 / keystrokes).unwrap();
+	writeln!(
+		out,
+		"{:^5.1}| {:^5.1}",
+		penalty.hands[0] as f64 * 100.0 / keystrokes,
+		penalty.hands[1] as f64 * 100.0 / keystrokes).unwrap();
+	// What reaching the upper layers costs, if the geometry charges for it.
+	if layout.geometry().layers.iter().any(|&a| a != Activation::None) {
+		writeln!(
+			out,
+			"\nLayer keys: {:.2}% of keystrokes; total: {:.0} ({:.3}% of total)",
+			100.0 * penalty.layer_times / keystrokes,
+			penalty.layer_total,
+			100.0 * penalty.layer_total / total).unwrap();
+	}
+	writeln!(out, "##########################################################################").unwrap();
+	out
+}
+
+// Prints the penalty of `item` on each of `objectives` and its weighted sum,
+// to go above its table.
+pub fn print_objectives(item: &BestLayoutsEntry, objectives: &Objectives)
+{
+	let values: Vec<String> = objectives.keys().iter()
+		.zip(objectives.values(item))
+		.map(|(key, value)| format!("{}: {:.0}", key, value))
+		.collect();
+	println!("\n{}; weighted sum: {:.2}", values.join("; "), objectives.weighted_sum(item));
+}
+
+// Prints the score of `layout` on each of `corpora` on its own.
+pub fn print_breakdown(layout: &Layout, corpora: &[Corpus], model: &Model, format: OutputFormat)
+-> Result<(), Error>
+{
+	let weight: f64 = corpora.iter().map(|c| c.weight).sum();
+	if format == OutputFormat::Text {
+		println!("Per corpus:");
+	}
+	for corpus in corpora {
+		let quartads = corpus.data.quartads();
+		let item = penalty::score(&quartads, layout, model)?;
+		if format == OutputFormat::Text {
+			println!(
+				"{:<30} | weight: {:<5.3} | total: {:<10.2}; scaled: {:<10.4}",
+				corpus.name,
+				corpus.weight / weight,
+				item.penalty.total,
+				item.penalty.total / item.penalty.len as f64);
+		} else {
+			print_report("Corpus", &corpus.name, &item, format);
+		}
+	}
+	if format == OutputFormat::Text {
+		println!();
+	}
+	Ok(())
+}
+
+fn print_json(kind: &str, name: &str, item: &BestLayoutsEntry)
+{
+	let penalty = &item.penalty;
+	let len = penalty.len as f64;
+	let keystrokes = penalty.keystrokes as f64;
+
+	let layers: Vec<String> = item.layout.layers().iter()
+		.map(|layer| {
+			let rows: Vec<String> = item.layout.rows(layer).iter()
+				.map(|row| {
+					let keys: Vec<String> = row.iter().map(|c| json_string(&c.to_string())).collect();
+					format!("[{}]", keys.join(","))
+				})
+				.collect();
+			format!("[{}]", rows.join(","))
+		})
+		.collect();
+	let penalties: Vec<String> = PENALTY_DESCRIPTIONS.iter().zip(&penalty.penalties)
+		.map(|(desc, p)| {
+			format!("{}:{{\"times\":{},\"total\":{}}}", json_string(desc.key), json_number(p.times), json_number(p.total))
+		})
+		.collect();
+	let fingers: Vec<String> = finger_loads(item).iter().map(|&f| json_number(f)).collect();
+
+	println!(
+		"{{\"kind\":{},\"name\":{},\"layers\":[{}],\"total\":{},\"scaled\":{},\"penalties\":{{{}}},\"layer_keys\":{{\"times\":{},\"total\":{}}},\"fingers\":[{}],\"hands\":[{},{}]}}",
+		json_string(&kind.to_lowercase()),
+		json_string(name),
+		layers.join(","),
+		json_number(penalty.total),
+		json_number(penalty.total / len),
+		penalties.join(","),
+		json_number(penalty.layer_times),
+		json_number(penalty.layer_total),
+		fingers.join(","),
+		json_number(penalty.hands[0] as f64 * 100.0 / keystrokes),
+		json_number(penalty.hands[1] as f64 * 100.0 / keystrokes));
+}
+
+fn print_csv(kind: &str, name: &str, item: &BestLayoutsEntry)
+{
+	let penalty = &item.penalty;
+	let len = penalty.len as f64;
+	let keystrokes = penalty.keystrokes as f64;
+
+	let mut fields = vec![csv_field(&kind.to_lowercase()), csv_field(name)];
+	for layer in item.layout.layers().iter() {
+		let keys: String = item.layout.rows(layer).iter()
+			.flat_map(|row| row.iter())
+			.map(|c| if c.is_control() { c.escape_default().to_string() } else { c.to_string() })
+			.collect();
+		fields.push(csv_field(&keys));
+	}
+	fields.push(csv_number(penalty.total));
+	fields.push(csv_number(penalty.total / len));
+	for p in &penalty.penalties {
+		fields.push(csv_number(p.times));
+		fields.push(csv_number(p.total));
+	}
+	fields.push(csv_number(penalty.layer_times));
+	fields.push(csv_number(penalty.layer_total));
+	for f in finger_loads(item).iter() {
+		fields.push(csv_number(*f));
+	}
+	fields.push(csv_number(penalty.hands[0] as f64 * 100.0 / keystrokes));
+	fields.push(csv_number(penalty.hands[1] as f64 * 100.0 / keystrokes));
+	println!("{}", fields.join(","));
+}
+
+// Finger load in percent of all keystrokes, left pinky to left index, then
+// right pinky to right index.
+fn finger_loads(item: &BestLayoutsEntry)
+-> [f64; 8]
+{
+	let keystrokes = item.penalty.keystrokes as f64;
+	let mut loads = [0.0; 8];
+	for (load, &count) in loads.iter_mut().zip(&item.penalty.fingers) {
+		*load = count as f64 * 100.0 / keystrokes;
+	}
+	loads
+}
+
+fn json_string(s: &str)
+-> String
+{
+	let mut out = String::from("\"");
+	for c in s.chars() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\t' => out.push_str("\\t"),
+			c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+			c => out.push(c),
+		}
+	}
+	out.push('"');
+	out
+}
+
+// `x` as a JSON number. JSON has no NaN or infinity, which shares of nothing
+// come to, such as the scaled total of a layout that types none of the corpus:
+// those are null.
+fn json_number(x: f64)
+-> String
+{
+	if x.is_finite() {
+		x.to_string()
+	} else {
+		"null".to_string()
+	}
+}
+
+// Empty for a share of no keystrokes, like `json_number`'s null.
+fn csv_number(x: f64)
+-> String
+{
+	if x.is_finite() {
+		x.to_string()
+	} else {
+		String::new()
+	}
+}
+
+fn csv_field(s: &str)
+-> String
+{
+	if s.contains([',', '"', '\n']) {
+		format!("\"{}\"", s.replace('"', "\"\""))
+	} else {
+		s.to_string()
+	}
+}
@@ -0,0 +1,53 @@
+//! What the optimisers need of a way to score layouts.
+//!
+//! `Model` scores layouts with the penalties of penalty.rs and swaps with the
+//! incremental `Evaluator`. Another scorer only has to give a full score with
+//! its breakdown and an `Evaluate` that follows swaps of keys.
+
+use error::Error;
+use evaluator::{Evaluate, Evaluator, QuartadIndex};
+use layout::Layout;
+use model::Model;
+use penalty::{self, BestLayoutsEntry, QuartadList};
+
+pub trait Scorer: Sync
+{
+	// What the scorer keeps of the quartads of a corpus to score many layouts
+	// on them.
+	type Index: Sync;
+
+	// The score of `layout` on `quartads`, or why it cannot be scored there.
+	fn score(&self, quartads: &QuartadList, layout: &Layout)
+	-> Result<BestLayoutsEntry, Error>;
+
+	fn index(&self, quartads: &QuartadList)
+	-> Self::Index;
+
+	// An evaluator of swaps of the keys of `layout`, on the quartads of
+	// `index`.
+	fn evaluator<'a>(&'a self, index: &'a Self::Index, layout: &Layout)
+	-> Box<dyn Evaluate + 'a>;
+}
+
+impl Scorer for Model
+{
+	type Index = QuartadIndex;
+
+	fn score(&self, quartads: &QuartadList, layout: &Layout)
+	-> Result<BestLayoutsEntry, Error>
+	{
+		penalty::score(quartads, layout, self)
+	}
+
+	fn index(&self, quartads: &QuartadList)
+	-> QuartadIndex
+	{
+		QuartadIndex::new(quartads)
+	}
+
+	fn evaluator<'a>(&'a self, index: &'a QuartadIndex, layout: &Layout)
+	-> Box<dyn Evaluate + 'a>
+	{
+		Box::new(Evaluator::new(index, layout, self))
+	}
+}
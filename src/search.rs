@@ -0,0 +1,85 @@
+//! Local searches other than annealing, for `Optimizer::climb`.
+//!
+//! Both make the same random swaps of free keys as annealing and differ only
+//! in which swaps they keep. Tabu search looks at a few swaps at a time and
+//! makes the best of them, even when it is worse, except for swaps of keys it
+//! swapped lately: those would only take it back where it came from. Late
+//! acceptance hill climbing keeps a swap that is no worse than the layout it
+//! had a fixed number of cycles ago, which lets it out of a local minimum
+//! without a temperature to tune.
+
+use std::collections::VecDeque;
+
+// The number of swaps a tabu search looks at before it makes one.
+pub const CANDIDATES: usize = 32;
+// How many swaps the tabu list remembers, by default.
+pub const TENURE:     usize = 50;
+// How many cycles late acceptance looks back, by default.
+pub const HISTORY:    usize = 1000;
+
+// The pairs of keys swapped lately, which a tabu search does not swap again.
+pub struct TabuList
+{
+	pairs:  VecDeque<(usize, usize)>,
+	tenure: usize,
+}
+
+impl TabuList
+{
+	pub fn new(tenure: usize)
+	-> TabuList
+	{
+		TabuList { pairs: VecDeque::with_capacity(tenure + 1), tenure }
+	}
+
+	pub fn contains(&self, i: usize, j: usize)
+	-> bool
+	{
+		self.pairs.contains(&pair(i, j))
+	}
+
+	// Remembers a swap of keys `i` and `j`, and forgets the oldest one beyond
+	// the tenure.
+	pub fn push(&mut self, i: usize, j: usize)
+	{
+		self.pairs.push_back(pair(i, j));
+		if self.pairs.len() > self.tenure {
+			self.pairs.pop_front();
+		}
+	}
+}
+
+// The totals of the layouts a late acceptance hill climb had over its last
+// cycles.
+pub struct LateAcceptance
+{
+	history: Vec<f64>,
+}
+
+impl LateAcceptance
+{
+	// A history of `length` cycles, all at `total`.
+	pub fn new(length: usize, total: f64)
+	-> LateAcceptance
+	{
+		LateAcceptance { history: vec![total; length.max(1)] }
+	}
+
+	// Whether to go from a layout with total `current` to one with `candidate`
+	// at cycle `cycle`: if it is no worse than either `current` or the layout
+	// the history length ago. The history then holds the one it goes on with.
+	pub fn accept(&mut self, cycle: usize, current: f64, candidate: f64)
+	-> bool
+	{
+		let v = cycle % self.history.len();
+		let accept = candidate <= current || candidate <= self.history[v];
+		self.history[v] = if accept { candidate } else { current };
+		accept
+	}
+}
+
+fn pair(i: usize, j: usize)
+-> (usize, usize)
+{
+	if i < j { (i, j) } else { (j, i) }
+}
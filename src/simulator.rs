@@ -1,260 +1,784 @@
+//! Applies the math in annealing.rs to keyboard layouts.
+
 extern crate num_cpus;
-/// Applies the math in annealing.rs to keyboard layouts.
 extern crate rand;
+extern crate rand_chacha;
 extern crate rayon;
 
-//use self::rand::{random, thread_rng};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use self::rand::*;
-use std::cmp::Ordering;
-//use std::collections::;
+use self::rand_chacha::ChaCha20Rng;
 use self::rayon::prelude::*;
-use std::iter;
-use std::*;
 
-use annealing;
-use layout;
-use penalty;
+use annealing::Annealing;
+use error::Error;
+use genetic::{self, Crossover};
+use layout::{self, Layout, LayoutShuffleMask};
+use model::Model;
+use pareto::{Front, Objectives};
+use penalty::{BestLayoutsEntry, QuartadList};
+use scorer::Scorer;
+use search::{self, LateAcceptance, TabuList};
+
+// How `Optimizer::optimize` searches for better layouts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Search {
+    // Simulated annealing: `anneal`.
+    Annealing,
+    // A genetic algorithm: `evolve`.
+    Genetic,
+    // Tabu search: `climb`.
+    Tabu,
+    // Late acceptance hill climbing: `climb`.
+    LateAcceptance,
+}
+
+impl FromStr for Search {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Search, String> {
+        match s {
+            "sa" => Ok(Search::Annealing),
+            "ga" => Ok(Search::Genetic),
+            "tabu" => Ok(Search::Tabu),
+            "lahc" => Ok(Search::LateAcceptance),
+            _ => Err(format!("unknown optimizer `{}` (expected sa, ga, tabu or lahc)", s)),
+        }
+    }
+}
+
+pub struct OptimizerOptions {
+    pub search: Search,
+    // How many layouts `refine` keeps at each round.
+    pub top_layouts: usize,
+    pub num_swaps: usize,
+    // Seed for the random number generators of `anneal`.
+    pub seed: u64,
+    pub cycles: usize,
+    pub iterations: usize,
+    pub annealing: Annealing,
+    // Random swaps each worker of the first iteration makes to its starting
+    // layout before it anneals it. `evolve` makes them to the copies of the
+    // starting layouts that fill its first generation, and shuffles the
+    // copies altogether if 0.
+    pub warm_up: usize,
+    // The number of layouts in each generation of `evolve`, how it crosses
+    // them and how many generations it breeds.
+    pub population: usize,
+    pub crossover: Crossover,
+    pub generations: usize,
+    // The number of swaps the tabu list of `climb` remembers.
+    pub tabu_tenure: usize,
+    // The number of cycles late acceptance in `climb` looks back.
+    pub history: usize,
+    // `anneal` hands its state to the progress callback every this many
+    // cycles, or only when it is interrupted if 0.
+    pub checkpoint_cycles: usize,
+}
+
+// The defaults of the command line, with a seed of 0.
+impl Default for OptimizerOptions {
+    fn default() -> OptimizerOptions {
+        OptimizerOptions {
+            search: Search::Annealing,
+            top_layouts: 1,
+            num_swaps: 2,
+            seed: 0,
+            cycles: 205_000,
+            iterations: 5,
+            annealing: Annealing::default(),
+            warm_up: 0,
+            population: 64,
+            crossover: Crossover::Pmx,
+            generations: 200,
+            tabu_tenure: search::TENURE,
+            history: search::HISTORY,
+            checkpoint_cycles: 0,
+        }
+    }
+}
+
+// What an optimiser is doing, for its caller to show. Annealing workers
+// report from several threads at once.
+pub enum Progress<'a> {
+    // An annealing iteration starts.
+    Iteration(usize),
+    // The best layout an annealing worker has found so far, now and then.
+    Best(&'a Layout),
+    // An annealing worker is done: the layout it started from and the best
+    // one it found.
+    Worker(&'a BestLayoutsEntry, &'a BestLayoutsEntry),
+    // A refine round is done: its number, the total it started from and the
+    // best total it found.
+    Round(usize, f64, f64),
+    // Where an annealing run is, to carry it on later with `resume`.
+    Checkpoint(&'a AnnealState),
+    // A generation of `evolve` has a better layout than the one before: its
+    // number and the best total.
+    Generation(usize, f64),
+}
 
-use penalty::*;
+// Where an annealing run is: enough to carry it on exactly where it stopped.
+#[derive(Clone)]
+pub struct AnnealState {
+    // The number of workers of each iteration.
+    pub threads: usize,
+    // The iteration under way, from 1.
+    pub iteration: usize,
+    // The best layouts at the start of the iteration, best first.
+    pub best_layouts: Vec<Layout>,
+    // The workers of the iteration, or none if it has not started yet.
+    pub workers: Vec<WorkerState>,
+}
 
 #[derive(Clone)]
+pub struct WorkerState {
+    // The cycles the worker has run.
+    pub cycle: usize,
+    // How far its random number generator has got, in 32-bit words.
+    pub rng_pos: u128,
+    pub print_frequency: usize,
+    // The layout the worker last accepted, its total and the running sums of
+    // its evaluator (see `Evaluate::sums`).
+    pub layout: Layout,
+    pub accepted_total: f64,
+    pub sums: Vec<f64>,
+    // The best layout the worker has found and its total.
+    pub best_layout: Layout,
+    pub best_total: f64,
+}
 
-struct LL<T> {
-    item: T,
-    len: usize,
-    next: Option<Box<LL<T>>>,
+// The layouts `refine` ends with: the best few of the last round and the one
+// it settled on.
+pub struct Refined {
+    pub top_layouts: Vec<BestLayoutsEntry>,
+    pub winner: BestLayoutsEntry,
 }
 
-pub fn simulate<'a>(
-    quartads: &penalty::QuartadList<'a>,
-    init_layout: &layout::Layout,
-    debug: bool,
-    top_layouts: usize,
-    num_swaps: usize,
-) {
-    const CYCLES: i32 = 205000;
-    const ITERATIONS: i32 = 5;
-    let threads = num_cpus::get();
-    let BEST_LAYOUTS_KEPT: usize = threads * 3;
-
-    let initial_penalty = || penalty::calculate_penalty(&quartads, init_layout);
-    let mut best_layouts: Vec<BestLayoutsEntry> =
-        (0..BEST_LAYOUTS_KEPT).map(|_| initial_penalty()).collect();
-
-    // in each iteration each thread takes a random layout and tries to optimalize it for 5000 cycles;
-    //results are appended to bestLayouts, which is then sorted and truntcated back to best ten
-    for it_num in 1..ITERATIONS + 1 {
-        println!("iteration: {}", it_num);
-        let iteration: Vec<BestLayoutsEntry> = (0..threads)
-            .map(|i| &best_layouts[best_layouts.len() - 1 - i as usize])
-            .collect::<Vec<&BestLayoutsEntry>>()
-            .into_par_iter()
-            .map(|entry| {
-                let mut accepted_layout = entry.clone();
-                let mut bestLayout: BestLayoutsEntry = entry.clone();
-
-                let printFrequency = thread_rng().gen::<i32>() % 5000 + 5000;
-
-                for cycle in 1..CYCLES + 1 {
-                    let mut curr_layout = accepted_layout.clone();
-                    curr_layout
-                        .layout
-                        .shuffle(random::<usize>() % num_swaps + 1);
-
-                    // Calculate penalty.
-                    curr_layout = penalty::calculate_penalty(&quartads, &curr_layout.layout);
-
-                    if curr_layout.penalty.total < bestLayout.penalty.total {
-                        bestLayout = curr_layout.clone();
-                    }
-                    // Probabilistically accept worse transitions; always accept better
-                    // transitions.
-                    if annealing::accept_transition(
-                        (curr_layout.penalty.total - accepted_layout.penalty.total) / (accepted_layout.penalty.total as f64),
-                        cycle as usize,
-                    ) {
-                        accepted_layout = curr_layout.clone();
-                    }
-                    if cycle % printFrequency  == 0 {
-                        print_result(&bestLayout);
-                    }
-                }
-                print_result(&entry);
-                bestLayout
-            })
-            .collect();
-        for entry in iteration {
-            //print_result(&entry.layout, entry.penalty, &entry.penalties, len);
-            best_layouts.push(entry);
+// Searches for better layouts on `quartads`, moving only the keys `mask`
+// leaves free.
+pub struct Optimizer<'a, S: Scorer + 'a> {
+    scorer: &'a S,
+    quartads: &'a QuartadList,
+    index: S::Index,
+    mask: &'a LayoutShuffleMask,
+    options: &'a OptimizerOptions,
+    interrupt: Option<&'a AtomicBool>,
+}
+
+impl<'a, S: Scorer> Optimizer<'a, S> {
+    pub fn new(
+        scorer: &'a S,
+        quartads: &'a QuartadList,
+        mask: &'a LayoutShuffleMask,
+        options: &'a OptimizerOptions,
+    ) -> Optimizer<'a, S> {
+        Optimizer {
+            scorer,
+            quartads,
+            index: scorer.index(quartads),
+            mask,
+            options,
+            interrupt: None,
         }
-        best_layouts.sort_unstable();
-        best_layouts.truncate(BEST_LAYOUTS_KEPT as usize);
     }
-    println!("................................................");
-    for entry in best_layouts {
-        print_result(&entry);
+
+    // Makes `anneal` stop as soon as `flag` is set, and hand its state to the
+    // progress callback before it returns `Error::Interrupted`.
+    pub fn interrupt_on(&mut self, flag: &'a AtomicBool) {
+        self.interrupt = Some(flag);
+    }
+
+    pub fn options(&self) -> &OptimizerOptions {
+        self.options
+    }
+
+    pub fn scorer(&self) -> &S {
+        self.scorer
     }
-}
 
-/*
-    pub fn refine<'a>(
-        quartads:    &penalty::QuartadList<'a>,
-    len:          usize,
-    init_layout: &layout::Layout,
-    penalties:   &Vec<penalty::KeyPenalty<'a>>,
-    debug:        bool,
-    top_layouts:  usize,
-    num_swaps:    usize)
-{
-    let penalty = penalty::calculate_penalty(&quartads, len, init_layout, penalties, true);
-
-    println!("Initial layout:");
-    print_result(init_layout, &penalty);
-
-    let mut curr_layout = init_layout.clone();
-    let mut curr_penalty = penalty.1;
-
-    loop {
-        // Test every layout within `num_swaps` swaps of the initial layout.
-        let mut best_layouts: Box<LL<BestLayoutsEntry>> = Box::new(LL{
-            item : BestLayoutsEntry{
-                layout: init_layout.clone(),
-                penalty: penalty::calculate_penalty(&quartads, len, &init_layout, penalties, false).1
-            },
-            len:1,
-            next : None
-        });
-        let permutations = layout::LayoutPermutations::new(init_layout, num_swaps);
-        for (i, layout) in permutations.enumerate() {
-            let penalty = penalty::calculate_penalty(&quartads, len, &layout, penalties, false);
-
-            if debug {
-                println!("Iteration {}: {}", i, penalty.1);
+    // Searches from the layouts of `population` the way the options say, and
+    // returns the best layouts found, best first.
+    pub fn optimize(
+        &self,
+        population: &[Layout],
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> Result<Vec<BestLayoutsEntry>, Error> {
+        match self.options.search {
+            Search::Annealing => self.anneal_population(population, progress),
+            Search::Genetic => self.evolve(population, progress),
+            Search::Tabu | Search::LateAcceptance => self.climb(population, progress),
+        }
+    }
+
+    // Anneals from `init_layout` and returns the best layouts found, best
+    // first.
+    pub fn anneal(
+        &self,
+        init_layout: &Layout,
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> Result<Vec<BestLayoutsEntry>, Error> {
+        self.anneal_population(std::slice::from_ref(init_layout), progress)
+    }
+
+    // Anneals from the layouts of `population`, each worker of the first
+    // iteration starting from the next one, and returns the best layouts
+    // found, best first. The whole population competes with what the first
+    // iteration finds.
+    pub fn anneal_population(
+        &self,
+        population: &[Layout],
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> Result<Vec<BestLayoutsEntry>, Error> {
+        let threads = num_cpus::get();
+        let state = AnnealState {
+            threads,
+            iteration: 1,
+            // in each iteration each thread takes a random layout and tries to optimalize it for `options.cycles` cycles;
+            //results are appended to bestLayouts, which is then sorted and truntcated back to best ten
+            best_layouts: self.starting_layouts(population, threads)?,
+            workers: Vec::new(),
+        };
+        self.resume(state, progress)
+    }
+
+    // The best layouts to start the first iteration of `anneal` or `climb`
+    // with: worker i starts from the i-th layout from the end, so the
+    // population goes in backwards, over and over until there are three
+    // layouts for each thread.
+    fn starting_layouts(&self, population: &[Layout], threads: usize) -> Result<Vec<Layout>, Error> {
+        if population.is_empty() {
+            return Err(Error::Invalid("no layouts to start from".to_string()));
+        }
+        let population = population
+            .iter()
+            .map(|layout| self.scorer.score(self.quartads, layout).map(|e| e.layout))
+            .collect::<Result<Vec<Layout>, Error>>()?;
+        let slots = population.len().max(threads * 3);
+        Ok((0..slots)
+            .map(|i| population[(slots - 1 - i) % population.len()].clone())
+            .collect())
+    }
+
+    // Carries on an annealing run from `state`.
+    pub fn resume(
+        &self,
+        state: AnnealState,
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> Result<Vec<BestLayoutsEntry>, Error> {
+        let options = self.options;
+        let best_layouts_kept = state.threads * 3;
+        let mut best_layouts = state
+            .best_layouts
+            .iter()
+            .map(|layout| self.scorer.score(self.quartads, layout))
+            .collect::<Result<Vec<BestLayoutsEntry>, Error>>()?;
+        let mut workers = state.workers;
+
+        for it_num in state.iteration..options.iterations + 1 {
+            if workers.is_empty() {
+                progress(Progress::Iteration(it_num));
+                workers = (0..state.threads)
+                    .map(|i| self.start_worker(&best_layouts[best_layouts.len() - 1 - i], it_num, i))
+                    .collect();
             }
 
-            // Insert this layout into best layouts.
-            let new_entry = BestLayoutsEntry {
-                layout: layout,
-                penalty: penalty.1,
+            // Run the workers a stretch of cycles at a time, with a checkpoint
+            // after each.
+            let iteration: Vec<BestLayoutsEntry> = loop {
+                let until = match options.checkpoint_cycles {
+                    0 => options.cycles,
+                    n => (workers.iter().map(|w| w.cycle).min().unwrap_or(0) / n + 1) * n,
+                };
+                let done: Vec<(WorkerState, Option<BestLayoutsEntry>)> = workers
+                    .into_par_iter()
+                    .enumerate()
+                    .map(|(i, worker)| {
+                        let entry = &best_layouts[best_layouts.len() - 1 - i];
+                        self.run_worker(worker, it_num, i, until.min(options.cycles), entry, progress)
+                    })
+                    .collect::<Result<_, Error>>()?;
+                let (states, results): (Vec<_>, Vec<_>) = done.into_iter().unzip();
+                workers = states;
+                if results.iter().all(|r| r.is_some()) {
+                    break results.into_iter().flatten().collect();
+                }
+
+                let interrupted = self.interrupt.is_some_and(|f| f.load(Ordering::SeqCst));
+                if interrupted || options.checkpoint_cycles > 0 {
+                    progress(Progress::Checkpoint(&AnnealState {
+                        threads: state.threads,
+                        iteration: it_num,
+                        best_layouts: best_layouts.iter().map(|e| e.layout.clone()).collect(),
+                        workers: workers.clone(),
+                    }));
+                }
+                if interrupted {
+                    return Err(Error::Interrupted(format!("interrupted in iteration {}", it_num)));
+                }
             };
-            best_layouts = list_insert_ordered(best_layouts, new_entry);
+            workers.clear();
 
-            // Limit best layouts list length.
-            while best_layouts.len > top_layouts {
-                best_layouts = best_layouts.next.unwrap();
+            for entry in iteration {
+                best_layouts.push(entry);
             }
+            best_layouts.sort_unstable();
+            best_layouts.truncate(best_layouts_kept);
         }
+        Ok(best_layouts)
+    }
 
-        let mut lay= Some(best_layouts);
-        // Print the top layouts.
-        while let Some(ll )= lay{
-            let entry = ll.item;
-            let ref layout = entry.layout;
-            let penalty = penalty::calculate_penalty(&quartads, len, &layout, penalties, true);
-            println!("");
-            print_result(&layout, &penalty);
-            lay = ll.next;
+    // Worker `worker` of iteration `iteration`, about to start from `entry`.
+    fn start_worker(&self, entry: &BestLayoutsEntry, iteration: usize, worker: usize) -> WorkerState {
+        // Each worker gets its own generator so that a run does not
+        // depend on how rayon schedules the work.
+        let mut rng = worker_rng(self.options.seed, iteration, worker);
+        let print_frequency = rng.gen::<usize>() % 5000 + 5000;
+        let mut layout = entry.layout.clone();
+        if iteration == 1 {
+            layout.shuffle(self.options.warm_up, self.mask, &mut rng);
         }
+        let evaluator = self.scorer.evaluator(&self.index, &layout);
+        WorkerState {
+            cycle: 0,
+            rng_pos: rng.get_word_pos(),
+            print_frequency,
+            layout: layout.clone(),
+            accepted_total: evaluator.total(),
+            sums: evaluator.sums(),
+            best_layout: layout,
+            best_total: evaluator.total(),
+        }
+    }
+
+    // Runs `state` up to cycle `until`, or until interrupted. A worker that
+    // has run all its cycles comes back with the best layout it found.
+    fn run_worker(
+        &self,
+        mut state: WorkerState,
+        iteration: usize,
+        worker: usize,
+        until: usize,
+        entry: &BestLayoutsEntry,
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> Result<(WorkerState, Option<BestLayoutsEntry>), Error> {
+        let options = self.options;
+        let num_swaps = options.num_swaps;
+        let mut rng = worker_rng(options.seed, iteration, worker);
+        rng.set_word_pos(state.rng_pos);
+        let mut evaluator = self.scorer.evaluator(&self.index, &state.layout);
+        evaluator.set_sums(&state.sums);
+
+        while state.cycle < until {
+            if self.interrupt.is_some_and(|f| f.load(Ordering::Relaxed)) {
+                break;
+            }
+            state.cycle += 1;
+            let cycle = state.cycle;
+
+            let swaps: Vec<(usize, usize)> = (0..rng.gen::<usize>() % num_swaps.max(1) + 1)
+                .map(|_| Layout::shuffle_position(self.mask, &mut rng))
+                .collect();
+            evaluator.swap(&swaps);
+            let total = evaluator.total();
 
-        // Keep going until swapping doesn't get us any more improvements.
-        let best = best_layouts.item;
-        if curr_penalty <= best.penalty {
-            break;
-        } else {
-            curr_layout = best.layout;
-            curr_penalty = best.penalty;
+            if total < state.best_total {
+                state.best_layout = evaluator.layout().clone();
+                state.best_total = total;
+            }
+            // Probabilistically accept worse transitions; always accept better
+            // transitions.
+            if options.annealing.accept_transition(
+                (total - state.accepted_total) / state.accepted_total,
+                cycle,
+                &mut rng,
+            ) {
+                state.accepted_total = total;
+            } else {
+                evaluator.undo();
+            }
+            if cycle.is_multiple_of(state.print_frequency) {
+                progress(Progress::Best(&state.best_layout));
+            }
+        }
+
+        state.rng_pos = rng.get_word_pos();
+        state.layout = evaluator.layout().clone();
+        state.sums = evaluator.sums();
+        if state.cycle < options.cycles {
+            return Ok((state, None));
         }
+        let best = self.scorer.score(self.quartads, &state.best_layout)?;
+        progress(Progress::Worker(entry, &best));
+        Ok((state, Some(best)))
     }
 
-    println!("");
-    println!("Ultimate winner:");
-    println!("{}", curr_layout);
-}
-*/
-pub fn print_result<'a>(item: &BestLayoutsEntry) {
-    let layout = &item.layout;
-    let total = item.penalty.total;
-    let len = item.penalty.len;
-    let penalties = &item.penalty.penalties;
-    let penalty = &item.penalty;
-    let fingers = &penalty.fingers;
-    let show_all = false;
-    print!(
-        "{}{}{}{}{}{}{}{}{}",
-        format!("\n{}\n", layout),
-        format!(
-            "total: {0:<10.2}; scaled: {1:<10.4}\n",
-            total,
-            total / (len as f64)
-        ),
-        //format!("base {}\n",penalties[0]),
-        format!(
-            "\n{:<30} | {:^7} | {:^7} | {:^8} | {:<10}\n",
-            "Name", "% times", "Avg", "% Total", "Total"
-        ),
-        "----------------------------------------------------------------------\n",
-        penalties
-            .into_iter()
-            .map(|penalty| {
-                if penalty.show || show_all {
-                    format!(
-                        "{:<30} | {:<7.2} | {:<7.3} | {:<8.3} | {:<10.0}\n",
-                        penalty.name,
-                        (100.0 * penalty.times / (len as f64)),
-                        penalty.total / (len as f64),
-                        100.0 * penalty.total / total,
-                        penalty.total
-                    )
-                } else {
-                    "".to_string()
+    // Evolves a generation grown from the layouts of `population` for
+    // `generations` generations and returns the best layouts of the last one,
+    // best first. Each child is scored in full rather than a swap at a time,
+    // so a generation takes about as long as `population` times the swaps of
+    // an annealing cycle.
+    pub fn evolve(
+        &self,
+        population: &[Layout],
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> Result<Vec<BestLayoutsEntry>, Error> {
+        if population.is_empty() {
+            return Err(Error::Invalid("no layouts to evolve from".to_string()));
+        }
+        let options = self.options;
+        let threads = num_cpus::get();
+        let size = options.population.max(genetic::ELITE + 1);
+        let children = size - genetic::ELITE;
+        for layout in population {
+            self.scorer.score(self.quartads, layout)?;
+        }
+
+        // The first generation: the starting layouts, and copies of them
+        // spread out with random swaps.
+        let mut members: Vec<(Layout, f64)> = (0..size)
+            .into_par_iter()
+            .map(|i| {
+                let mut layout = population[i % population.len()].clone();
+                if i >= population.len() {
+                    let mut rng = worker_rng(options.seed, 0, i);
+                    let swaps = match options.warm_up {
+                        0 => self.mask.positions().len(),
+                        n => n,
+                    };
+                    layout.shuffle(swaps, self.mask, &mut rng);
                 }
+                let total = self.total(&layout);
+                (layout, total)
             })
-            .collect::<Vec<_>>()
-            .join(""),
-        "----------------------------------------------------------------------\n",
-        format!(
-            "\n{:^5.1} {:^5.1} {:^5.1} {:^5.1} | {:^5.1} {:^5.1} {:^5.1} {:^5.1}\n",
-            fingers[0] as f64 * 100.0 / len as f64 ,
-            fingers[1] as f64 * 100.0 / len as f64 ,
-            fingers[2] as f64 * 100.0 / len as f64 ,
-            fingers[3] as f64 * 100.0 / len as f64 ,
-            fingers[7] as f64 * 100.0 / len as f64 ,
-            fingers[6] as f64 * 100.0 / len as f64 ,
-            fingers[5] as f64 * 100.0 / len as f64 ,
-            fingers[4] as f64 * 100.0 / len as f64 
-        ),
-
-        format!("{:^5.1}| {:^5.1}\n", penalty.hands[0] as f64 * 100.0 / len as f64 , penalty.hands[1] as f64 * 100.0 / len as f64 ),
-        "##########################################################################\n"
-    );
+            .collect();
+        sort_members(&mut members);
+
+        for generation in 1..options.generations + 1 {
+            // Every child has its own generator, as annealing workers do.
+            let totals: Vec<f64> = members.iter().map(|m| m.1).collect();
+            let offspring: Vec<(Layout, f64)> = (0..children)
+                .into_par_iter()
+                .map(|i| {
+                    let mut rng = worker_rng(options.seed, generation, i);
+                    let a = &members[genetic::tournament(&totals, &mut rng)].0;
+                    let b = &members[genetic::tournament(&totals, &mut rng)].0;
+                    let mut child = options
+                        .crossover
+                        .cross(a, b, self.mask, &mut rng)
+                        .unwrap_or_else(|| a.clone());
+                    genetic::mutate(&mut child, options.num_swaps, self.mask, &mut rng);
+                    let total = self.total(&child);
+                    (child, total)
+                })
+                .collect();
+            let best = members[0].1;
+            members.truncate(genetic::ELITE);
+            members.extend(offspring);
+            sort_members(&mut members);
+            if members[0].1 < best {
+                progress(Progress::Generation(generation, members[0].1));
+            }
+        }
+
+        let mut best_layouts: Vec<BestLayoutsEntry> = Vec::new();
+        for (layout, _) in members {
+            if best_layouts.len() == threads * 3 {
+                break;
+            }
+            if !best_layouts.iter().any(|e| e.layout == layout) {
+                best_layouts.push(self.scorer.score(self.quartads, &layout)?);
+            }
+        }
+        best_layouts.sort();
+        Ok(best_layouts)
+    }
+
+    // Runs tabu search or late acceptance hill climbing from the layouts of
+    // `population`, in iterations of a climb on each thread as `anneal` does,
+    // and returns the best layouts found, best first.
+    pub fn climb(
+        &self,
+        population: &[Layout],
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> Result<Vec<BestLayoutsEntry>, Error> {
+        let options = self.options;
+        let threads = num_cpus::get();
+        let mut best_layouts = self
+            .starting_layouts(population, threads)?
+            .iter()
+            .map(|layout| self.scorer.score(self.quartads, layout))
+            .collect::<Result<Vec<BestLayoutsEntry>, Error>>()?;
+
+        for it_num in 1..options.iterations + 1 {
+            progress(Progress::Iteration(it_num));
+            let iteration = (0..threads)
+                .into_par_iter()
+                .map(|i| {
+                    let entry = &best_layouts[best_layouts.len() - 1 - i];
+                    let mut rng = worker_rng(options.seed, it_num, i);
+                    let best_layout = match options.search {
+                        Search::Tabu => self.tabu_search(&entry.layout, &mut rng),
+                        _ => self.late_acceptance(&entry.layout, &mut rng),
+                    };
+                    let best = self.scorer.score(self.quartads, &best_layout)?;
+                    progress(Progress::Worker(entry, &best));
+                    Ok(best)
+                })
+                .collect::<Result<Vec<BestLayoutsEntry>, Error>>()?;
+
+            best_layouts.extend(iteration);
+            best_layouts.sort_unstable();
+            best_layouts.truncate(threads * 3);
+        }
+        Ok(best_layouts)
+    }
+
+    // Tabu search from `layout` for `cycles` swaps looked at. Returns the best
+    // layout it found.
+    fn tabu_search(&self, layout: &Layout, rng: &mut ChaCha20Rng) -> Layout {
+        let options = self.options;
+        let mut evaluator = self.scorer.evaluator(&self.index, layout);
+        let mut tabu = TabuList::new(options.tabu_tenure);
+        let mut best_layout = layout.clone();
+        let mut best_total = evaluator.total();
+
+        let mut cycle = 0;
+        while cycle < options.cycles {
+            // The best of a few swaps, leaving out those on the tabu list
+            // unless they beat the best layout so far.
+            let mut chosen: Option<((usize, usize), f64)> = None;
+            for _ in 0..search::CANDIDATES.min(options.cycles - cycle) {
+                cycle += 1;
+                let (i, j) = Layout::shuffle_position(self.mask, rng);
+                if i == j {
+                    continue;
+                }
+                evaluator.swap(&[(i, j)]);
+                let total = evaluator.total();
+                evaluator.undo();
+                if tabu.contains(i, j) && total >= best_total {
+                    continue;
+                }
+                if chosen.is_none_or(|(_, t)| total < t) {
+                    chosen = Some(((i, j), total));
+                }
+            }
+
+            if let Some(((i, j), total)) = chosen {
+                evaluator.swap(&[(i, j)]);
+                tabu.push(i, j);
+                if total < best_total {
+                    best_layout = evaluator.layout().clone();
+                    best_total = total;
+                }
+            }
+        }
+        best_layout
+    }
+
+    // Late acceptance hill climbing from `layout` for `cycles` cycles. Returns
+    // the best layout it found.
+    fn late_acceptance(&self, layout: &Layout, rng: &mut ChaCha20Rng) -> Layout {
+        let options = self.options;
+        let mut evaluator = self.scorer.evaluator(&self.index, layout);
+        let mut current = evaluator.total();
+        let mut history = LateAcceptance::new(options.history, current);
+        let mut best_layout = layout.clone();
+        let mut best_total = current;
+
+        for cycle in 0..options.cycles {
+            let swaps: Vec<(usize, usize)> = (0..rng.gen::<usize>() % options.num_swaps.max(1) + 1)
+                .map(|_| Layout::shuffle_position(self.mask, rng))
+                .collect();
+            evaluator.swap(&swaps);
+            let total = evaluator.total();
+
+            if total < best_total {
+                best_layout = evaluator.layout().clone();
+                best_total = total;
+            }
+            if history.accept(cycle, current, total) {
+                current = total;
+            } else {
+                evaluator.undo();
+            }
+        }
+        best_layout
+    }
+
+    // The total of `layout`.
+    fn total(&self, layout: &Layout) -> f64 {
+        self.scorer.evaluator(&self.index, layout).total()
+    }
+
+    // Hill-climbs from `init_layout` until no combination of up to
+    // `num_swaps` swaps improves on the best layout.
+    pub fn refine(
+        &self,
+        init_layout: &Layout,
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> Result<Refined, Error> {
+        let num_swaps = self.options.num_swaps;
+        let top_layouts = self.options.top_layouts;
+        let initial = self.scorer.score(self.quartads, init_layout)?;
+
+        let mut curr = initial.clone();
+        let mut best_layouts = vec![initial];
+        let mut round = 0;
+
+        loop {
+            round += 1;
+
+            // Test every layout within `num_swaps` swaps of the current layout,
+            // keeping the best few. Ties are broken by enumeration order so the
+            // result does not depend on how rayon splits the work.
+            let evaluator = self.scorer.evaluator(&self.index, &curr.layout);
+            let neighbours = layout::SwapCombinations::new(num_swaps, self.mask)
+                .enumerate()
+                .par_bridge()
+                .map_init(
+                    || evaluator.clone_box(),
+                    |evaluator, (i, swaps)| {
+                        evaluator.swap(&swaps);
+                        let total = evaluator.total();
+                        evaluator.undo();
+                        (total, i, swaps)
+                    },
+                )
+                .fold(Vec::new, |top, entry| keep_top(top, vec![entry], top_layouts))
+                .reduce(Vec::new, |a, b| keep_top(a, b, top_layouts));
+
+            for (_, _, swaps) in &neighbours {
+                let mut layout = curr.layout.clone();
+                for &(a, b) in swaps {
+                    layout.swap(a, b);
+                }
+                if !best_layouts.iter().any(|e| e.layout == layout) {
+                    best_layouts.push(self.scorer.score(self.quartads, &layout)?);
+                }
+            }
+            best_layouts.sort();
+            best_layouts.truncate(top_layouts.max(1));
+
+            progress(Progress::Round(
+                round,
+                curr.penalty.total,
+                best_layouts[0].penalty.total,
+            ));
+
+            // Keep going until swapping doesn't get us any more improvements.
+            if best_layouts[0].penalty.total < curr.penalty.total {
+                curr = best_layouts[0].clone();
+            } else {
+                break;
+            }
+        }
+
+        Ok(Refined {
+            top_layouts: best_layouts,
+            winner: curr,
+        })
+    }
 }
-/*
-// Take ownership of the list and give it back as a hack to make the borrow checker happy :^)
-fn list_insert_ordered(list: &mut Box<LL<BestLayoutsEntry>>, entry: BestLayoutsEntry)
-{
-    let mut cur = list;
-    loop {
-        if cur.item.cmp(&entry) == Ordering::Less{
-            //std::mem::swap(&mut entry, cur.item)
-            let tmp = BestLayoutsEntry{
-                layout:  cur.item.layout.clone(),
-                penalty: cur.item.penalty
+
+impl<'a> Optimizer<'a, Model> {
+    // Anneals from the layouts of `population` towards the trade-offs between
+    // `objectives`, and returns the Pareto front of the layouts found (see
+    // `pareto`), best weighted sum first. Each iteration anneals on the model
+    // with the weights of the objectives scaled: the first by their own
+    // weights, the others by random factors between 1/4 and 4 on top, so that
+    // each one favours different objectives. Every worker starts from a
+    // member of the front so far. Worker reports are on the scaled model.
+    pub fn pareto(
+        &self,
+        population: &[Layout],
+        objectives: &Objectives,
+        progress: &(dyn Fn(Progress) + Sync),
+    ) -> Result<Vec<BestLayoutsEntry>, Error> {
+        let options = self.options;
+        let threads = num_cpus::get();
+        let mut front = Front::new(objectives);
+        let mut starts = self.starting_layouts(population, threads)?;
+
+        for it_num in 1..options.iterations + 1 {
+            progress(Progress::Iteration(it_num));
+            // Workers use the generators of their own numbers; the one after
+            // them is free for the scales.
+            let mut rng = worker_rng(options.seed, it_num, threads);
+            let scales: Vec<f64> = objectives
+                .keys()
+                .iter()
+                .map(|_| if it_num == 1 { 1.0 } else { 4f64.powf(rng.gen::<f64>() * 2.0 - 1.0) })
+                .collect();
+            let model = objectives.model(self.scorer, &scales);
+            let scaled = Optimizer {
+                scorer: &model,
+                quartads: self.quartads,
+                index: model.index(self.quartads),
+                mask: self.mask,
+                options,
+                interrupt: None,
             };
-            cur.item = entry;
-            let entry = tmp;
-
-            let mut node = Box::new(LL {
-                item : entry,
-                len:cur.len,
-                next : None
-            });
-            let rest = cur.next;
-            node.next = rest;
-            cur.next = Some(node);
-            cur.len+=1;
-
-            break;
+            let entries = starts
+                .iter()
+                .map(|layout| model.score(self.quartads, layout))
+                .collect::<Result<Vec<BestLayoutsEntry>, Error>>()?;
+            let found: Vec<Option<BestLayoutsEntry>> = (0..threads)
+                .into_par_iter()
+                .map(|i| {
+                    let entry = &entries[entries.len() - 1 - i];
+                    let worker = scaled.start_worker(entry, it_num, i);
+                    scaled
+                        .run_worker(worker, it_num, i, options.cycles, entry, progress)
+                        .map(|(_, best)| best)
+                })
+                .collect::<Result<_, Error>>()?;
+
+            for entry in found.into_iter().flatten() {
+                front.insert(self.scorer.score(self.quartads, &entry.layout)?);
+            }
+            let members: Vec<Layout> = front.members().into_iter().map(|e| e.layout.clone()).collect();
+            starts = self.starting_layouts(&members, threads)?;
+        }
+
+        // The starting layouts compete too.
+        for layout in population {
+            front.insert(self.scorer.score(self.quartads, layout)?);
         }
+        Ok(front.members().into_iter().cloned().collect())
     }
+}
+
+// The generator of worker `worker` in iteration `iteration`, derived from
+// `seed` with a splitmix64 step so that neighbouring workers get unrelated
+// streams.
+fn worker_rng(seed: u64, iteration: usize, worker: usize) -> ChaCha20Rng {
+    let mut z = seed
+        .wrapping_add((iteration as u64) << 32)
+        .wrapping_add(worker as u64)
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    ChaCha20Rng::seed_from_u64(z ^ (z >> 31))
+}
 
-}*/
+// Sorts the members of a generation, best first, keeping ties in order.
+fn sort_members(members: &mut [(Layout, f64)]) {
+    members.sort_by(|x, y| x.1.partial_cmp(&y.1).unwrap_or(std::cmp::Ordering::Equal));
+}
+
+type Neighbour = (f64, usize, Vec<(usize, usize)>);
+
+fn keep_top(mut a: Vec<Neighbour>, b: Vec<Neighbour>, n: usize) -> Vec<Neighbour> {
+    a.extend(b);
+    a.sort_by(|x, y| {
+        x.0.partial_cmp(&y.0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(x.1.cmp(&y.1))
+    });
+    a.truncate(n.max(1));
+    a
+}
@@ -0,0 +1,132 @@
+//! Checks that a layout is whole before it is scored: `keygen validate`.
+//!
+//! A layout can put a character on two keys of a layer, of which only the
+//! last is ever typed, leave keys without a character, and miss characters
+//! of the corpus altogether. The penalty model scores such a layout all the
+//! same, as if the missing characters were never typed.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use layout::Layout;
+
+pub struct Validation
+{
+	// Characters on more than one key of a layer: the character, the layer
+	// and the keys.
+	pub duplicates: Vec<(char, usize, Vec<usize>)>,
+	// Keys without a character, as (layer, key).
+	pub empty:      Vec<(usize, usize)>,
+	// The characters of the corpus the layout cannot type and their counts,
+	// most frequent first.
+	pub untypeable: Vec<(char, i64)>,
+	// The number of characters in the corpus.
+	pub chars:      i64,
+}
+
+impl Validation
+{
+	// Checks `layout` against a corpus with the character counts `chars`.
+	pub fn new(layout: &Layout, chars: &HashMap<char, i64>)
+	-> Validation
+	{
+		let map = layout.get_position_map();
+		let mut untypeable: Vec<(char, i64)> = chars.iter()
+			.filter(|&(&c, _)| map.get_key_position(c).is_none())
+			.map(|(&c, &count)| (c, count))
+			.collect();
+		untypeable.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+		Validation {
+			duplicates: layout.duplicates(),
+			empty: layout.empty_keys(),
+			untypeable,
+			chars: chars.values().sum(),
+		}
+	}
+
+	// Whether the layout types every character of the corpus from one key.
+	// Empty keys are fine.
+	pub fn is_complete(&self)
+	-> bool
+	{
+		self.duplicates.is_empty() && self.untypeable.is_empty()
+	}
+
+	// The share of the corpus the layout cannot type, in percent.
+	pub fn untypeable_percent(&self)
+	-> f64
+	{
+		let count: i64 = self.untypeable.iter().map(|&(_, count)| count).sum();
+		percent(count, self.chars)
+	}
+
+	// What is wrong with the layout, in one line.
+	pub fn summary(&self)
+	-> String
+	{
+		let mut problems = Vec::new();
+		if !self.duplicates.is_empty() {
+			problems.push(format!("{} characters on two keys of a layer", self.duplicates.len()));
+		}
+		if !self.untypeable.is_empty() {
+			problems.push(format!(
+				"cannot type {:.2}% of the corpus ({} characters)",
+				self.untypeable_percent(), self.untypeable.len()));
+		}
+		if problems.is_empty() {
+			"complete".to_string()
+		} else {
+			problems.join(", ")
+		}
+	}
+}
+
+impl fmt::Display for Validation
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		if self.duplicates.is_empty() {
+			writeln!(f, "Duplicates: none")?;
+		} else {
+			writeln!(f, "Duplicates:")?;
+			for &(c, n, ref keys) in &self.duplicates {
+				let keys: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+				writeln!(f, "  {:?} on keys {} of layer {}", c, keys.join(", "), n)?;
+			}
+		}
+
+		if self.empty.is_empty() {
+			writeln!(f, "Empty keys: none")?;
+		} else {
+			let mut layers: Vec<String> = Vec::new();
+			let mut last = None;
+			for &(n, i) in &self.empty {
+				if last == Some(n) {
+					layers.last_mut().unwrap().push_str(&format!(", {}", i));
+				} else {
+					layers.push(format!("layer {}: {}", n, i));
+					last = Some(n);
+				}
+			}
+			writeln!(f, "Empty keys: {}", layers.join("; "))?;
+		}
+
+		if self.untypeable.is_empty() {
+			writeln!(f, "Cannot type: nothing")
+		} else {
+			writeln!(f, "Cannot type: {:.2}% of the corpus", self.untypeable_percent())?;
+			for &(c, count) in &self.untypeable {
+				writeln!(f, "  {:<8} {:>6.2}% {:>10}", format!("{:?}", c), percent(count, self.chars), count)?;
+			}
+			Ok(())
+		}
+	}
+}
+
+fn percent(count: i64, total: i64)
+-> f64
+{
+	if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 }
+}
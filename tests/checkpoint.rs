@@ -0,0 +1,72 @@
+//! Tests for `keygen run --checkpoint` and `keygen resume`.
+
+use std::env;
+use std::fs;
+use std::process::Output;
+
+mod common;
+
+use common::keygen;
+
+// The output from the last iteration on, which a resumed run prints too.
+fn last_iteration(output: &Output)
+-> String
+{
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	let start = stdout.find("......").expect("no last iteration in the output");
+	stdout[start..].to_string()
+}
+
+#[test]
+fn resume_finishes_the_run()
+{
+	let file = env::temp_dir().join(format!("keygen-resume-{}.ckpt", std::process::id()));
+	let file = file.to_str().unwrap();
+	let run = keygen(&[
+		"run", "corpus/books.veryshort.txt", "--seed", "3", "--iterations", "2", "--cycles", "2000",
+		"--checkpoint", file, "--checkpoint-every", "500"]);
+	assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+
+	// The last checkpoint is from the middle of the last iteration.
+	let checkpoint = fs::read_to_string(file).expect("no checkpoint");
+	assert!(checkpoint.starts_with("# keygen checkpoint\n"), "{}", checkpoint);
+	assert!(checkpoint.contains("\niteration 2\n"), "{}", checkpoint);
+	assert!(checkpoint.contains("\nworker 1500 "), "{}", checkpoint);
+
+	let resumed = keygen(&["resume", file]);
+	fs::remove_file(file).ok();
+	assert!(resumed.status.success(), "{}", String::from_utf8_lossy(&resumed.stderr));
+	assert!(String::from_utf8_lossy(&resumed.stdout).starts_with("resuming iteration 2 of 2\n"));
+	assert_eq!(last_iteration(&resumed), last_iteration(&run));
+}
+
+#[test]
+fn resume_needs_a_checkpoint()
+{
+	let output = keygen(&["resume", "tests/data/thumb-shift-layout.txt"]);
+	assert_eq!(output.status.code(), Some(4));
+	assert!(String::from_utf8_lossy(&output.stderr).contains("not a checkpoint"));
+}
+
+#[test]
+fn resume_keeps_a_drawn_seed()
+{
+	let file = env::temp_dir().join(format!("keygen-resume-seed-{}.ckpt", std::process::id()));
+	let file = file.to_str().unwrap();
+	let run = keygen(&[
+		"run", "corpus/books.veryshort.txt", "--iterations", "2", "--cycles", "2000",
+		"--checkpoint", file, "--checkpoint-every", "500"]);
+	assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+	let stdout = String::from_utf8_lossy(&run.stdout);
+	let seed = stdout.lines().find_map(|line| line.strip_prefix("seed: ")).expect("no seed in the output");
+
+	let checkpoint = fs::read_to_string(file).expect("no checkpoint");
+	assert!(checkpoint.contains(&format!(" --seed {}\n", seed)), "{}", checkpoint);
+	let resumed = keygen(&["resume", file]);
+	fs::remove_file(file).ok();
+	assert!(resumed.status.success(), "{}", String::from_utf8_lossy(&resumed.stderr));
+
+	let uninterrupted = keygen(&["run", "corpus/books.veryshort.txt", "--iterations", "2", "--cycles", "2000", "--seed", seed]);
+	assert!(uninterrupted.status.success(), "{}", String::from_utf8_lossy(&uninterrupted.stderr));
+	assert_eq!(last_iteration(&resumed), last_iteration(&uninterrupted));
+}
@@ -0,0 +1,15 @@
+//! Helpers shared by the tests that run the keygen binary.
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+// Runs keygen with `args` from the root of the crate.
+pub fn keygen(args: &[&str])
+-> Output
+{
+	Command::new(env!("CARGO_BIN_EXE_keygen"))
+		.current_dir(Path::new(env!("CARGO_MANIFEST_DIR")))
+		.args(args)
+		.output()
+		.expect("could not run keygen")
+}
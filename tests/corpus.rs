@@ -0,0 +1,25 @@
+//! Tests for corpus options.
+
+use std::env;
+use std::fs;
+
+mod common;
+
+use common::keygen;
+
+#[test]
+fn options_need_text()
+{
+	let ngrams = env::temp_dir().join(format!("keygen-corpus-{}.ngrams", std::process::id()));
+	let ngrams = ngrams.to_str().unwrap();
+	let output = keygen(&["corpus-stats", "corpus/books.veryshort.txt", "-o", ngrams]);
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+	let read = keygen(&["run-ref", ngrams]);
+	let lowercase = keygen(&["run-ref", ngrams, "--lowercase"]);
+	fs::remove_file(ngrams).ok();
+	assert!(read.status.success(), "{}", String::from_utf8_lossy(&read.stderr));
+	assert_eq!(lowercase.status.code(), Some(2));
+	let stderr = String::from_utf8_lossy(&lowercase.stderr);
+	assert!(stderr.contains(&format!("{} is an n-gram file: --lowercase", ngrams)), "{}", stderr);
+}
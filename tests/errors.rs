@@ -0,0 +1,68 @@
+//! Tests for the exit codes and messages keygen stops with.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+mod common;
+
+use common::keygen;
+
+// Runs keygen on a file holding `contents` in place of `{}` in `args`, and
+// checks that it exits with `code` and says `message`.
+fn check_file(name: &str, contents: &str, args: &[&str], code: i32, message: &str)
+{
+	let file = env::temp_dir().join(format!("keygen-errors-{}-{}", std::process::id(), name));
+	let file = file.to_str().unwrap();
+	fs::write(file, contents).expect("could not write the file");
+	let args: Vec<&str> = args.iter().map(|&a| if a == "{}" { file } else { a }).collect();
+	let output = keygen(&args);
+	fs::remove_file(file).ok();
+	check(&output, code, &format!("Error: {}: {}", file, message));
+}
+
+fn check(output: &Output, code: i32, message: &str)
+{
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	assert_eq!(output.status.code(), Some(code), "{}", stderr);
+	assert!(stderr.contains(message), "expected `{}` in:\n{}", message, stderr);
+}
+
+#[test]
+fn bad_option()
+{
+	check(&keygen(&["run-ref", "corpus/books.veryshort.txt", "--bogus"]), 2, "Error: Unrecognized option: 'bogus'");
+	check(&keygen(&["run", "corpus/books.veryshort.txt", "--cycles", "many"]), 2, "Error: --cycles");
+	check(&keygen(&["run", "corpus/books.veryshort.txt", "-s", "0", "--cycles", "10", "--iterations", "1"]), 2, "Error: -s: at least one swap is needed");
+	check(&keygen(&["shuffle", "corpus/books.veryshort.txt"]), 2, "Error: unknown command `shuffle`");
+	check(&keygen(&["run", "corpus/books.veryshort.txt", "--objectives", "same_fingers"]), 2, "Error: --objectives: unknown penalty `same_fingers`");
+	check(&keygen(&["run", "corpus/books.veryshort.txt", "--scalarize"]), 2, "Error: --scalarize needs --objectives");
+}
+
+#[test]
+fn missing_file()
+{
+	check(&keygen(&["run-ref", "corpus/missing.txt"]), 3, "Error: corpus/missing.txt: ");
+	check(&keygen(&["refine", "corpus/books.veryshort.txt", "layouts/missing.txt"]), 3, "Error: layouts/missing.txt: ");
+}
+
+#[test]
+fn malformed_layout()
+{
+	check_file("short.txt", "qwert yuiop\n", &["refine", "corpus/books.veryshort.txt", "{}"], 4, "line 2, column 2: the file ends before key 11 of layer 0");
+	let layout = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/thumb-shift-layout.txt"))
+		.expect("could not read the layout");
+	check_file(
+		"between.txt", &layout.replacen("qwert yuiop", "qwertxyuiop", 1), &["refine", "corpus/books.veryshort.txt", "{}"],
+		4, "line 1, column 6: `x` is not on a key of the geometry");
+}
+
+#[test]
+fn invalid_model()
+{
+	let args = ["run-ref", "corpus/books.veryshort.txt", "-m", "{}"];
+	check_file("section.toml", "[colours]\nred = 1\n", &args, 4, "line 2: unknown section [colours]");
+	check_file("negative.toml", "[load]\nhands = [-1, 2]\n", &args, 4, "line 2: hands has the target -1, expected a number of at least 0");
+	check_file("zero.toml", "[load]\nhands = [0, 0]\n", &args, 4, "line 2: hands has no target above 0");
+}
@@ -0,0 +1,92 @@
+//! Golden-file tests for `keygen export`. Run with `KEYGEN_BLESS=1` to write
+//! the golden files from the current output after a deliberate change.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Exports `args` to `format` and compares the output with `golden`.
+fn check(args: &[&str], format: &str, golden: &str)
+{
+	let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+	let output = Command::new(env!("CARGO_BIN_EXE_keygen"))
+		.current_dir(root)
+		.arg("export")
+		.args(args)
+		.args(["--to", format])
+		.output()
+		.expect("could not run keygen");
+	assert!(output.status.success(), "keygen export failed:\n{}", String::from_utf8_lossy(&output.stderr));
+
+	let path = root.join("tests").join("golden").join(golden);
+	if env::var_os("KEYGEN_BLESS").is_some() {
+		fs::write(&path, &output.stdout).expect("could not write golden file");
+		return;
+	}
+	let expected = fs::read(&path).expect("could not read golden file");
+	assert!(output.stdout == expected, "{} differs from the export:\n{}", golden, String::from_utf8_lossy(&output.stdout));
+}
+
+const THUMB_SHIFT: [&str; 3] = ["tests/data/thumb-shift-layout.txt", "-g", "tests/data/thumb-shift.txt"];
+
+#[test]
+fn qwerty_qmk()
+{
+	check(&[], "qmk", "qwerty.keymap.c");
+}
+
+#[test]
+fn qwerty_zmk()
+{
+	check(&[], "zmk", "qwerty.keymap");
+}
+
+#[test]
+fn qwerty_xkb()
+{
+	check(&[], "xkb", "qwerty.xkb");
+}
+
+#[test]
+fn qwerty_keylayout()
+{
+	check(&[], "keylayout", "qwerty.keylayout");
+}
+
+#[test]
+fn qwerty_klc()
+{
+	check(&[], "klc", "qwerty.klc");
+}
+
+#[test]
+fn thumb_shift_qmk()
+{
+	check(&THUMB_SHIFT, "qmk", "thumb-shift.keymap.c");
+}
+
+#[test]
+fn thumb_shift_zmk()
+{
+	check(&THUMB_SHIFT, "zmk", "thumb-shift.keymap");
+}
+
+#[test]
+fn thumb_shift_xkb()
+{
+	check(&THUMB_SHIFT, "xkb", "thumb-shift.xkb");
+}
+
+#[test]
+fn no_room_on_ansi()
+{
+	let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+	let output = Command::new(env!("CARGO_BIN_EXE_keygen"))
+		.current_dir(root)
+		.args(["export", "tests/data/corne-layout.kbd", "-g", "geometries/corne.txt", "--to", "xkb"])
+		.output()
+		.expect("could not run keygen");
+	assert_eq!(output.status.code(), Some(5));
+	assert!(String::from_utf8_lossy(&output.stderr).contains("has no place on an ANSI board"));
+}
@@ -0,0 +1,48 @@
+//! Tests for machine-readable output.
+
+use std::env;
+use std::fs;
+
+mod common;
+
+use common::keygen;
+
+#[test]
+fn json_without_keystrokes()
+{
+	// No character of the corpus is on any reference layout, so there is
+	// nothing to take shares of.
+	let corpus = env::temp_dir().join(format!("keygen-format-{}.txt", std::process::id()));
+	fs::write(&corpus, "ññññññññ").expect("could not write the corpus");
+	let output = keygen(&["run-ref", corpus.to_str().unwrap(), "-f", "json"]);
+	fs::remove_file(&corpus).ok();
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	assert!(stdout.lines().count() > 1);
+	for line in stdout.lines() {
+		assert!(!line.contains("NaN") && !line.contains("inf"), "{}", line);
+		assert!(line.contains("\"total\":0,\"scaled\":null,"), "{}", line);
+		assert!(line.ends_with("\"fingers\":[null,null,null,null,null,null,null,null],\"hands\":[null,null]}"), "{}", line);
+	}
+}
+
+#[test]
+fn csv_without_keystrokes()
+{
+	let corpus = env::temp_dir().join(format!("keygen-format-csv-{}.txt", std::process::id()));
+	fs::write(&corpus, "ññññññññ").expect("could not write the corpus");
+	let output = keygen(&["run-ref", corpus.to_str().unwrap(), "-f", "csv"]);
+	fs::remove_file(&corpus).ok();
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	let rows: Vec<&str> = stdout.lines().filter(|line| line.starts_with("reference,")).collect();
+	assert!(!rows.is_empty());
+	for row in rows {
+		assert!(!row.contains("NaN") && !row.contains("inf"), "{}", row);
+		assert!(row.contains(",0,,0,0,"), "{}", row);
+		// Eight finger loads and two hand loads, all empty.
+		assert!(row.ends_with(&format!(",0{}", ",".repeat(10))), "{}", row);
+	}
+}
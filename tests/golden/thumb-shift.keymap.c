@@ -0,0 +1,20 @@
+// Generated by keygen. The keys follow the rows of the geometry, left to
+// right, thumb keys last.
+// Left out for want of a keycode: 'é'
+
+#include QMK_KEYBOARD_H
+
+const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {
+    [0] = LAYOUT(
+        KC_Q,    KC_W,    KC_E,    KC_R,    KC_T,      KC_Y,    KC_U,    KC_I,    KC_O,    KC_P,    KC_MINS,
+        KC_A,    KC_S,    KC_D,    KC_F,    KC_G,      KC_H,    KC_J,    KC_K,    KC_L,    KC_SCLN, KC_QUOT,
+        KC_Z,    KC_X,    KC_C,    KC_V,    KC_B,      KC_N,    KC_M,    KC_COMM, KC_DOT,  KC_SLSH,
+                                            MO(1),     KC_SPC
+    ),
+    [1] = LAYOUT(
+        S(KC_Q), S(KC_W), S(KC_E), S(KC_R), S(KC_T),   S(KC_Y), S(KC_U), S(KC_I), S(KC_O), S(KC_P), KC_NO,
+        S(KC_A), S(KC_S), S(KC_D), S(KC_F), S(KC_G),   S(KC_H), S(KC_J), S(KC_K), S(KC_L), KC_COLN, KC_DQUO,
+        S(KC_Z), S(KC_X), S(KC_C), S(KC_V), S(KC_B),   S(KC_N), S(KC_M), KC_LT,   KC_GT,   KC_QUES,
+                                            KC_TRNS,   KC_ENT
+    )
+};
@@ -0,0 +1,149 @@
+//! Tests for using keygen as a library.
+
+extern crate keygen;
+
+use std::sync::{Arc, Mutex};
+
+use keygen::{corpus, layout};
+use keygen::genetic::Crossover;
+use keygen::pareto::{self, Objectives};
+use keygen::{Geometry, LayoutShuffleMask, Model, Optimizer, OptimizerOptions, Progress, Scorer, Search};
+
+#[test]
+fn refine_improves_on_qwerty()
+{
+	let geometry = Arc::new(Geometry::default());
+	let qwerty = layout::QWERTY_LAYOUT.layout(&geometry);
+	let corpus = corpus::load("corpus/books.veryshort.txt", 1.0).expect("could not load the corpus");
+	let quartads = corpus::blend(&[corpus]);
+	let model = Model::new(&geometry);
+	let mask = LayoutShuffleMask::new(&geometry);
+	let options = OptimizerOptions {
+		top_layouts: 3,
+		num_swaps: 1,
+		seed: 1,
+		..OptimizerOptions::default()
+	};
+
+	let initial = model.score(&quartads, &qwerty).expect("could not score QWERTY");
+	let rounds = Mutex::new(0);
+	let refined = Optimizer::new(&model, &quartads, &mask, &options)
+		.refine(&qwerty, &|progress| {
+			if let Progress::Round(..) = progress {
+				*rounds.lock().unwrap() += 1;
+			}
+		})
+		.expect("could not refine QWERTY");
+
+	assert!(refined.winner.penalty.total < initial.penalty.total);
+	assert_eq!(refined.top_layouts.len(), 3);
+	assert!(refined.top_layouts.windows(2).all(|w| w[0].penalty.total <= w[1].penalty.total));
+	assert!(*rounds.lock().unwrap() > 1);
+}
+
+#[test]
+fn evolve_keeps_keys_and_pins()
+{
+	let geometry = Arc::new(Geometry::default());
+	let qwerty = layout::QWERTY_LAYOUT.layout(&geometry);
+	let corpus = corpus::load("corpus/books.veryshort.txt", 1.0).expect("could not load the corpus");
+	let quartads = corpus::blend(&[corpus]);
+	let model = Model::new(&geometry);
+	let mut mask = LayoutShuffleMask::new(&geometry);
+	mask.pin(&qwerty, "a,s").expect("could not pin a and s");
+	let options = OptimizerOptions {
+		search: Search::Genetic,
+		seed: 1,
+		cycles: 0,
+		iterations: 1,
+		population: 32,
+		crossover: Crossover::Cycle,
+		generations: 20,
+		..OptimizerOptions::default()
+	};
+
+	let initial = model.score(&quartads, &qwerty).expect("could not score QWERTY");
+	let best = Optimizer::new(&model, &quartads, &mask, &options)
+		.optimize(std::slice::from_ref(&qwerty), &|_| ())
+		.expect("could not evolve QWERTY");
+
+	assert!(best[0].penalty.total < initial.penalty.total);
+	assert!(best.windows(2).all(|w| w[0].penalty.total <= w[1].penalty.total));
+	let keys = |l: &layout::Layout| {
+		let mut keys: Vec<Vec<char>> = (0..geometry.len()).map(|i| l.chars_at(i)).collect();
+		keys.sort();
+		keys
+	};
+	for entry in &best {
+		assert_eq!(keys(&entry.layout), keys(&qwerty));
+		for &c in &['a', 's'] {
+			let key = |l: &layout::Layout| (0..geometry.len()).find(|&i| l.chars_at(i)[0] == c);
+			assert_eq!(key(&entry.layout), key(&qwerty));
+		}
+	}
+}
+
+#[test]
+fn climbs_keep_pins()
+{
+	let geometry = Arc::new(Geometry::default());
+	let qwerty = layout::QWERTY_LAYOUT.layout(&geometry);
+	let corpus = corpus::load("corpus/books.veryshort.txt", 1.0).expect("could not load the corpus");
+	let quartads = corpus::blend(&[corpus]);
+	let model = Model::new(&geometry);
+	let mut mask = LayoutShuffleMask::new(&geometry);
+	mask.pin(&qwerty, "z,x,c,v").expect("could not pin z, x, c and v");
+	let initial = model.score(&quartads, &qwerty).expect("could not score QWERTY");
+
+	for &search in &[Search::Tabu, Search::LateAcceptance] {
+		let options = OptimizerOptions {
+			search,
+			seed: 1,
+			cycles: 2000,
+			iterations: 1,
+			tabu_tenure: 10,
+			history: 50,
+			..OptimizerOptions::default()
+		};
+		let best = Optimizer::new(&model, &quartads, &mask, &options)
+			.optimize(std::slice::from_ref(&qwerty), &|_| ())
+			.expect("could not climb from QWERTY");
+
+		assert!(best[0].penalty.total < initial.penalty.total, "{:?}", search);
+		assert!(best.windows(2).all(|w| w[0].penalty.total <= w[1].penalty.total));
+		for i in 22..26 {
+			assert_eq!(best[0].layout.chars_at(i), qwerty.chars_at(i), "{:?}", search);
+		}
+	}
+}
+
+#[test]
+fn pareto_front_is_not_dominated()
+{
+	let geometry = Arc::new(Geometry::default());
+	let qwerty = layout::QWERTY_LAYOUT.layout(&geometry);
+	let corpus = corpus::load("corpus/books.veryshort.txt", 1.0).expect("could not load the corpus");
+	let quartads = corpus::blend(&[corpus]);
+	let model = Model::new(&geometry);
+	let mask = LayoutShuffleMask::new(&geometry);
+	let options = OptimizerOptions {
+		seed: 1,
+		cycles: 3000,
+		iterations: 3,
+		..OptimizerOptions::default()
+	};
+	let objectives: Objectives = "same_finger:2,hand_load".parse().expect("could not read the objectives");
+
+	let front = Optimizer::new(&model, &quartads, &mask, &options)
+		.pareto(std::slice::from_ref(&qwerty), &objectives, &|_| ())
+		.expect("could not anneal from QWERTY");
+
+	assert!(!front.is_empty());
+	let initial = objectives.values(&model.score(&quartads, &qwerty).expect("could not score QWERTY"));
+	for a in &front {
+		let values = objectives.values(a);
+		assert!(!pareto::dominates(&initial, &values));
+		assert!(front.iter().all(|b| !pareto::dominates(&objectives.values(b), &values)));
+	}
+	assert!(front.windows(2).all(|w| objectives.weighted_sum(&w[0]) <= objectives.weighted_sum(&w[1])));
+}
@@ -0,0 +1,57 @@
+//! Tests for `keygen permute`.
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn permute(args: &[&str])
+-> Output
+{
+	Command::new(env!("CARGO_BIN_EXE_keygen"))
+		.current_dir(Path::new(env!("CARGO_MANIFEST_DIR")))
+		.arg("permute")
+		.arg("corpus/books.veryshort.txt")
+		.args(args)
+		.output()
+		.expect("could not run keygen")
+}
+
+// The arrangements `permute` prints as CSV rows: their layers and totals.
+fn arrangements(output: &Output)
+-> Vec<String>
+{
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+	String::from_utf8_lossy(&output.stdout)
+		.lines()
+		.filter(|line| line.starts_with("arrangement,"))
+		.map(|line| line.split(',').skip(2).take(3).collect::<Vec<_>>().join(","))
+		.collect()
+}
+
+#[test]
+fn pruning_finds_the_best()
+{
+	// Five keys have 120 arrangements: keeping them all leaves nothing to
+	// prune, so the first three are the best three there are.
+	let all = arrangements(&permute(&["--keys", "a,s,d,j,k", "-t", "120", "-f", "csv"]));
+	assert_eq!(all.len(), 120);
+	let best = arrangements(&permute(&["--keys", "a,s,d,j,k", "-t", "3", "-f", "csv"]));
+	assert_eq!(best, all[..3].to_vec());
+
+	let output = permute(&["--keys", "a,s,d,j,k", "-t", "3"]);
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	let searched = stdout.lines().find(|line| line.contains("searched")).expect("no search summary");
+	assert!(searched.starts_with("Keys 11, 12, 13, 17, 18: searched "), "{}", searched);
+	assert!(searched.ends_with(" of 120 arrangements"), "{}", searched);
+	assert!(!searched.contains("searched 120 "), "{}", searched);
+}
+
+#[test]
+fn keys_must_be_free()
+{
+	let output = permute(&["--keys", "a,s", "--pin", "s"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(String::from_utf8_lossy(&output.stderr).contains("--keys: key 12 is fixed or pinned"));
+
+	let output = permute(&["--keys", "a"]);
+	assert_eq!(output.status.code(), Some(2));
+}
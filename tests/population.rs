@@ -0,0 +1,40 @@
+//! Tests for starting `keygen run` from several layouts.
+
+use std::env;
+use std::fs;
+
+mod common;
+
+use common::keygen;
+
+#[test]
+fn directory_and_references()
+{
+	let dir = env::temp_dir().join(format!("keygen-population-{}", std::process::id()));
+	fs::create_dir_all(&dir).expect("could not make the directory");
+	for name in &["a.c", "b.c", ".hidden.c"] {
+		fs::copy("tests/golden/qwerty.keymap.c", dir.join(name)).expect("could not copy the layout");
+	}
+
+	let output = keygen(&[
+		"run", "corpus/books.veryshort.txt", dir.to_str().unwrap(), "--seed-refs", "--warm-up", "10",
+		"--seed", "1", "--iterations", "1", "--cycles", "500"]);
+	fs::remove_dir_all(&dir).ok();
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	assert!(output.status.success(), "{}", stderr);
+	// Two files, and the reference layouts but CAPEWELL and DABEST.
+	assert!(stdout.contains("\nstarting from 12 layouts\n"), "{}", stdout);
+	assert!(stderr.contains("not starting from CAPEWELL, DABEST"), "{}", stderr);
+	assert!(stderr.contains("a.c: cannot type"), "{}", stderr);
+}
+
+#[test]
+fn population_only_for_run()
+{
+	let output = keygen(&["refine", "corpus/books.veryshort.txt", "tests/golden/qwerty.keymap.c", "tests/golden/qwerty.keymap.c"]);
+	assert_eq!(output.status.code(), Some(2));
+	let output = keygen(&["run-ref", "corpus/books.veryshort.txt", "--seed-refs"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(String::from_utf8_lossy(&output.stderr).contains("--seed-refs only applies to run"));
+}
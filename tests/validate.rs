@@ -0,0 +1,51 @@
+//! Tests for `keygen validate`.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn validate(args: &[&str])
+-> Output
+{
+	Command::new(env!("CARGO_BIN_EXE_keygen"))
+		.current_dir(Path::new(env!("CARGO_MANIFEST_DIR")))
+		.arg("validate")
+		.args(args)
+		.output()
+		.expect("could not run keygen")
+}
+
+#[test]
+fn reference_duplicates()
+{
+	let output = validate(&["corpus/books.veryshort.txt"]);
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	assert_eq!(output.status.code(), Some(5));
+	assert!(stdout.contains("Layout: DABEST\nDuplicates:\n  'd' on keys 7, 16 of layer 0\n"), "{}", stdout);
+	assert!(stdout.contains("Layout: CAPEWELL\nDuplicates:\n  'w' on keys 2, 28 of layer 0\n"), "{}", stdout);
+	assert!(String::from_utf8_lossy(&output.stderr).contains("incomplete layout: QWERTY"));
+}
+
+#[test]
+fn complete_layout()
+{
+	let output = validate(&["corpus/books.veryshort.txt", "tests/data/thumb-shift-layout.txt", "--unsupported", "strip"]);
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+	assert!(stdout.contains("Duplicates: none\nEmpty keys: layer 0: 32; layer 1: 32\nCannot type: nothing\n"), "{}", stdout);
+}
+
+#[test]
+fn native_duplicates()
+{
+	let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+	let layout = fs::read_to_string(root.join("tests/data/thumb-shift-layout.txt")).expect("could not read the layout");
+	let file = env::temp_dir().join(format!("keygen-validate-{}.txt", std::process::id()));
+	fs::write(&file, layout.replacen("yuiop", "yuioq", 1)).expect("could not write the layout");
+	let output = validate(&["corpus/books.veryshort.txt", file.to_str().unwrap(), "--unsupported", "strip"]);
+	fs::remove_file(&file).ok();
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	assert_eq!(output.status.code(), Some(5), "{}", String::from_utf8_lossy(&output.stderr));
+	assert!(stdout.contains("Duplicates:\n  'q' on keys 0, 9 of layer 0\n"), "{}", stdout);
+}